@@ -0,0 +1,40 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+#![cfg(feature = "backend-bitmap")]
+
+pub use criterion::{black_box, Criterion};
+use vm_memory::bitmap::AtomicBitmap;
+
+const PAGE_SIZE: usize = 0x1000;
+// Size of the memory tracked by the bitmaps used in the benchmarks.
+const SIZE: usize = 1 << 30;
+
+pub fn benchmark_for_bitmap(c: &mut Criterion) {
+    let bitmap = AtomicBitmap::new(SIZE, PAGE_SIZE);
+
+    // Marking a large write as dirty at once only needs one atomic operation per word.
+    c.bench_function("AtomicBitmap::set_addr_range_1GiB", |b| {
+        b.iter(|| bitmap.set_addr_range(black_box(0), black_box(SIZE)))
+    });
+
+    // Setting the same range one page at a time, which costs one atomic operation per page.
+    c.bench_function("AtomicBitmap::set_addr_range_1GiB_per_page", |b| {
+        b.iter(|| {
+            for addr in (0..SIZE).step_by(PAGE_SIZE) {
+                bitmap.set_addr_range(black_box(addr), PAGE_SIZE)
+            }
+        })
+    });
+
+    c.bench_function("AtomicBitmap::count_addr_range_1GiB", |b| {
+        b.iter(|| bitmap.count_addr_range(black_box(0), black_box(SIZE)))
+    });
+
+    bitmap.reset();
+    c.bench_function("AtomicBitmap::is_any_addr_set_1GiB_clean", |b| {
+        b.iter(|| bitmap.is_any_addr_set(black_box(0), black_box(SIZE)))
+    });
+
+    c.bench_function("AtomicBitmap::reset_addr_range_1GiB", |b| {
+        b.iter(|| bitmap.reset_addr_range(black_box(0), black_box(SIZE)))
+    });
+}
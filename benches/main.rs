@@ -8,6 +8,7 @@ pub use criterion::{black_box, criterion_group, criterion_main, Criterion};
 #[cfg(feature = "backend-mmap")]
 use vm_memory::{GuestAddress, GuestMemoryMmap};
 
+mod bitmap;
 mod guest_memory;
 mod mmap;
 mod volatile;
@@ -31,6 +32,11 @@ pub fn criterion_benchmark(_c: &mut Criterion) {
     mmap::benchmark_for_mmap(_c);
 }
 
+pub fn benchmark_bitmap(_c: &mut Criterion) {
+    #[cfg(feature = "backend-bitmap")]
+    bitmap::benchmark_for_bitmap(_c)
+}
+
 pub fn benchmark_guest_memory(_c: &mut Criterion) {
     #[cfg(feature = "backend-mmap")]
     guest_memory::benchmark_for_guest_memory(_c)
@@ -39,7 +45,7 @@ pub fn benchmark_guest_memory(_c: &mut Criterion) {
 criterion_group! {
     name = benches;
     config = Criterion::default().sample_size(200).measurement_time(std::time::Duration::from_secs(50));
-    targets = criterion_benchmark, benchmark_guest_memory, benchmark_for_volatile
+    targets = criterion_benchmark, benchmark_bitmap, benchmark_guest_memory, benchmark_for_volatile
 }
 
 criterion_main! {
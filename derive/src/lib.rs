@@ -0,0 +1,162 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Derive macro for the `ByteValued` trait of `vm-memory`.
+//!
+//! This crate is re-exported by `vm-memory` when its `derive` feature is enabled, and should be
+//! used through it:
+//!
+//! ```
+//! use vm_memory::ByteValued;
+//!
+//! #[derive(ByteValued, Clone, Copy, Default)]
+//! #[repr(C)]
+//! struct Header {
+//!     magic: u32,
+//!     version: u16,
+//!     flags: u16,
+//!     size: u64,
+//! }
+//!
+//! let header = Header {
+//!     magic: 0x1234_5678,
+//!     ..Default::default()
+//! };
+//! assert_eq!(header.as_slice().len(), 16);
+//! ```
+//!
+//! The layout of the type is checked at compile time. Types without a `repr(C)`,
+//! `repr(transparent)` or `repr(packed)` attribute are rejected, since the layout of the default
+//! representation is unspecified:
+//!
+//! ```compile_fail
+//! # use vm_memory::ByteValued;
+//! #[derive(ByteValued, Clone, Copy, Default)]
+//! struct Header {
+//!     magic: u32,
+//! }
+//! ```
+//!
+//! So are types with implicit padding, whose padding bytes would be exposed by `as_slice`:
+//!
+//! ```compile_fail
+//! # use vm_memory::ByteValued;
+//! #[derive(ByteValued, Clone, Copy, Default)]
+//! #[repr(C)]
+//! struct Header {
+//!     magic: u32,
+//!     size: u64,
+//! }
+//! ```
+//!
+//! And types with fields that are not `ByteValued` themselves:
+//!
+//! ```compile_fail
+//! # use vm_memory::ByteValued;
+//! #[derive(ByteValued, Clone, Copy, Default)]
+//! #[repr(C)]
+//! struct Header {
+//!     magic: u32,
+//!     valid: [bool; 4],
+//! }
+//! ```
+//!
+//! Enums, unions and generic types are not supported.
+
+#![deny(missing_docs)]
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parenthesized, parse_macro_input, Data, DeriveInput, Error, Result};
+
+/// Derives `ByteValued` for structs with a stable layout and no padding, whose fields are all
+/// `ByteValued`.
+///
+/// The type still has to implement (or derive) `Copy`.
+#[proc_macro_derive(ByteValued)]
+pub fn derive_byte_valued(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+// Returns whether the type has a representation with a stable layout.
+fn has_stable_repr(input: &DeriveInput) -> Result<bool> {
+    let mut stable = false;
+    for attr in input
+        .attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("repr"))
+    {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("C")
+                || meta.path.is_ident("transparent")
+                || meta.path.is_ident("packed")
+            {
+                stable = true;
+            }
+            // Skip the arguments of `packed(N)` and `align(N)`.
+            if meta.input.peek(syn::token::Paren) {
+                let content;
+                parenthesized!(content in meta.input);
+                content.parse::<TokenStream2>()?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(stable)
+}
+
+fn expand(input: &DeriveInput) -> Result<TokenStream2> {
+    let name = &input.ident;
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new(
+                input.span(),
+                "ByteValued can only be derived for structs",
+            ))
+        }
+    };
+    if !input.generics.params.is_empty() {
+        return Err(Error::new(
+            input.generics.span(),
+            "ByteValued can not be derived for generic types",
+        ));
+    }
+    if !has_stable_repr(input)? {
+        return Err(Error::new(
+            name.span(),
+            "ByteValued can only be derived for types with `repr(C)`, `repr(transparent)` or \
+             `repr(packed)`",
+        ));
+    }
+
+    let types: Vec<_> = fields.iter().map(|field| &field.ty).collect();
+    let field_checks = types.iter().map(|ty| {
+        quote_spanned! {ty.span()=>
+            assert_byte_valued::<#ty>();
+        }
+    });
+    let padding_msg = format!("`{}` has padding bytes, so it can not be ByteValued", name);
+
+    Ok(quote! {
+        const _: () = {
+            fn assert_byte_valued<T: ::vm_memory::ByteValued>() {}
+            #[allow(dead_code)]
+            fn assert_fields() {
+                #(#field_checks)*
+            }
+            assert!(
+                ::core::mem::size_of::<#name>() == 0 #(+ ::core::mem::size_of::<#types>())*,
+                #padding_msg
+            );
+        };
+
+        // SAFETY: The type has a stable layout without padding, and all its fields are
+        // `ByteValued`, so any byte pattern is a valid value of the type.
+        unsafe impl ::vm_memory::ByteValued for #name {}
+    })
+}
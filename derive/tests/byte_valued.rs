@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+use std::mem::size_of;
+
+use vm_memory::{ByteValued, Bytes, GuestAddress, GuestMemoryMmap, Le16, Le32};
+
+#[derive(ByteValued, Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: Le32,
+    flags: Le16,
+    next: Le16,
+}
+
+#[derive(ByteValued, Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C, packed)]
+struct Packed {
+    kind: u8,
+    value: u64,
+}
+
+#[derive(ByteValued, Clone, Copy, Debug, Default, PartialEq)]
+#[repr(transparent)]
+struct Wrapper(u32);
+
+#[derive(ByteValued, Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C, align(8))]
+struct Nested {
+    desc: Descriptor,
+    wrapper: Wrapper,
+    values: [u32; 3],
+}
+
+#[derive(ByteValued, Clone, Copy, Debug, Default, PartialEq)]
+#[repr(C)]
+struct Empty;
+
+#[test]
+fn test_derive() {
+    assert_eq!(size_of::<Packed>(), 9);
+    assert_eq!(size_of::<Empty>(), 0);
+
+    let packed = Packed {
+        kind: 1,
+        value: 0x0203_0405_0607_0809,
+    };
+    assert_eq!(packed.as_slice(), [1, 9, 8, 7, 6, 5, 4, 3, 2]);
+    assert_eq!(Wrapper::from_slice(&[1, 0, 0, 0]), Some(&Wrapper(1)));
+
+    let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+    let nested = Nested {
+        desc: Descriptor {
+            addr: 0x1000,
+            len: Le32::from(0x200),
+            flags: Le16::from(1),
+            next: Le16::from(2),
+        },
+        wrapper: Wrapper(3),
+        values: [4, 5, 6],
+    };
+    gm.write_obj(nested, GuestAddress(0x100)).unwrap();
+    assert_eq!(gm.read_obj::<Nested>(GuestAddress(0x100)).unwrap(), nested);
+    assert_eq!(gm.read_obj::<u32>(GuestAddress(0x114)).unwrap(), 4);
+}
@@ -3,9 +3,10 @@
 
 //! Bitmap backend implementation based on atomic integers.
 
+use std::ops::Range;
 use std::sync::atomic::{AtomicU64, Ordering};
 
-use crate::bitmap::{Bitmap, RefSlice, WithBitmapSlice};
+use crate::bitmap::{clip_range, Bitmap, BitmapRange, DirtyRangeIter, RefSlice, WithBitmapSlice};
 
 #[cfg(feature = "backend-mmap")]
 use crate::mmap::NewBitmap;
@@ -51,39 +52,113 @@ impl AtomicBitmap {
         }
     }
 
+    // Set bit `n`, returning whether it was already set. Bits outside the range of the bitmap
+    // are treated as always set.
+    pub(crate) fn test_and_set_bit(&self, index: usize) -> bool {
+        if index >= self.size {
+            return true;
+        }
+        let mask = 1 << (index & 63);
+        self.map[index >> 6].fetch_or(mask, Ordering::SeqCst) & mask != 0
+    }
+
     /// Is the bit corresponding to address `addr` set?
     pub fn is_addr_set(&self, addr: usize) -> bool {
         self.is_bit_set(addr / self.page_size)
     }
 
-    /// Set a range of `len` bytes starting at `start_addr`. The first bit set in the bitmap
-    /// is for the page corresponding to `start_addr`, and the last bit that we set corresponds
-    /// to address `start_addr + len - 1`.
-    pub fn set_addr_range(&self, start_addr: usize, len: usize) {
+    // Get the range of bits covering the `len` bytes starting at `start_addr`, limited to the
+    // bits of the bitmap.
+    fn bit_range(&self, start_addr: usize, len: usize) -> Range<usize> {
         // Return early in the unlikely event that `len == 0` so the `len - 1` computation
         // below does not underflow.
         if len == 0 {
-            return;
+            return 0..0;
         }
 
         let first_bit = start_addr / self.page_size;
         // Handle input ranges where `start_addr + len - 1` would otherwise overflow an `usize`
         // by ignoring pages at invalid addresses.
         let last_bit = start_addr.saturating_add(len - 1) / self.page_size;
-        for n in first_bit..=last_bit {
-            if n >= self.size {
-                // Attempts to set bits beyond the end of the bitmap are simply ignored.
-                break;
+        // Bits beyond the end of the bitmap are simply ignored.
+        let end = std::cmp::min(last_bit + 1, self.size);
+        std::cmp::min(first_bit, end)..end
+    }
+
+    // Call `f` with the index and the mask of the bits within `bits` for every word of the
+    // bitmap overlapping `bits`, stopping early when `f` returns `false`.
+    fn for_each_word<F>(&self, bits: Range<usize>, mut f: F)
+    where
+        F: FnMut(&AtomicU64, u64) -> bool,
+    {
+        let mut n = bits.start;
+        while n < bits.end {
+            let first = n & 63;
+            let count = std::cmp::min(64 - first, bits.end - n);
+            let mask = if count == 64 {
+                !0
+            } else {
+                ((1 << count) - 1) << first
+            };
+            if !f(&self.map[n >> 6], mask) {
+                return;
             }
-            self.map[n >> 6].fetch_or(1 << (n & 63), Ordering::SeqCst);
+            n += count;
         }
     }
 
+    /// Set a range of `len` bytes starting at `start_addr`. The first bit set in the bitmap
+    /// is for the page corresponding to `start_addr`, and the last bit that we set corresponds
+    /// to address `start_addr + len - 1`.
+    ///
+    /// Bits are set one word at a time, using a single atomic operation for up to 64 pages.
+    pub fn set_addr_range(&self, start_addr: usize, len: usize) {
+        self.for_each_word(self.bit_range(start_addr, len), |word, mask| {
+            word.fetch_or(mask, Ordering::SeqCst);
+            true
+        });
+    }
+
+    /// Reset a range of `len` bytes starting at `start_addr`, clearing the bits of all the pages
+    /// overlapping the range.
+    pub fn reset_addr_range(&self, start_addr: usize, len: usize) {
+        self.for_each_word(self.bit_range(start_addr, len), |word, mask| {
+            word.fetch_and(!mask, Ordering::SeqCst);
+            true
+        });
+    }
+
+    /// Is the bit of any page overlapping the range of `len` bytes starting at `start_addr` set?
+    pub fn is_any_addr_set(&self, start_addr: usize, len: usize) -> bool {
+        let mut dirty = false;
+        self.for_each_word(self.bit_range(start_addr, len), |word, mask| {
+            dirty = word.load(Ordering::Acquire) & mask != 0;
+            !dirty
+        });
+        dirty
+    }
+
+    /// Count the set bits of the pages overlapping the range of `len` bytes starting at
+    /// `start_addr`.
+    pub fn count_addr_range(&self, start_addr: usize, len: usize) -> usize {
+        let mut count = 0;
+        self.for_each_word(self.bit_range(start_addr, len), |word, mask| {
+            count += (word.load(Ordering::Acquire) & mask).count_ones() as usize;
+            true
+        });
+        count
+    }
+
     /// Get the length of the bitmap in bits (i.e. in how many pages it can represent).
     pub fn len(&self) -> usize {
         self.size
     }
 
+    /// Get the size in bytes of the pages tracked by each bit of the bitmap.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
     /// Atomically get and reset the dirty page bitmap.
     pub fn get_and_reset(&self) -> Vec<u64> {
         self.map
@@ -98,8 +173,212 @@ impl AtomicBitmap {
             it.store(0, Ordering::Release);
         }
     }
+
+    /// OR an external dirty log into the bitmap.
+    ///
+    /// Bit `n` of `log` (bit `n % 64` of `log[n / 64]`) marks the page of `log_page_size` bytes
+    /// at offset `n * log_page_size` as dirty, which is the format returned by the
+    /// `KVM_GET_DIRTY_LOG` ioctl for a memory slot (with 4 KiB pages). When `log_page_size`
+    /// differs from the page size of the bitmap, every page of the bitmap overlapping a dirty
+    /// page of the log is marked as dirty. Parts of the log beyond the end of the bitmap are
+    /// ignored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `log_page_size` is zero.
+    pub fn merge_dirty_log(&self, log: &[u64], log_page_size: usize) {
+        assert!(log_page_size > 0, "dirty log page size must not be zero");
+        if log_page_size == self.page_size {
+            // Fast path: merge one word at a time.
+            for (i, (word, &bits)) in self.map.iter().zip(log.iter()).enumerate() {
+                let bits = bits & self.word_mask(i);
+                if bits != 0 {
+                    word.fetch_or(bits, Ordering::SeqCst);
+                }
+            }
+        } else {
+            for_each_log_run(log, log_page_size, |offset, len| {
+                self.set_addr_range(offset, len)
+            });
+        }
+    }
+
+    /// Get the contents of the bitmap merged with an external dirty log, in the format of
+    /// `get_and_reset`, without modifying the bitmap. The format of `log` is the one described
+    /// for `merge_dirty_log`, and a zero `log_page_size` panics likewise.
+    pub fn merged_with_dirty_log(&self, log: &[u64], log_page_size: usize) -> Vec<u64> {
+        assert!(log_page_size > 0, "dirty log page size must not be zero");
+        let mut words: Vec<u64> = self.map.iter().map(|u| u.load(Ordering::Acquire)).collect();
+        self.merge_log_into(&mut words, log, log_page_size);
+        words
+    }
+
+    /// Atomically get and reset the dirty page bitmap like `get_and_reset`, and merge an external
+    /// dirty log into the result. The format of `log` is the one described for
+    /// `merge_dirty_log`, and a zero `log_page_size` panics likewise.
+    pub fn get_and_reset_merged(&self, log: &[u64], log_page_size: usize) -> Vec<u64> {
+        assert!(log_page_size > 0, "dirty log page size must not be zero");
+        let mut words = self.get_and_reset();
+        self.merge_log_into(&mut words, log, log_page_size);
+        words
+    }
+
+    // Mask of the bits of word `index` which correspond to pages of the bitmap.
+    fn word_mask(&self, index: usize) -> u64 {
+        let first = index * 64;
+        if first + 64 <= self.size {
+            !0
+        } else if first < self.size {
+            (1 << (self.size - first)) - 1
+        } else {
+            0
+        }
+    }
+
+    // Merge an external dirty log into a copy of the bitmap words.
+    fn merge_log_into(&self, words: &mut [u64], log: &[u64], log_page_size: usize) {
+        if self.size == 0 {
+            return;
+        }
+        if log_page_size == self.page_size {
+            for (i, (word, &bits)) in words.iter_mut().zip(log.iter()).enumerate() {
+                *word |= bits & self.word_mask(i);
+            }
+        } else {
+            for_each_log_run(log, log_page_size, |offset, len| {
+                let first = offset / self.page_size;
+                let last = offset.saturating_add(len - 1) / self.page_size;
+                for n in first..=std::cmp::min(last, self.size.saturating_sub(1)) {
+                    words[n >> 6] |= 1 << (n & 63);
+                }
+            });
+        }
+    }
+
+    /// Get an iterator over the dirty areas of the bitmap, as `(offset, len)` byte ranges.
+    /// Adjacent dirty pages are coalesced into a single range, and ranges always cover whole
+    /// pages. The bitmap is not modified.
+    pub fn dirty_ranges(&self) -> DirtyRanges<'_> {
+        DirtyRanges::new(self, false, 0..self.size)
+    }
+
+    /// Get an iterator over the dirty areas of the bitmap like `dirty_ranges`, atomically
+    /// resetting each word of the bitmap as it is reached by the iterator. Bits of words that
+    /// have not been reached when the iterator is dropped are left unchanged.
+    pub fn get_and_reset_ranges(&self) -> DirtyRanges<'_> {
+        DirtyRanges::new(self, true, 0..self.size)
+    }
+}
+
+// Call `f` with the `(offset, len)` byte range of every run of set bits within a word of `log`,
+// where each bit stands for a page of `page_size` bytes.
+fn for_each_log_run<F: FnMut(usize, usize)>(log: &[u64], page_size: usize, mut f: F) {
+    for (i, &word) in log.iter().enumerate() {
+        let mut word = word;
+        while word != 0 {
+            let first = word.trailing_zeros() as usize;
+            let ones = (word >> first).trailing_ones() as usize;
+            let bit = i * 64 + first;
+            f(
+                bit.saturating_mul(page_size),
+                ones.saturating_mul(page_size),
+            );
+            word = if first + ones < 64 {
+                word & (!0 << (first + ones))
+            } else {
+                0
+            };
+        }
+    }
+}
+
+/// Iterator over the dirty areas of an `AtomicBitmap`, returned by
+/// [`AtomicBitmap::dirty_ranges`](struct.AtomicBitmap.html#method.dirty_ranges) and
+/// [`AtomicBitmap::get_and_reset_ranges`](struct.AtomicBitmap.html#method.get_and_reset_ranges).
+///
+/// The bitmap is scanned one word at a time, so sparse bitmaps are cheap to walk.
+#[derive(Debug)]
+pub struct DirtyRanges<'a> {
+    bitmap: &'a AtomicBitmap,
+    reset: bool,
+    // Range of bits to walk.
+    bits: Range<usize>,
+    // Index of the next word to load.
+    next_word: usize,
+    // Bits of the last loaded word which have not been consumed yet.
+    word: u64,
 }
 
+impl<'a> DirtyRanges<'a> {
+    fn new(bitmap: &'a AtomicBitmap, reset: bool, bits: Range<usize>) -> Self {
+        DirtyRanges {
+            bitmap,
+            reset,
+            next_word: bits.start >> 6,
+            bits,
+            word: 0,
+        }
+    }
+
+    // Load the bits within `bits` of the next word of the bitmap, or return `None` at the end of
+    // the range.
+    fn load_word(&mut self) -> Option<u64> {
+        let first = self.next_word * 64;
+        if first >= self.bits.end {
+            return None;
+        }
+        let word = &self.bitmap.map[self.next_word];
+        self.next_word += 1;
+
+        let low = self.bits.start.saturating_sub(first);
+        let high = self.bits.end - first;
+        let mask = if high >= 64 { !0 } else { (1 << high) - 1 } & (!0 << low);
+        Some(if self.reset {
+            word.fetch_and(!mask, Ordering::SeqCst) & mask
+        } else {
+            word.load(Ordering::Acquire) & mask
+        })
+    }
+}
+
+impl Iterator for DirtyRanges<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.word == 0 {
+            self.word = self.load_word()?;
+        }
+
+        // The current word was loaded from index `next_word - 1`.
+        let base = (self.next_word - 1) * 64;
+        let first = self.word.trailing_zeros() as usize;
+        let ones = (self.word >> first).trailing_ones() as usize;
+        let start = base + first;
+        let mut end = start + ones;
+
+        if first + ones < 64 {
+            // Consume the bits of the run, which ends within the current word.
+            self.word &= !0 << (first + ones);
+        } else {
+            // The run continues into the following words.
+            self.word = 0;
+            while let Some(word) = self.load_word() {
+                let ones = word.trailing_ones() as usize;
+                end += ones;
+                if ones < 64 {
+                    self.word = word & (!0 << ones);
+                    break;
+                }
+            }
+        }
+
+        let page_size = self.bitmap.page_size;
+        Some((start * page_size, (end - start) * page_size))
+    }
+}
+
+impl std::iter::FusedIterator for DirtyRanges<'_> {}
+
 impl Clone for AtomicBitmap {
     fn clone(&self) -> Self {
         let map = self
@@ -134,6 +413,25 @@ impl Bitmap for AtomicBitmap {
     }
 }
 
+impl BitmapRange for AtomicBitmap {
+    fn reset_range(&self, offset: usize, len: usize) {
+        self.reset_addr_range(offset, len)
+    }
+
+    fn dirty_ranges_in(&self, offset: usize, len: usize) -> DirtyRangeIter<'_> {
+        Box::new(
+            DirtyRanges::new(self, false, self.bit_range(offset, len))
+                .map(move |range| clip_range(range, offset, len)),
+        )
+    }
+
+    fn get_and_reset_range(&self, offset: usize, len: usize) -> Vec<(usize, usize)> {
+        DirtyRanges::new(self, true, self.bit_range(offset, len))
+            .map(|range| clip_range(range, offset, len))
+            .collect()
+    }
+}
+
 impl Default for AtomicBitmap {
     fn default() -> Self {
         AtomicBitmap::new(0, 0x1000)
@@ -143,31 +441,7 @@ impl Default for AtomicBitmap {
 #[cfg(feature = "backend-mmap")]
 impl NewBitmap for AtomicBitmap {
     fn with_len(len: usize) -> Self {
-        let page_size;
-
-        #[cfg(unix)]
-        {
-            // SAFETY: There's no unsafe potential in calling this function.
-            page_size = unsafe { libc::sysconf(libc::_SC_PAGE_SIZE) };
-        }
-
-        #[cfg(windows)]
-        {
-            use winapi::um::sysinfoapi::{GetSystemInfo, LPSYSTEM_INFO, SYSTEM_INFO};
-
-            // It's safe to initialize this object from a zeroed memory region.
-            let mut sysinfo: SYSTEM_INFO = unsafe { std::mem::zeroed() };
-
-            // It's safe to call this method as the pointer is based on the address
-            // of the previously initialized `sysinfo` object.
-            unsafe { GetSystemInfo(&mut sysinfo as LPSYSTEM_INFO) };
-
-            page_size = sysinfo.dwPageSize;
-        }
-
-        // The `unwrap` is safe to use because the above call should always succeed on the
-        // supported platforms, and the size of a page will always fit within a `usize`.
-        AtomicBitmap::new(len, usize::try_from(page_size).unwrap())
+        AtomicBitmap::new(len, super::host_page_size())
     }
 }
 
@@ -175,7 +449,7 @@ impl NewBitmap for AtomicBitmap {
 mod tests {
     use super::*;
 
-    use crate::bitmap::tests::test_bitmap;
+    use crate::bitmap::tests::{test_bitmap, test_bitmap_range};
 
     #[test]
     fn test_bitmap_basic() {
@@ -222,9 +496,133 @@ mod tests {
         assert!(!b.is_addr_set(1152));
     }
 
+    #[test]
+    fn test_bitmap_range_ops() {
+        let b = AtomicBitmap::new(200 * 128, 128);
+        assert!(!b.is_any_addr_set(0, 200 * 128));
+        assert_eq!(b.count_addr_range(0, 200 * 128), 0);
+
+        // Set a range spanning a whole word and parts of its neighbours.
+        b.set_addr_range(60 * 128 + 1, 70 * 128);
+        assert_eq!(b.get_and_reset(), [0xf << 60, !0, 0b111, 0]);
+
+        b.set_addr_range(60 * 128, 70 * 128);
+        assert!(!b.is_bit_set(59));
+        assert!(b.is_bit_set(60));
+        assert!(b.is_bit_set(129));
+        assert!(!b.is_bit_set(130));
+        assert_eq!(b.count_addr_range(0, 200 * 128), 70);
+        assert_eq!(b.count_addr_range(64 * 128 + 1, 64 * 128), 65);
+        assert_eq!(b.count_addr_range(0, 0), 0);
+        assert!(b.is_any_addr_set(129 * 128 + 127, 1));
+        assert!(!b.is_any_addr_set(130 * 128, 70 * 128));
+        assert!(!b.is_any_addr_set(0, 60 * 128));
+        assert!(b.is_any_addr_set(0, 60 * 128 + 1));
+
+        b.reset_addr_range(62 * 128 + 64, 66 * 128);
+        assert_eq!(b.count_addr_range(0, 200 * 128), 3);
+        assert!(b.is_bit_set(60));
+        assert!(b.is_bit_set(61));
+        assert!(!b.is_bit_set(62));
+        assert!(!b.is_bit_set(128));
+        assert!(b.is_bit_set(129));
+
+        // Ranges going beyond the end of the bitmap are truncated.
+        b.set_addr_range(190 * 128, usize::MAX);
+        assert_eq!(b.count_addr_range(190 * 128, usize::MAX), 10);
+        assert_eq!(b.count_addr_range(usize::MAX, 1), 0);
+        b.reset_addr_range(0, usize::MAX);
+        assert!(!b.is_any_addr_set(0, usize::MAX));
+    }
+
+    #[test]
+    fn test_dirty_ranges() {
+        let b = AtomicBitmap::new(300 * 128, 128);
+        assert_eq!(b.dirty_ranges().next(), None);
+
+        // Single pages, a run crossing a word boundary, a run covering a whole word and
+        // ending at the start of the next one, and the last page of the bitmap.
+        for page in [0, 2, 3, 60, 61, 62, 63, 64, 65, 128, 129]
+            .into_iter()
+            .chain(192..257)
+            .chain([299])
+        {
+            b.set_addr_range(page * 128, 1);
+        }
+
+        let expected = vec![
+            (0, 128),
+            (2 * 128, 2 * 128),
+            (60 * 128, 6 * 128),
+            (128 * 128, 2 * 128),
+            (192 * 128, 65 * 128),
+            (299 * 128, 128),
+        ];
+        assert_eq!(b.dirty_ranges().collect::<Vec<_>>(), expected);
+        // The non-destructive iterator leaves the bitmap untouched.
+        assert_eq!(b.dirty_ranges().collect::<Vec<_>>(), expected);
+
+        let mut it = b.get_and_reset_ranges();
+        assert_eq!(it.next(), Some(expected[0]));
+        // Only the first word has been reset so far.
+        assert!(!b.is_bit_set(2));
+        assert!(b.is_bit_set(64));
+        assert_eq!(it.collect::<Vec<_>>(), expected[1..]);
+        assert_eq!(b.dirty_ranges().next(), None);
+        assert_eq!(b.get_and_reset_ranges().next(), None);
+    }
+
+    #[test]
+    fn test_merge_dirty_log() {
+        // Same page size, with log bits beyond the end of the bitmap.
+        let b = AtomicBitmap::new(70 * 0x1000, 0x1000);
+        b.set_addr_range(0, 1);
+        let log = [0b1010, 1 << 5 | 1 << 6 | 1 << 63, !0];
+        assert_eq!(b.merged_with_dirty_log(&log, 0x1000), [0b1011, 1 << 5]);
+        assert_eq!(b.get_and_reset(), [1, 0]);
+
+        b.set_addr_range(0, 1);
+        b.merge_dirty_log(&log, 0x1000);
+        assert_eq!(b.get_and_reset(), [0b1011, 1 << 5]);
+
+        // Bitmap pages larger than the log pages.
+        let b = AtomicBitmap::new(0x80_0000, 0x20_0000);
+        b.merge_dirty_log(&[1 << 1 | 1 << 2, 1 << 0], 0x1000);
+        assert_eq!(b.get_and_reset(), [0b1]);
+        b.merge_dirty_log(&[0, 0, 0, 0, 0, 0, 0, 0, 1 << 0], 0x1000);
+        assert_eq!(b.get_and_reset(), [0b10]);
+        // A log page straddling two bitmap pages.
+        let b = AtomicBitmap::new(0x4000, 0x1000);
+        b.merge_dirty_log(&[1 << 1], 0x2000);
+        assert_eq!(b.get_and_reset(), [0b1100]);
+
+        // Bitmap pages smaller than the log pages.
+        let b = AtomicBitmap::new(0x10000, 0x400);
+        b.set_addr_range(0xfc00, 1);
+        assert_eq!(
+            b.get_and_reset_merged(&[1 << 1 | 1 << 3, 1], 0x1000),
+            [0xf0 | 0xf000 | 1 << 63, 0]
+        );
+        assert_eq!(b.get_and_reset(), [0, 0]);
+
+        // Empty bitmap.
+        let b = AtomicBitmap::new(0, 0x1000);
+        b.merge_dirty_log(&[!0], 0x2000);
+        assert_eq!(b.merged_with_dirty_log(&[!0], 0x2000), [0]);
+        assert_eq!(b.get_and_reset_merged(&[!0], 0x400), [0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "dirty log page size must not be zero")]
+    fn test_merge_dirty_log_zero_page_size() {
+        AtomicBitmap::new(0x4000, 0x1000).merged_with_dirty_log(&[1], 0);
+    }
+
     #[test]
     fn test_bitmap_impl() {
         let b = AtomicBitmap::new(0x2000, 128);
         test_bitmap(&b);
+        b.reset();
+        test_bitmap_range(&b);
     }
 }
@@ -4,7 +4,7 @@
 use std::ops::Deref;
 use std::sync::Arc;
 
-use crate::bitmap::{ArcSlice, AtomicBitmap, Bitmap, WithBitmapSlice};
+use crate::bitmap::{ArcSlice, AtomicBitmap, Bitmap, BitmapRange, DirtyRangeIter, WithBitmapSlice};
 
 #[cfg(feature = "backend-mmap")]
 use crate::mmap::NewBitmap;
@@ -59,6 +59,20 @@ impl Bitmap for AtomicBitmapArc {
     }
 }
 
+impl BitmapRange for AtomicBitmapArc {
+    fn reset_range(&self, offset: usize, len: usize) {
+        self.inner.reset_range(offset, len)
+    }
+
+    fn dirty_ranges_in(&self, offset: usize, len: usize) -> DirtyRangeIter<'_> {
+        self.inner.dirty_ranges_in(offset, len)
+    }
+
+    fn get_and_reset_range(&self, offset: usize, len: usize) -> Vec<(usize, usize)> {
+        self.inner.get_and_reset_range(offset, len)
+    }
+}
+
 impl Default for AtomicBitmapArc {
     fn default() -> Self {
         Self::new(AtomicBitmap::default())
@@ -76,11 +90,13 @@ impl NewBitmap for AtomicBitmapArc {
 mod tests {
     use super::*;
 
-    use crate::bitmap::tests::test_bitmap;
+    use crate::bitmap::tests::{test_bitmap, test_bitmap_range};
 
     #[test]
     fn test_bitmap_impl() {
         let b = AtomicBitmapArc::new(AtomicBitmap::new(0x2000, 128));
         test_bitmap(&b);
+        b.reset();
+        test_bitmap_range(&b);
     }
 }
@@ -0,0 +1,335 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Bitmap backend recording the numbers of dirty pages in a ring.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use crate::bitmap::{AtomicBitmap, Bitmap, RefSlice, WithBitmapSlice};
+use crate::{GuestAddress, GuestMemory, GuestMemoryRegion};
+
+#[cfg(feature = "backend-mmap")]
+use crate::mmap::NewBitmap;
+
+/// Number of entries of the rings of the `DirtyRing` objects created by `NewBitmap::with_len`.
+pub const DEFAULT_DIRTY_RING_ENTRIES: usize = 4096;
+
+// Bit of the ring state selecting the active ring. The other bits hold the index of the next
+// free entry of the active ring.
+const RING_SELECT: u64 = 1 << 63;
+
+#[derive(Debug)]
+struct Ring {
+    // Entries hold page numbers plus one.
+    entries: Box<[AtomicU64]>,
+    // Number of writers done with the entry (or overflow) they reserved.
+    committed: AtomicUsize,
+    // Whether some writers did not find a free entry.
+    overflow: AtomicBool,
+}
+
+impl Ring {
+    fn new(capacity: usize) -> Self {
+        Ring {
+            entries: (0..capacity).map(|_| AtomicU64::new(0)).collect(),
+            committed: AtomicUsize::new(0),
+            overflow: AtomicBool::new(false),
+        }
+    }
+}
+
+/// `DirtyRing` records the numbers of dirty pages in a bounded ring, so collecting them costs
+/// time proportional to the number of dirty pages rather than to the size of the memory area.
+///
+/// Each page is only appended once per harvest window. When more pages are dirtied in a window
+/// than the ring can hold, the next harvest falls back to scanning a full bitmap of the pages.
+/// Writers never block; they append to one of two rings, which are swapped by
+/// [`harvest`](struct.DirtyRing.html#method.harvest).
+#[derive(Debug)]
+pub struct DirtyRing {
+    state: AtomicU64,
+    rings: [Ring; 2],
+    // Pages dirtied in the current window, used to deduplicate the ring entries and as fallback
+    // when the ring overflows.
+    pages: AtomicBitmap,
+    // Serializes harvests.
+    harvest: Mutex<()>,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl DirtyRing {
+    /// Create a new dirty ring for `byte_size` bytes with pages of `page_size` bytes, with
+    /// rings of `capacity` entries.
+    pub fn new(byte_size: usize, page_size: usize, capacity: usize) -> Self {
+        DirtyRing {
+            state: AtomicU64::new(0),
+            rings: [Ring::new(capacity), Ring::new(capacity)],
+            pages: AtomicBitmap::new(byte_size, page_size),
+            harvest: Mutex::new(()),
+        }
+    }
+
+    /// Get the number of pages tracked by the ring.
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Get the size in bytes of the tracked pages.
+    pub fn page_size(&self) -> usize {
+        self.pages.page_size()
+    }
+
+    /// Get the number of entries of the ring.
+    pub fn capacity(&self) -> usize {
+        self.rings[0].entries.len()
+    }
+
+    fn push(&self, page: usize) {
+        let state = self.state.fetch_add(1, Ordering::SeqCst);
+        let ring = &self.rings[(state >> 63) as usize];
+        let index = (state & !RING_SELECT) as usize;
+        match ring.entries.get(index) {
+            Some(entry) => entry.store(page as u64 + 1, Ordering::Release),
+            None => ring.overflow.store(true, Ordering::Release),
+        }
+        ring.committed.fetch_add(1, Ordering::Release);
+    }
+
+    /// Collect the offsets of the pages dirtied since the previous harvest, in ascending order,
+    /// and start a new harvest window.
+    ///
+    /// Writes racing with the harvest are either reported by this harvest or by the next one. If
+    /// the ring overflowed, pages dirtied while harvesting may be reported by both.
+    pub fn harvest(&self) -> Vec<usize> {
+        let _guard = self.harvest.lock().unwrap_or_else(PoisonError::into_inner);
+
+        // Direct new writers to the other ring, which was emptied by the previous harvest.
+        let active = self.state.load(Ordering::SeqCst) & RING_SELECT;
+        let state = self.state.swap(active ^ RING_SELECT, Ordering::SeqCst);
+        let ring = &self.rings[(active >> 63) as usize];
+
+        // Wait for the writers that reserved an entry of the ring before the swap.
+        let reserved = (state & !RING_SELECT) as usize;
+        while ring.committed.load(Ordering::Acquire) != reserved {
+            std::thread::yield_now();
+        }
+
+        let page_size = self.page_size();
+        let mut offsets = Vec::new();
+        if ring.overflow.load(Ordering::Acquire) {
+            for (offset, len) in self.pages.get_and_reset_ranges() {
+                offsets.extend((offset..offset + len).step_by(page_size));
+            }
+        } else {
+            for entry in ring.entries[..reserved].iter() {
+                let offset = (entry.load(Ordering::Acquire) - 1) as usize * page_size;
+                self.pages.reset_addr_range(offset, 1);
+                offsets.push(offset);
+            }
+            offsets.sort_unstable();
+        }
+        offsets.dedup();
+
+        ring.committed.store(0, Ordering::Release);
+        ring.overflow.store(false, Ordering::Release);
+        offsets
+    }
+}
+
+impl<'a> WithBitmapSlice<'a> for DirtyRing {
+    type S = RefSlice<'a, Self>;
+}
+
+impl Bitmap for DirtyRing {
+    fn mark_dirty(&self, offset: usize, len: usize) {
+        if len == 0 {
+            return;
+        }
+        let page_size = self.page_size();
+        let first = offset / page_size;
+        let last = offset.saturating_add(len - 1) / page_size;
+        for page in first..=std::cmp::min(last, self.len().saturating_sub(1)) {
+            if !self.pages.test_and_set_bit(page) {
+                self.push(page);
+            }
+        }
+    }
+
+    /// Check whether the specified `offset` has been dirtied in the current harvest window.
+    fn dirty_at(&self, offset: usize) -> bool {
+        self.pages.is_addr_set(offset)
+    }
+
+    fn slice_at(&self, offset: usize) -> <Self as WithBitmapSlice<'_>>::S {
+        RefSlice::new(self, offset)
+    }
+}
+
+impl Default for DirtyRing {
+    fn default() -> Self {
+        DirtyRing::new(0, 0x1000, 0)
+    }
+}
+
+#[cfg(feature = "backend-mmap")]
+impl NewBitmap for DirtyRing {
+    fn with_len(len: usize) -> Self {
+        DirtyRing::new(len, super::host_page_size(), DEFAULT_DIRTY_RING_ENTRIES)
+    }
+}
+
+/// Harvest the dirty rings of all the regions of `mem`, returning the start address of each
+/// region along with the guest addresses of its dirty pages.
+///
+/// # Examples (uses the `backend-bitmap` and `backend-mmap` features)
+///
+/// ```
+/// # #[cfg(all(feature = "backend-bitmap", feature = "backend-mmap"))]
+/// # {
+/// # use vm_memory::bitmap::{harvest_dirty_rings, DirtyRing};
+/// # use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+/// #
+/// let gm = GuestMemoryMmap::<DirtyRing>::from_ranges(&[
+///     (GuestAddress(0), 0x10000),
+///     (GuestAddress(0x100000), 0x10000),
+/// ])
+/// .expect("Could not create guest memory");
+///
+/// gm.write_obj(1u64, GuestAddress(0x100000)).unwrap();
+/// let dirty = harvest_dirty_rings(&gm);
+/// assert_eq!(dirty[0], (GuestAddress(0), vec![]));
+/// assert_eq!(dirty[1], (GuestAddress(0x100000), vec![GuestAddress(0x100000)]));
+/// # }
+/// ```
+pub fn harvest_dirty_rings<M>(mem: &M) -> Vec<(GuestAddress, Vec<GuestAddress>)>
+where
+    M: GuestMemory + ?Sized,
+    M::R: GuestMemoryRegion<B = DirtyRing>,
+{
+    mem.iter()
+        .map(|region| {
+            let base = region.start_addr();
+            let pages = region
+                .bitmap()
+                .harvest()
+                .into_iter()
+                .map(|offset| GuestAddress(base.0 + offset as u64))
+                .collect();
+            (base, pages)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::bitmap::tests::{range_is_clean, test_bitmap};
+
+    #[test]
+    fn test_bitmap_impl() {
+        let b = DirtyRing::new(0x2000, 128, 8);
+        test_bitmap(&b);
+        assert_eq!(b.harvest(), [0x1000, 0x1080]);
+        assert!(range_is_clean(&b, 0, 0x2000));
+    }
+
+    #[test]
+    fn test_harvest() {
+        let b = DirtyRing::new(0x10000, 0x1000, 4);
+        assert_eq!(b.len(), 16);
+        assert_eq!(b.capacity(), 4);
+        assert!(b.harvest().is_empty());
+
+        // Pages are deduplicated within a window.
+        b.mark_dirty(0x5000, 1);
+        b.mark_dirty(0x1000, 0x2000);
+        b.mark_dirty(0x5fff, 1);
+        b.mark_dirty(0x2000, 1);
+        assert!(b.dirty_at(0x2000));
+        assert_eq!(b.harvest(), [0x1000, 0x2000, 0x5000]);
+        assert!(!b.dirty_at(0x2000));
+        assert!(b.harvest().is_empty());
+
+        // Pages are reported again once they are dirtied in a new window.
+        b.mark_dirty(0x2000, 1);
+        assert_eq!(b.harvest(), [0x2000]);
+
+        // Overflowing the ring falls back to the bitmap, including for the ranges going beyond
+        // the end of the tracked area.
+        b.mark_dirty(0x8000, usize::MAX);
+        b.mark_dirty(0, 1);
+        let expected: Vec<_> = std::iter::once(0)
+            .chain((0x8000..0x10000).step_by(0x1000))
+            .collect();
+        assert_eq!(b.harvest(), expected);
+        assert!(b.harvest().is_empty());
+        b.mark_dirty(0x3000, 1);
+        assert_eq!(b.harvest(), [0x3000]);
+    }
+
+    #[test]
+    fn test_concurrent_harvest() {
+        let b = Arc::new(DirtyRing::new(0x400_0000, 0x1000, 256));
+        let writers: Vec<_> = (0..4)
+            .map(|i| {
+                let b = b.clone();
+                std::thread::spawn(move || {
+                    for page in (i..b.len()).step_by(4) {
+                        b.mark_dirty(page * 0x1000, 1);
+                    }
+                })
+            })
+            .collect();
+
+        let mut seen = vec![false; b.len()];
+        let mut mark_seen = |offsets: Vec<usize>| {
+            for offset in offsets {
+                seen[offset / 0x1000] = true;
+            }
+        };
+        while !writers.iter().all(|w| w.is_finished()) {
+            mark_seen(b.harvest());
+        }
+        for w in writers {
+            w.join().unwrap();
+        }
+        mark_seen(b.harvest());
+
+        // No page is lost, whether it went through a ring or through the bitmap.
+        assert!(seen.iter().all(|&s| s));
+        assert!(b.harvest().is_empty());
+    }
+
+    #[cfg(feature = "backend-mmap")]
+    #[test]
+    fn test_harvest_guest_memory() {
+        use crate::{Bytes, GuestMemoryMmap};
+
+        let gm = GuestMemoryMmap::<DirtyRing>::from_ranges(&[
+            (GuestAddress(0), 0x10000),
+            (GuestAddress(0x10000), 0x10000),
+        ])
+        .unwrap();
+        let page_size = gm.iter().next().unwrap().bitmap().page_size() as u64;
+
+        gm.write_slice(&[1; 0x20], GuestAddress(0xfff0)).unwrap();
+        gm.write_obj(1u32, GuestAddress(0x10000 + 3 * page_size))
+            .unwrap();
+        assert_eq!(
+            harvest_dirty_rings(&gm),
+            [
+                (GuestAddress(0), vec![GuestAddress(0x10000 - page_size)]),
+                (
+                    GuestAddress(0x10000),
+                    vec![GuestAddress(0x10000), GuestAddress(0x10000 + 3 * page_size)]
+                ),
+            ]
+        );
+        assert!(harvest_dirty_rings(&gm)
+            .iter()
+            .all(|(_, pages)| pages.is_empty()));
+    }
+}
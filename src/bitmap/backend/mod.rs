@@ -3,8 +3,44 @@
 
 mod atomic_bitmap;
 mod atomic_bitmap_arc;
+mod dirty_ring;
+mod multi_bitmap;
 mod slice;
+mod sparse_bitmap;
 
-pub use atomic_bitmap::AtomicBitmap;
+pub use atomic_bitmap::{AtomicBitmap, DirtyRanges};
 pub use atomic_bitmap_arc::AtomicBitmapArc;
+pub use dirty_ring::{harvest_dirty_rings, DirtyRing, DEFAULT_DIRTY_RING_ENTRIES};
+pub use multi_bitmap::{DirtyConsumer, MultiBitmap};
 pub use slice::{ArcSlice, RefSlice};
+pub use sparse_bitmap::{SparseBitmap, SparseDirtyRanges};
+
+// Get the page size of the host, which is the default granularity of the bitmaps.
+#[cfg(feature = "backend-mmap")]
+fn host_page_size() -> usize {
+    let page_size;
+
+    #[cfg(unix)]
+    {
+        // SAFETY: There's no unsafe potential in calling this function.
+        page_size = unsafe { libc::sysconf(libc::_SC_PAGE_SIZE) };
+    }
+
+    #[cfg(windows)]
+    {
+        use winapi::um::sysinfoapi::{GetSystemInfo, LPSYSTEM_INFO, SYSTEM_INFO};
+
+        // It's safe to initialize this object from a zeroed memory region.
+        let mut sysinfo: SYSTEM_INFO = unsafe { std::mem::zeroed() };
+
+        // It's safe to call this method as the pointer is based on the address
+        // of the previously initialized `sysinfo` object.
+        unsafe { GetSystemInfo(&mut sysinfo as LPSYSTEM_INFO) };
+
+        page_size = sysinfo.dwPageSize;
+    }
+
+    // The `unwrap` is safe to use because the above call should always succeed on the
+    // supported platforms, and the size of a page will always fit within a `usize`.
+    usize::try_from(page_size).unwrap()
+}
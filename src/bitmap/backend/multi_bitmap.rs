@@ -0,0 +1,256 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Bitmap backend which tracks writes on behalf of multiple independent consumers.
+
+use std::ops::Deref;
+use std::sync::{Arc, Weak};
+
+use arc_swap::ArcSwap;
+
+use crate::bitmap::{AtomicBitmap, Bitmap, RefSlice, WithBitmapSlice};
+
+#[cfg(feature = "backend-mmap")]
+use crate::mmap::NewBitmap;
+
+#[derive(Debug)]
+struct Consumers {
+    byte_size: usize,
+    page_size: usize,
+    // The list is replaced as a whole when consumers attach or detach, so that the writes being
+    // tracked only need a lock-free load of the current list.
+    bitmaps: ArcSwap<Vec<Arc<AtomicBitmap>>>,
+}
+
+/// `MultiBitmap` fans out the writes it tracks to any number of consumers, each of them having
+/// its own view of the pages dirtied since it last looked.
+///
+/// Consumers [`attach`](struct.MultiBitmap.html#method.attach) to the bitmap and get a
+/// `DirtyConsumer` handle, through which they can query and reset their view independently of
+/// the other consumers. Writes are only tracked while at least one consumer is attached.
+///
+/// # Examples (uses the `backend-bitmap` and `backend-mmap` features)
+///
+/// ```
+/// # #[cfg(all(feature = "backend-bitmap", feature = "backend-mmap"))]
+/// # {
+/// # use vm_memory::bitmap::MultiBitmap;
+/// # use vm_memory::{Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+/// #
+/// let gm = GuestMemoryMmap::<MultiBitmap>::from_ranges(&[(GuestAddress(0), 0x10000)])
+///     .expect("Could not create guest memory");
+/// let region = gm.find_region(GuestAddress(0)).unwrap();
+/// let migration = region.bitmap().attach();
+/// let backup = region.bitmap().attach();
+///
+/// gm.write_obj(1u64, GuestAddress(0x1000)).unwrap();
+/// assert_eq!(migration.get_and_reset_ranges().count(), 1);
+/// // Fetching the dirty pages of one consumer does not affect the other ones.
+/// assert!(!migration.is_addr_set(0x1000));
+/// assert!(backup.is_addr_set(0x1000));
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MultiBitmap {
+    consumers: Arc<Consumers>,
+}
+
+impl MultiBitmap {
+    /// Create a new bitmap tracking `byte_size` bytes, with one bit per page of `page_size`
+    /// bytes for every consumer.
+    pub fn new(byte_size: usize, page_size: usize) -> Self {
+        MultiBitmap {
+            consumers: Arc::new(Consumers {
+                byte_size,
+                page_size,
+                bitmaps: ArcSwap::from_pointee(Vec::new()),
+            }),
+        }
+    }
+
+    /// Attach a new consumer, whose view starts clean and records all the writes tracked from
+    /// now on. The consumer is detached when the returned handle is dropped.
+    pub fn attach(&self) -> DirtyConsumer {
+        let bitmap = Arc::new(AtomicBitmap::new(
+            self.consumers.byte_size,
+            self.consumers.page_size,
+        ));
+        self.consumers.bitmaps.rcu(|bitmaps| {
+            let mut bitmaps = Vec::clone(bitmaps);
+            bitmaps.push(bitmap.clone());
+            bitmaps
+        });
+
+        DirtyConsumer {
+            bitmap,
+            consumers: Arc::downgrade(&self.consumers),
+        }
+    }
+
+    /// Get the number of attached consumers.
+    pub fn consumers(&self) -> usize {
+        self.consumers.bitmaps.load().len()
+    }
+}
+
+impl<'a> WithBitmapSlice<'a> for MultiBitmap {
+    type S = RefSlice<'a, Self>;
+}
+
+impl Bitmap for MultiBitmap {
+    fn mark_dirty(&self, offset: usize, len: usize) {
+        for bitmap in self.consumers.bitmaps.load().iter() {
+            bitmap.set_addr_range(offset, len)
+        }
+    }
+
+    /// Check whether the specified `offset` is marked as dirty in the view of any consumer.
+    fn dirty_at(&self, offset: usize) -> bool {
+        self.consumers
+            .bitmaps
+            .load()
+            .iter()
+            .any(|bitmap| bitmap.is_addr_set(offset))
+    }
+
+    fn slice_at(&self, offset: usize) -> <Self as WithBitmapSlice<'_>>::S {
+        RefSlice::new(self, offset)
+    }
+}
+
+impl Default for MultiBitmap {
+    fn default() -> Self {
+        MultiBitmap::new(0, 0x1000)
+    }
+}
+
+#[cfg(feature = "backend-mmap")]
+impl NewBitmap for MultiBitmap {
+    fn with_len(len: usize) -> Self {
+        MultiBitmap::new(len, super::host_page_size())
+    }
+}
+
+/// Handle to the view of a consumer attached to a `MultiBitmap`.
+///
+/// The handle dereferences to the `AtomicBitmap` holding the view of the consumer, so the view
+/// can be queried and reset (e.g. with `get_and_reset` or `get_and_reset_ranges`) without
+/// affecting the other consumers. The consumer is detached when the handle is dropped.
+#[derive(Debug)]
+pub struct DirtyConsumer {
+    bitmap: Arc<AtomicBitmap>,
+    consumers: Weak<Consumers>,
+}
+
+impl DirtyConsumer {
+    /// Detach the consumer from the bitmap it is attached to.
+    pub fn detach(self) {}
+}
+
+impl Deref for DirtyConsumer {
+    type Target = AtomicBitmap;
+
+    fn deref(&self) -> &Self::Target {
+        self.bitmap.deref()
+    }
+}
+
+impl Drop for DirtyConsumer {
+    fn drop(&mut self) {
+        if let Some(consumers) = self.consumers.upgrade() {
+            consumers.bitmaps.rcu(|bitmaps| {
+                bitmaps
+                    .iter()
+                    .filter(|bitmap| !Arc::ptr_eq(bitmap, &self.bitmap))
+                    .cloned()
+                    .collect::<Vec<_>>()
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::bitmap::tests::{range_is_clean, range_is_dirty, test_bitmap};
+
+    #[test]
+    fn test_bitmap_impl() {
+        let b = MultiBitmap::new(0x2000, 128);
+        let consumer = b.attach();
+        test_bitmap(&b);
+        assert!(range_is_dirty(&*consumer, 0x1000, 0x100));
+    }
+
+    #[test]
+    fn test_consumers() {
+        let b = MultiBitmap::new(0x4000, 0x1000);
+        // Writes are not tracked without consumers.
+        b.mark_dirty(0, 0x1000);
+        assert!(!b.dirty_at(0));
+
+        let c1 = b.attach();
+        let c2 = b.attach();
+        assert_eq!(b.consumers(), 2);
+
+        b.mark_dirty(0x1000, 0x1000);
+        assert!(b.dirty_at(0x1000));
+        assert_eq!(
+            c1.get_and_reset_ranges().collect::<Vec<_>>(),
+            [(0x1000, 0x1000)]
+        );
+        assert!(!c1.is_addr_set(0x1000));
+        assert!(c2.is_addr_set(0x1000));
+        assert!(b.dirty_at(0x1000));
+
+        // A new consumer only sees the writes done after it attached.
+        let c3 = b.attach();
+        assert!(range_is_clean(&*c3, 0, 0x4000));
+        b.mark_dirty(0x3000, 1);
+        assert!(c1.is_addr_set(0x3000));
+        assert!(c2.is_addr_set(0x3000));
+        assert!(c3.is_addr_set(0x3000));
+
+        c2.detach();
+        drop(c3);
+        assert_eq!(b.consumers(), 1);
+        assert!(!b.dirty_at(0x1000));
+        assert!(b.dirty_at(0x3000));
+
+        c1.reset();
+        assert!(!b.dirty_at(0x3000));
+
+        // Handles can outlive the bitmap.
+        drop(b);
+        assert!(!c1.is_addr_set(0x3000));
+    }
+
+    #[cfg(feature = "backend-mmap")]
+    #[test]
+    fn test_guest_memory() {
+        use crate::{Bytes, GuestAddress, GuestMemory, GuestMemoryMmap, GuestMemoryRegion};
+
+        let gm = GuestMemoryMmap::<MultiBitmap>::from_ranges(&[
+            (GuestAddress(0), 0x10000),
+            (GuestAddress(0x10000), 0x10000),
+        ])
+        .unwrap();
+        let consumers: Vec<_> = gm
+            .iter()
+            .map(|region| (region.bitmap().attach(), region.bitmap().attach()))
+            .collect();
+
+        gm.write_slice(&[1; 0x20], GuestAddress(0xfff0)).unwrap();
+        for (c1, c2) in consumers.iter() {
+            assert_eq!(
+                c1.get_and_reset()
+                    .iter()
+                    .map(|w| w.count_ones())
+                    .sum::<u32>(),
+                1
+            );
+            assert_eq!(c2.dirty_ranges().count(), 1);
+        }
+        assert!(gm.iter().all(|region| region.bitmap().consumers() == 2));
+    }
+}
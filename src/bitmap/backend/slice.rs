@@ -7,7 +7,7 @@ use std::fmt::{self, Debug};
 use std::ops::Deref;
 use std::sync::Arc;
 
-use crate::bitmap::{Bitmap, BitmapSlice, WithBitmapSlice};
+use crate::bitmap::{Bitmap, BitmapRange, BitmapSlice, DirtyRangeIter, WithBitmapSlice};
 
 /// Represents a slice into a `Bitmap` object, starting at `base_offset`.
 #[derive(Clone, Copy)]
@@ -71,6 +71,38 @@ where
     }
 }
 
+impl<B> BitmapRange for BaseSlice<B>
+where
+    B: Clone + Deref,
+    B::Target: BitmapRange,
+{
+    fn reset_range(&self, offset: usize, len: usize) {
+        self.inner
+            .reset_range(self.base_offset.wrapping_add(offset), len)
+    }
+
+    /// Return an iterator over the dirty ranges within the specified range, with offsets
+    /// relative to the base offset of the slice.
+    fn dirty_ranges_in(&self, offset: usize, len: usize) -> DirtyRangeIter<'_> {
+        let base_offset = self.base_offset;
+        Box::new(
+            self.inner
+                .dirty_ranges_in(base_offset.wrapping_add(offset), len)
+                .map(move |(offset, len)| (offset.wrapping_sub(base_offset), len)),
+        )
+    }
+
+    fn get_and_reset_range(&self, offset: usize, len: usize) -> Vec<(usize, usize)> {
+        let mut ranges = self
+            .inner
+            .get_and_reset_range(self.base_offset.wrapping_add(offset), len);
+        for range in ranges.iter_mut() {
+            range.0 = range.0.wrapping_sub(self.base_offset);
+        }
+        ranges
+    }
+}
+
 impl<B> Debug for BaseSlice<B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Dummy impl for now.
@@ -97,7 +129,7 @@ pub type ArcSlice<B> = BaseSlice<Arc<B>>;
 mod tests {
     use super::*;
 
-    use crate::bitmap::tests::{range_is_clean, range_is_dirty, test_bitmap};
+    use crate::bitmap::tests::{range_is_clean, range_is_dirty, test_bitmap, test_bitmap_range};
     use crate::bitmap::AtomicBitmap;
 
     #[test]
@@ -125,5 +157,20 @@ mod tests {
             let slice = bitmap.slice_at(0);
             test_bitmap(&slice);
         }
+
+        {
+            let bitmap = AtomicBitmap::new(bitmap_size, 128);
+            let slice = bitmap.slice_at(0x1000);
+            test_bitmap_range(&slice);
+
+            // Offsets are relative to the base offset of the slice.
+            bitmap.mark_dirty(0x1800, 0x80);
+            assert_eq!(
+                slice.dirty_ranges_in(0, 0x2000).collect::<Vec<_>>(),
+                [(0x800, 0x80)]
+            );
+            assert_eq!(slice.get_and_reset_range(0x800, 0x80), [(0x800, 0x80)]);
+            assert!(!bitmap.dirty_at(0x1800));
+        }
     }
 }
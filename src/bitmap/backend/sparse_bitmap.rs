@@ -0,0 +1,476 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Two-level bitmap backend for very large memory areas.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+
+use crate::bitmap::{clip_range, Bitmap, BitmapRange, DirtyRangeIter, RefSlice, WithBitmapSlice};
+
+#[cfg(feature = "backend-mmap")]
+use crate::mmap::NewBitmap;
+
+// Number of leaf words covered by a chunk, i.e. the number of bits of a summary word.
+const WORDS_PER_CHUNK: usize = 64;
+// Number of pages covered by a chunk.
+const BITS_PER_CHUNK: usize = 64 * WORDS_PER_CHUNK;
+
+// Get the mask of the bits of a word which starts at bit `base` and overlaps `range`.
+fn word_mask(range: &Range<usize>, base: usize) -> u64 {
+    let low = std::cmp::min(range.start.saturating_sub(base), 64);
+    let high = std::cmp::min(range.end.saturating_sub(base), 64);
+    if low >= high {
+        return 0;
+    }
+    let mask = if high == 64 { !0 } else { (1 << high) - 1 };
+    mask & (!0 << low)
+}
+
+#[derive(Debug, Default)]
+struct Chunk {
+    // Bit `n` is set when word `n` of the leaves might have bits set. Leaf bits are always set
+    // before the summary bit, and the summary bit is always reset before the leaf bits, so a
+    // clear summary bit means the leaf word is clean.
+    summary: AtomicU64,
+    // Leaf words of the chunk, allocated when the chunk is first marked as dirty.
+    leaves: OnceLock<Box<[AtomicU64]>>,
+}
+
+impl Chunk {
+    fn leaves_or_alloc(&self) -> &[AtomicU64] {
+        self.leaves
+            .get_or_init(|| (0..WORDS_PER_CHUNK).map(|_| AtomicU64::new(0)).collect())
+    }
+}
+
+/// `SparseBitmap` implements a page level bit map like `AtomicBitmap`, using two levels to
+/// reduce the memory footprint and scanning cost for very large memory areas.
+///
+/// The pages are grouped in chunks of 4096 pages. Each chunk has a summary word recording which
+/// of its 64 leaf words may have bits set, and the leaf words of a chunk are only allocated when
+/// one of its pages is first marked as dirty. Walking the dirty pages loads the summary word of
+/// every chunk, so it is still linear in the size of the memory area, but only touches one word
+/// per 4096 pages instead of 64 and skips the leaf words of clean chunks.
+#[derive(Debug)]
+pub struct SparseBitmap {
+    chunks: Vec<Chunk>,
+    size: usize,
+    page_size: usize,
+}
+
+#[allow(clippy::len_without_is_empty)]
+impl SparseBitmap {
+    /// Create a new bitmap of `byte_size`, with one bit per page of `page_size` bytes. Only the
+    /// summary words are allocated upfront.
+    pub fn new(byte_size: usize, page_size: usize) -> Self {
+        let size = byte_size.div_ceil(page_size);
+        let chunks = (0..size.div_ceil(BITS_PER_CHUNK))
+            .map(|_| Chunk::default())
+            .collect();
+
+        SparseBitmap {
+            chunks,
+            size,
+            page_size,
+        }
+    }
+
+    /// Get the length of the bitmap in bits (i.e. in how many pages it can represent).
+    pub fn len(&self) -> usize {
+        self.size
+    }
+
+    /// Get the size in bytes of the pages tracked by each bit of the bitmap.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Get the number of chunks of the bitmap whose leaf words have been allocated.
+    pub fn allocated_chunks(&self) -> usize {
+        self.chunks
+            .iter()
+            .filter(|chunk| chunk.leaves.get().is_some())
+            .count()
+    }
+
+    /// Is bit `n` set? Bits outside the range of the bitmap are always unset.
+    pub fn is_bit_set(&self, index: usize) -> bool {
+        if index >= self.size {
+            return false;
+        }
+        let word = index >> 6;
+        self.chunks[word / WORDS_PER_CHUNK]
+            .leaves
+            .get()
+            .is_some_and(|leaves| {
+                leaves[word % WORDS_PER_CHUNK].load(Ordering::Acquire) & (1 << (index & 63)) != 0
+            })
+    }
+
+    /// Is the bit corresponding to address `addr` set?
+    pub fn is_addr_set(&self, addr: usize) -> bool {
+        self.is_bit_set(addr / self.page_size)
+    }
+
+    // Get the range of bits covering the `len` bytes starting at `start_addr`, limited to the
+    // bits of the bitmap.
+    fn bit_range(&self, start_addr: usize, len: usize) -> Range<usize> {
+        if len == 0 {
+            return 0..0;
+        }
+        let first_bit = start_addr / self.page_size;
+        let last_bit = start_addr.saturating_add(len - 1) / self.page_size;
+        let end = std::cmp::min(last_bit + 1, self.size);
+        std::cmp::min(first_bit, end)..end
+    }
+
+    /// Set a range of `len` bytes starting at `start_addr`, allocating the leaf words of the
+    /// chunks covering the range if needed.
+    pub fn set_addr_range(&self, start_addr: usize, len: usize) {
+        let bits = self.bit_range(start_addr, len);
+        for word in (bits.start >> 6)..bits.end.div_ceil(64) {
+            let chunk = &self.chunks[word / WORDS_PER_CHUNK];
+            let index = word % WORDS_PER_CHUNK;
+            chunk.leaves_or_alloc()[index].fetch_or(word_mask(&bits, word * 64), Ordering::SeqCst);
+            chunk.summary.fetch_or(1 << index, Ordering::SeqCst);
+        }
+    }
+
+    /// Reset a range of `len` bytes starting at `start_addr`, clearing the bits of all the pages
+    /// overlapping the range.
+    pub fn reset_addr_range(&self, start_addr: usize, len: usize) {
+        let bits = self.bit_range(start_addr, len);
+        for word in (bits.start >> 6)..bits.end.div_ceil(64) {
+            let chunk = &self.chunks[word / WORDS_PER_CHUNK];
+            let Some(leaves) = chunk.leaves.get() else {
+                continue;
+            };
+            let index = word % WORDS_PER_CHUNK;
+            let mask = word_mask(&bits, word * 64);
+            if mask == !0 {
+                chunk.summary.fetch_and(!(1 << index), Ordering::SeqCst);
+            }
+            leaves[index].fetch_and(!mask, Ordering::SeqCst);
+        }
+    }
+
+    /// Reset all bitmap bits to 0. The leaf words stay allocated.
+    pub fn reset(&self) {
+        for chunk in self.chunks.iter() {
+            chunk.summary.store(0, Ordering::SeqCst);
+            if let Some(leaves) = chunk.leaves.get() {
+                for leaf in leaves.iter() {
+                    leaf.store(0, Ordering::Release);
+                }
+            }
+        }
+    }
+
+    /// Get an iterator over the dirty areas of the bitmap, as `(offset, len)` byte ranges.
+    /// Adjacent dirty pages are coalesced into a single range, and ranges always cover whole
+    /// pages. The bitmap is not modified.
+    pub fn dirty_ranges(&self) -> SparseDirtyRanges<'_> {
+        SparseDirtyRanges::new(self, false, 0..self.size)
+    }
+
+    /// Get an iterator over the dirty areas of the bitmap like `dirty_ranges`, atomically
+    /// resetting each leaf word as it is reached by the iterator. Bits of words that have not
+    /// been reached when the iterator is dropped are left unchanged.
+    pub fn get_and_reset_ranges(&self) -> SparseDirtyRanges<'_> {
+        SparseDirtyRanges::new(self, true, 0..self.size)
+    }
+}
+
+/// Iterator over the dirty areas of a `SparseBitmap`, returned by
+/// [`SparseBitmap::dirty_ranges`](struct.SparseBitmap.html#method.dirty_ranges) and
+/// [`SparseBitmap::get_and_reset_ranges`](struct.SparseBitmap.html#method.get_and_reset_ranges).
+#[derive(Debug)]
+pub struct SparseDirtyRanges<'a> {
+    bitmap: &'a SparseBitmap,
+    reset: bool,
+    // Range of bits to walk.
+    bits: Range<usize>,
+    // Index of the next chunk to look at.
+    next_chunk: usize,
+    // Summary bits of the last chunk which have not been consumed yet.
+    summary: u64,
+    // Index and unconsumed bits of the last leaf word which has been loaded.
+    word: (usize, u64),
+    // Range of bits found so far, which may continue in the next leaf word.
+    pending: Option<Range<usize>>,
+}
+
+impl<'a> SparseDirtyRanges<'a> {
+    fn new(bitmap: &'a SparseBitmap, reset: bool, bits: Range<usize>) -> Self {
+        SparseDirtyRanges {
+            bitmap,
+            reset,
+            next_chunk: bits.start / BITS_PER_CHUNK,
+            bits,
+            summary: 0,
+            word: (0, 0),
+            pending: None,
+        }
+    }
+
+    // Load the summary bits of the next chunk overlapping `bits`, or return `None` at the end of
+    // the range.
+    fn load_summary(&mut self) -> Option<u64> {
+        let base = self.next_chunk * WORDS_PER_CHUNK;
+        if base * 64 >= self.bits.end {
+            return None;
+        }
+        let chunk = &self.bitmap.chunks[self.next_chunk];
+        self.next_chunk += 1;
+
+        let words = (self.bits.start >> 6)..self.bits.end.div_ceil(64);
+        let mask = word_mask(&words, base);
+        Some(if self.reset {
+            // Only reset the summary bits of leaf words which are entirely covered by `bits`.
+            let full_words = self.bits.start.div_ceil(64)..(self.bits.end >> 6);
+            chunk
+                .summary
+                .fetch_and(!word_mask(&full_words, base), Ordering::SeqCst)
+                & mask
+        } else {
+            chunk.summary.load(Ordering::Acquire) & mask
+        })
+    }
+
+    // Load the next leaf word that has bits set within `bits`, returning its index and bits.
+    fn load_word(&mut self) -> Option<(usize, u64)> {
+        loop {
+            while self.summary == 0 {
+                self.summary = self.load_summary()?;
+            }
+            let word =
+                (self.next_chunk - 1) * WORDS_PER_CHUNK + self.summary.trailing_zeros() as usize;
+            self.summary &= self.summary - 1;
+
+            let leaves = match self.bitmap.chunks[word / WORDS_PER_CHUNK].leaves.get() {
+                Some(leaves) => leaves,
+                None => continue,
+            };
+            let leaf = &leaves[word % WORDS_PER_CHUNK];
+            let mask = word_mask(&self.bits, word * 64);
+            let bits = if self.reset {
+                leaf.fetch_and(!mask, Ordering::SeqCst) & mask
+            } else {
+                leaf.load(Ordering::Acquire) & mask
+            };
+            if bits != 0 {
+                return Some((word, bits));
+            }
+        }
+    }
+}
+
+impl Iterator for SparseDirtyRanges<'_> {
+    type Item = (usize, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.word.1 == 0 {
+                match self.load_word() {
+                    Some(word) => self.word = word,
+                    None => break,
+                }
+            }
+
+            let (index, bits) = self.word;
+            let first = bits.trailing_zeros() as usize;
+            let ones = (bits >> first).trailing_ones() as usize;
+            self.word.1 = if first + ones < 64 {
+                bits & (!0 << (first + ones))
+            } else {
+                0
+            };
+
+            let run = index * 64 + first..index * 64 + first + ones;
+            match self.pending.take() {
+                Some(pending) if pending.end == run.start => {
+                    self.pending = Some(pending.start..run.end)
+                }
+                Some(pending) => {
+                    self.pending = Some(run);
+                    return Some(self.to_bytes(pending));
+                }
+                None => self.pending = Some(run),
+            }
+        }
+
+        let pending = self.pending.take()?;
+        Some(self.to_bytes(pending))
+    }
+}
+
+impl SparseDirtyRanges<'_> {
+    fn to_bytes(&self, bits: Range<usize>) -> (usize, usize) {
+        let page_size = self.bitmap.page_size;
+        (bits.start * page_size, bits.len() * page_size)
+    }
+}
+
+impl std::iter::FusedIterator for SparseDirtyRanges<'_> {}
+
+impl Clone for SparseBitmap {
+    fn clone(&self) -> Self {
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let leaves = OnceLock::new();
+                if let Some(src) = chunk.leaves.get() {
+                    let _ = leaves.set(
+                        src.iter()
+                            .map(|leaf| AtomicU64::new(leaf.load(Ordering::Acquire)))
+                            .collect(),
+                    );
+                }
+                Chunk {
+                    summary: AtomicU64::new(chunk.summary.load(Ordering::Acquire)),
+                    leaves,
+                }
+            })
+            .collect();
+        SparseBitmap {
+            chunks,
+            size: self.size,
+            page_size: self.page_size,
+        }
+    }
+}
+
+impl<'a> WithBitmapSlice<'a> for SparseBitmap {
+    type S = RefSlice<'a, Self>;
+}
+
+impl Bitmap for SparseBitmap {
+    fn mark_dirty(&self, offset: usize, len: usize) {
+        self.set_addr_range(offset, len)
+    }
+
+    fn dirty_at(&self, offset: usize) -> bool {
+        self.is_addr_set(offset)
+    }
+
+    fn slice_at(&self, offset: usize) -> <Self as WithBitmapSlice<'_>>::S {
+        RefSlice::new(self, offset)
+    }
+}
+
+impl BitmapRange for SparseBitmap {
+    fn reset_range(&self, offset: usize, len: usize) {
+        self.reset_addr_range(offset, len)
+    }
+
+    fn dirty_ranges_in(&self, offset: usize, len: usize) -> DirtyRangeIter<'_> {
+        Box::new(
+            SparseDirtyRanges::new(self, false, self.bit_range(offset, len))
+                .map(move |range| clip_range(range, offset, len)),
+        )
+    }
+
+    fn get_and_reset_range(&self, offset: usize, len: usize) -> Vec<(usize, usize)> {
+        SparseDirtyRanges::new(self, true, self.bit_range(offset, len))
+            .map(|range| clip_range(range, offset, len))
+            .collect()
+    }
+}
+
+impl Default for SparseBitmap {
+    fn default() -> Self {
+        SparseBitmap::new(0, 0x1000)
+    }
+}
+
+#[cfg(feature = "backend-mmap")]
+impl NewBitmap for SparseBitmap {
+    fn with_len(len: usize) -> Self {
+        SparseBitmap::new(len, super::host_page_size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::bitmap::tests::{test_bitmap, test_bitmap_range};
+
+    #[test]
+    fn test_bitmap_basic() {
+        let a = SparseBitmap::new(1025, 128);
+        assert_eq!(a.len(), 9);
+        assert_eq!(a.page_size(), 128);
+
+        // 1 TiB worth of 4 KiB pages only allocates the summary words upfront.
+        let b = SparseBitmap::new(1 << 40, 0x1000);
+        assert_eq!(b.len(), 1 << 28);
+        assert_eq!(b.chunks.len(), 1 << 16);
+        assert_eq!(b.allocated_chunks(), 0);
+        assert_eq!(b.dirty_ranges().next(), None);
+
+        b.set_addr_range(0x1000, 0x2000);
+        b.set_addr_range((1 << 40) - 0x1000, usize::MAX);
+        assert_eq!(b.allocated_chunks(), 2);
+        assert!(!b.is_addr_set(0));
+        assert!(b.is_addr_set(0x1000));
+        assert!(b.is_addr_set(0x2fff));
+        assert!(!b.is_addr_set(0x3000));
+        assert!(b.is_addr_set((1 << 40) - 1));
+        assert!(!b.is_addr_set(1 << 40));
+
+        let copy_b = b.clone();
+        assert!(copy_b.is_addr_set(0x2000));
+        assert_eq!(copy_b.allocated_chunks(), 2);
+
+        b.reset();
+        assert!(!b.is_addr_set(0x1000));
+        assert_eq!(b.dirty_ranges().next(), None);
+        assert!(copy_b.is_addr_set(0x1000));
+    }
+
+    #[test]
+    fn test_dirty_ranges() {
+        let b = SparseBitmap::new(3 * BITS_PER_CHUNK * 128, 128);
+
+        // Runs crossing word and chunk boundaries.
+        b.set_addr_range(60 * 128, 10 * 128);
+        b.set_addr_range((BITS_PER_CHUNK - 2) * 128, 4 * 128);
+        b.set_addr_range((2 * BITS_PER_CHUNK + 5) * 128, 1);
+        let expected = vec![
+            (60 * 128, 10 * 128),
+            ((BITS_PER_CHUNK - 2) * 128, 4 * 128),
+            ((2 * BITS_PER_CHUNK + 5) * 128, 128),
+        ];
+        assert_eq!(b.dirty_ranges().collect::<Vec<_>>(), expected);
+        assert_eq!(b.dirty_ranges().collect::<Vec<_>>(), expected);
+
+        assert_eq!(b.get_and_reset_ranges().collect::<Vec<_>>(), expected);
+        assert_eq!(b.dirty_ranges().next(), None);
+        assert!(b
+            .chunks
+            .iter()
+            .all(|c| c.summary.load(Ordering::Relaxed) == 0));
+
+        // Partially resetting a word keeps its summary bit.
+        b.set_addr_range(0, 2 * 128);
+        b.reset_addr_range(0, 128);
+        assert_eq!(b.chunks[0].summary.load(Ordering::Relaxed), 1);
+        assert_eq!(b.dirty_ranges().collect::<Vec<_>>(), [(128, 128)]);
+        b.reset_addr_range(0, 64 * 128);
+        assert_eq!(b.chunks[0].summary.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_bitmap_impl() {
+        let b = SparseBitmap::new(0x2000, 128);
+        test_bitmap(&b);
+        b.reset();
+        test_bitmap_range(&b);
+
+        let b = SparseBitmap::new(0x10_0000, 128);
+        test_bitmap_range(&b.slice_at(0x8_0000));
+    }
+}
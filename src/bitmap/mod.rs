@@ -14,7 +14,10 @@ use std::fmt::Debug;
 use crate::{GuestMemory, GuestMemoryRegion};
 
 #[cfg(any(test, feature = "backend-bitmap"))]
-pub use backend::{ArcSlice, AtomicBitmap, RefSlice};
+pub use backend::{
+    harvest_dirty_rings, ArcSlice, AtomicBitmap, DirtyConsumer, DirtyRanges, DirtyRing,
+    MultiBitmap, RefSlice, SparseBitmap, SparseDirtyRanges, DEFAULT_DIRTY_RING_ENTRIES,
+};
 
 /// Trait implemented by types that support creating `BitmapSlice` objects.
 pub trait WithBitmapSlice<'a> {
@@ -47,6 +50,37 @@ pub trait Bitmap: for<'a> WithBitmapSlice<'a> {
     fn slice_at(&self, offset: usize) -> <Self as WithBitmapSlice>::S;
 }
 
+/// Iterator over `(offset, len)` dirty ranges, returned by `BitmapRange::dirty_ranges_in`.
+pub type DirtyRangeIter<'a> = Box<dyn Iterator<Item = (usize, usize)> + 'a>;
+
+/// Range operations that complement `Bitmap`, allowing generic code to query and reset the
+/// dirty state of memory areas without knowing the concrete backend.
+///
+/// Backends that track the dirty state at a coarser granularity than bytes (e.g. pages) reset
+/// every tracking unit that overlaps a range, and report the dirty units clipped to the range
+/// that is queried.
+pub trait BitmapRange: Bitmap {
+    /// Reset the dirty state of the memory range specified by the given `offset` and `len`.
+    fn reset_range(&self, offset: usize, len: usize);
+
+    /// Return an iterator over the dirty `(offset, len)` ranges within the memory range
+    /// specified by the given `offset` and `len`, in ascending order. Adjacent dirty areas are
+    /// coalesced.
+    fn dirty_ranges_in(&self, offset: usize, len: usize) -> DirtyRangeIter<'_>;
+
+    /// Atomically fetch and reset the dirty state of the memory range specified by the given
+    /// `offset` and `len`, returning the dirty ranges in the same form as `dirty_ranges_in`.
+    fn get_and_reset_range(&self, offset: usize, len: usize) -> Vec<(usize, usize)>;
+}
+
+// Clip the `range` reported by a backend to the range specified by `offset` and `len`.
+#[cfg(any(test, feature = "backend-bitmap"))]
+fn clip_range(range: (usize, usize), offset: usize, len: usize) -> (usize, usize) {
+    let start = std::cmp::max(range.0, offset);
+    let end = std::cmp::min(range.0.saturating_add(range.1), offset.saturating_add(len));
+    (start, end.saturating_sub(start))
+}
+
 /// A no-op `Bitmap` implementation that can be provided for backends that do not actually
 /// require the tracking functionality.
 
@@ -66,6 +100,18 @@ impl Bitmap for () {
     fn slice_at(&self, _offset: usize) -> Self {}
 }
 
+impl BitmapRange for () {
+    fn reset_range(&self, _offset: usize, _len: usize) {}
+
+    fn dirty_ranges_in(&self, _offset: usize, _len: usize) -> DirtyRangeIter<'_> {
+        Box::new(std::iter::empty())
+    }
+
+    fn get_and_reset_range(&self, _offset: usize, _len: usize) -> Vec<(usize, usize)> {
+        Vec::new()
+    }
+}
+
 /// A `Bitmap` and `BitmapSlice` implementation for `Option<B>`.
 
 impl<'a, B> WithBitmapSlice<'a> for Option<B>
@@ -99,6 +145,28 @@ impl<B: Bitmap> Bitmap for Option<B> {
     }
 }
 
+impl<B: BitmapRange> BitmapRange for Option<B> {
+    fn reset_range(&self, offset: usize, len: usize) {
+        if let Some(inner) = self {
+            inner.reset_range(offset, len)
+        }
+    }
+
+    fn dirty_ranges_in(&self, offset: usize, len: usize) -> DirtyRangeIter<'_> {
+        if let Some(inner) = self {
+            return inner.dirty_ranges_in(offset, len);
+        }
+        Box::new(std::iter::empty())
+    }
+
+    fn get_and_reset_range(&self, offset: usize, len: usize) -> Vec<(usize, usize)> {
+        if let Some(inner) = self {
+            return inner.get_and_reset_range(offset, len);
+        }
+        Vec::new()
+    }
+}
+
 /// Helper type alias for referring to the `BitmapSlice` concrete type associated with
 /// an object `B: WithBitmapSlice<'a>`.
 pub type BS<'a, B> = <B as WithBitmapSlice<'a>>::S;
@@ -157,6 +225,56 @@ pub(crate) mod tests {
         assert!(range_is_dirty(&s, 0, dirty_len));
     }
 
+    // Helper method that tests a generic `B: BitmapRange` implementation. It assumes `b` covers
+    // an area of length at least 0x2000, and tracks the dirty state at a granularity of at most
+    // 0x80 bytes.
+    pub fn test_bitmap_range<B: BitmapRange>(b: &B) {
+        let len = 0x2000;
+        assert_eq!(b.dirty_ranges_in(0, len).next(), None);
+
+        b.mark_dirty(0x100, 0x100);
+        b.mark_dirty(0x200, 0x100);
+        b.mark_dirty(0x1000, 0x80);
+        assert_eq!(
+            b.dirty_ranges_in(0, len).collect::<Vec<_>>(),
+            [(0x100, 0x200), (0x1000, 0x80)]
+        );
+        // Ranges are clipped to the queried range.
+        assert_eq!(
+            b.dirty_ranges_in(0x180, 0xec0).collect::<Vec<_>>(),
+            [(0x180, 0x180), (0x1000, 0x40)]
+        );
+        assert_eq!(b.dirty_ranges_in(0x400, 0xc00).next(), None);
+
+        assert_eq!(b.get_and_reset_range(0x200, 0x100), [(0x200, 0x100)]);
+        assert!(range_is_clean(b, 0x200, 0x100));
+        assert_eq!(b.get_and_reset_range(0x200, 0x100), []);
+        assert_eq!(
+            b.dirty_ranges_in(0, len).collect::<Vec<_>>(),
+            [(0x100, 0x100), (0x1000, 0x80)]
+        );
+
+        b.reset_range(0x1000, 0x80);
+        assert!(range_is_clean(b, 0x1000, 0x80));
+        assert!(range_is_dirty(b, 0x100, 0x100));
+        b.reset_range(0, len);
+        assert_eq!(b.dirty_ranges_in(0, len).next(), None);
+    }
+
+    #[test]
+    fn test_bitmap_range_noop() {
+        ().mark_dirty(0, 0x1000);
+        assert_eq!(().dirty_ranges_in(0, 0x1000).next(), None);
+        assert_eq!(().get_and_reset_range(0, 0x1000), []);
+
+        let b: Option<AtomicBitmap> = None;
+        b.mark_dirty(0, 0x1000);
+        assert_eq!(b.dirty_ranges_in(0, 0x1000).next(), None);
+        assert_eq!(b.get_and_reset_range(0, 0x1000), []);
+
+        test_bitmap_range(&Some(AtomicBitmap::new(0x2000, 128)));
+    }
+
     #[derive(Debug)]
     pub enum TestAccessError {
         RangeCleanCheck,
@@ -284,6 +402,25 @@ pub(crate) mod tests {
         .unwrap();
         dirty_offset += step;
 
+        // Test `read_volatile_from`.
+        h.test_access(bytes, dirty_offset, BUF_SIZE, |m, addr| {
+            assert_eq!(
+                m.read_volatile_from(addr, &mut Cursor::new(&buf), BUF_SIZE)
+                    .unwrap(),
+                BUF_SIZE
+            )
+        })
+        .unwrap();
+        dirty_offset += step;
+
+        // Test `read_exact_volatile_from`.
+        h.test_access(bytes, dirty_offset, BUF_SIZE, |m, addr| {
+            m.read_exact_volatile_from(addr, &mut Cursor::new(&buf), BUF_SIZE)
+                .unwrap()
+        })
+        .unwrap();
+        dirty_offset += step;
+
         // Test `store`.
         h.test_access(bytes, dirty_offset, size_of_val(&val), |m, addr| {
             m.store(val, addr, Ordering::Relaxed).unwrap()
@@ -18,6 +18,7 @@ use std::slice::{from_raw_parts, from_raw_parts_mut};
 use std::sync::atomic::Ordering;
 
 use crate::atomic_integer::AtomicInteger;
+use crate::io::{ReadVolatile, WriteVolatile};
 use crate::volatile_memory::VolatileSlice;
 
 /// Types for which it is safe to initialize from raw data.
@@ -29,7 +30,7 @@ use crate::volatile_memory::VolatileSlice;
 /// any type that includes a reference.
 ///
 /// Implementing this trait guarantees that it is safe to instantiate the struct with random data.
-pub unsafe trait ByteValued: Copy + Default + Send + Sync {
+pub unsafe trait ByteValued: Copy + Send + Sync {
     /// Converts a slice of raw data into a reference of `Self`.
     ///
     /// The value of `data` is not copied. Instead a reference is made from the given slice. The
@@ -82,6 +83,16 @@ pub unsafe trait ByteValued: Copy + Default + Send + Sync {
         }
     }
 
+    /// Returns a value of `Self` whose bytes are all zero.
+    ///
+    /// Unlike `Default::default()`, this is available for all `ByteValued` types, including
+    /// arrays of any length.
+    fn zeroed() -> Self {
+        // SAFETY: Safe because the trait guarantees that any combination of bytes is valid for
+        // this type.
+        unsafe { std::mem::zeroed() }
+    }
+
     /// Converts a reference to `self` into a slice of bytes.
     ///
     /// The value of `self` is not copied. Instead, the slice is made from a reference to `self`.
@@ -124,41 +135,24 @@ pub unsafe trait ByteValued: Copy + Default + Send + Sync {
     }
 }
 
-macro_rules! byte_valued_array {
-    ($T:ty, $($N:expr)+) => {
+macro_rules! byte_valued_type {
+    ($($T:ty)+) => {
         $(
-            // SAFETY: All intrinsic types and arrays of intrinsic types are ByteValued.
-            // They are just numbers.
-            unsafe impl ByteValued for [$T; $N] {}
+            // SAFETY: Safe as long T is POD.
+            // We are using this macro to generated the implementation for integer and floating
+            // point types below.
+            unsafe impl ByteValued for $T {}
         )+
-    }
-}
-
-macro_rules! byte_valued_type {
-    ($T:ty) => {
-        // SAFETY: Safe as long T is POD.
-        // We are using this macro to generated the implementation for integer types below.
-        unsafe impl ByteValued for $T {}
-        byte_valued_array! {
-            $T,
-            0  1  2  3  4  5  6  7  8  9
-            10 11 12 13 14 15 16 17 18 19
-            20 21 22 23 24 25 26 27 28 29
-            30 31 32
-        }
     };
 }
 
-byte_valued_type!(u8);
-byte_valued_type!(u16);
-byte_valued_type!(u32);
-byte_valued_type!(u64);
-byte_valued_type!(usize);
-byte_valued_type!(i8);
-byte_valued_type!(i16);
-byte_valued_type!(i32);
-byte_valued_type!(i64);
-byte_valued_type!(isize);
+byte_valued_type!(u8 u16 u32 u64 u128 usize);
+byte_valued_type!(i8 i16 i32 i64 i128 isize);
+byte_valued_type!(f32 f64);
+
+// SAFETY: Arrays have no padding between their elements, so any byte pattern is valid for an
+// array as long as it is valid for its elements.
+unsafe impl<T: ByteValued, const N: usize> ByteValued for [T; N] {}
 
 /// A trait used to identify types which can be accessed atomically by proxy.
 pub trait AtomicAccess:
@@ -264,7 +258,7 @@ pub trait Bytes<A> {
     ///
     /// Returns an error if there's not enough data inside the container.
     fn read_obj<T: ByteValued>(&self, addr: A) -> Result<T, Self::E> {
-        let mut result: T = Default::default();
+        let mut result = T::zeroed();
         self.read_slice(result.as_mut_slice(), addr).map(|_| result)
     }
 
@@ -322,6 +316,73 @@ pub trait Bytes<A> {
     where
         F: Write;
 
+    /// Reads up to `count` bytes from an object and writes them into the container at `addr`.
+    ///
+    /// Unlike [`read_from`](trait.Bytes.html#tymethod.read_from), the data is transferred
+    /// directly into the container through a [`VolatileSlice`](struct.VolatileSlice.html),
+    /// without going through an intermediate buffer.
+    ///
+    /// Returns the number of bytes written into the container.
+    ///
+    /// # Arguments
+    /// * `addr` - Begin writing at this address.
+    /// * `src` - Copy from `src` into the container.
+    /// * `count` - Copy `count` bytes from `src` into the container.
+    fn read_volatile_from<F>(&self, addr: A, src: &mut F, count: usize) -> Result<usize, Self::E>
+    where
+        F: ReadVolatile;
+
+    /// Reads exactly `count` bytes from an object and writes them into the container at `addr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `count` bytes couldn't have been copied from `src` to the container.
+    /// Part of the data may have been copied nevertheless.
+    ///
+    /// # Arguments
+    /// * `addr` - Begin writing at this address.
+    /// * `src` - Copy from `src` into the container.
+    /// * `count` - Copy exactly `count` bytes from `src` into the container.
+    fn read_exact_volatile_from<F>(
+        &self,
+        addr: A,
+        src: &mut F,
+        count: usize,
+    ) -> Result<(), Self::E>
+    where
+        F: ReadVolatile;
+
+    /// Reads up to `count` bytes from the container at `addr` and writes them into an object.
+    ///
+    /// Unlike [`write_to`](trait.Bytes.html#tymethod.write_to), the data is transferred
+    /// directly from the container through a [`VolatileSlice`](struct.VolatileSlice.html),
+    /// without going through an intermediate buffer.
+    ///
+    /// Returns the number of bytes written into the object.
+    ///
+    /// # Arguments
+    /// * `addr` - Begin reading from this address.
+    /// * `dst` - Copy from the container to `dst`.
+    /// * `count` - Copy `count` bytes from the container to `dst`.
+    fn write_volatile_to<F>(&self, addr: A, dst: &mut F, count: usize) -> Result<usize, Self::E>
+    where
+        F: WriteVolatile;
+
+    /// Reads exactly `count` bytes from the container at `addr` and writes them into an object.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `count` bytes couldn't have been copied from the container to `dst`.
+    /// Part of the data may have been copied nevertheless.
+    ///
+    /// # Arguments
+    /// * `addr` - Begin reading from this address.
+    /// * `dst` - Copy from the container to `dst`.
+    /// * `count` - Copy exactly `count` bytes from the container to `dst`.
+    fn write_all_volatile_to<F>(&self, addr: A, dst: &mut F, count: usize) -> Result<(), Self::E>
+    where
+        F: WriteVolatile;
+
     /// Atomically store a value at the specified address.
     fn store<T: AtomicAccess>(&self, val: T, addr: A, order: Ordering) -> Result<(), Self::E>;
 
@@ -358,9 +419,9 @@ pub(crate) mod tests {
 
     fn check_byte_valued_type<T>()
     where
-        T: ByteValued + PartialEq + Debug + Default,
+        T: ByteValued + PartialEq + Debug,
     {
-        let mut data = [0u8; 32];
+        let mut data = [0u8; 64];
         let pre_len = {
             let (pre, _, _) = unsafe { data.align_to::<T>() };
             pre.len()
@@ -368,7 +429,7 @@ pub(crate) mod tests {
         {
             let aligned_data = &mut data[pre_len..pre_len + size_of::<T>()];
             {
-                let mut val: T = Default::default();
+                let mut val = T::zeroed();
                 assert_eq!(T::from_slice(aligned_data), Some(&val));
                 assert_eq!(T::from_mut_slice(aligned_data), Some(&mut val));
                 assert_eq!(val.as_slice(), aligned_data);
@@ -405,6 +466,20 @@ pub(crate) mod tests {
         check_byte_valued_type::<i32>();
         check_byte_valued_type::<i64>();
         check_byte_valued_type::<isize>();
+        check_byte_valued_type::<u128>();
+        check_byte_valued_type::<i128>();
+        check_byte_valued_type::<f32>();
+        check_byte_valued_type::<f64>();
+        check_byte_valued_type::<crate::Le32>();
+        check_byte_valued_type::<crate::Be64>();
+    }
+
+    #[test]
+    fn test_zeroed() {
+        assert_eq!(u128::zeroed(), 0);
+        assert_eq!(f64::zeroed(), 0.0);
+        assert_eq!(<[u32; 100]>::zeroed(), [0; 100]);
+        assert_eq!(ByteValued::as_slice(&<[S; 40]>::zeroed()), [0; 320]);
     }
 
     pub const MOCK_BYTES_CONTAINER_SIZE: usize = 10;
@@ -487,6 +562,34 @@ pub(crate) mod tests {
             unimplemented!()
         }
 
+        fn read_volatile_from<F>(&self, _: usize, _: &mut F, _: usize) -> Result<usize, Self::E>
+        where
+            F: ReadVolatile,
+        {
+            unimplemented!()
+        }
+
+        fn read_exact_volatile_from<F>(&self, _: usize, _: &mut F, _: usize) -> Result<(), Self::E>
+        where
+            F: ReadVolatile,
+        {
+            unimplemented!()
+        }
+
+        fn write_volatile_to<F>(&self, _: usize, _: &mut F, _: usize) -> Result<usize, Self::E>
+        where
+            F: WriteVolatile,
+        {
+            unimplemented!()
+        }
+
+        fn write_all_volatile_to<F>(&self, _: usize, _: &mut F, _: usize) -> Result<(), Self::E>
+        where
+            F: WriteVolatile,
+        {
+            unimplemented!()
+        }
+
         fn store<T: AtomicAccess>(
             &self,
             _val: T,
@@ -6,8 +6,11 @@
 
 //! Explicit endian types useful for embedding in structs or reinterpreting data.
 //!
-//! Each endian type is guaarnteed to have the same size and alignment as a regular unsigned
-//! primitive of the equal size.
+//! Each endian type is guaarnteed to have the same size and alignment as the native integer or
+//! floating point primitive of the equal size.
+//!
+//! The [`EndianBytes`](trait.EndianBytes.html) extension trait reads and writes native values
+//! through any `Bytes` implementation, converting them from and to an explicit endianness.
 //!
 //! # Examples
 //!
@@ -35,7 +38,7 @@
 
 use std::mem::{align_of, size_of};
 
-use crate::bytes::ByteValued;
+use crate::bytes::{ByteValued, Bytes};
 
 macro_rules! const_assert {
     ($condition:expr) => {
@@ -45,7 +48,7 @@ macro_rules! const_assert {
 
 macro_rules! endian_type {
     ($old_type:ident, $new_type:ident, $to_new:ident, $from_new:ident) => {
-        /// An unsigned integer type of with an explicit endianness.
+        /// An integer type with an explicit endianness.
         ///
         /// See module level documentation for examples.
         #[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
@@ -93,14 +96,150 @@ macro_rules! endian_type {
     };
 }
 
+macro_rules! endian_float_type {
+    ($float_type:ident, $bits_type:ident, $new_type:ident, $to_new:ident, $from_new:ident) => {
+        /// A floating point type with an explicit endianness.
+        ///
+        /// Values are compared as floating point numbers, after conversion to the native
+        /// endianness.
+        #[derive(Copy, Clone, Debug, Default)]
+        pub struct $new_type($bits_type);
+
+        impl $new_type {
+            fn _assert() {
+                const_assert!(align_of::<$new_type>() == align_of::<$float_type>());
+                const_assert!(size_of::<$new_type>() == size_of::<$float_type>());
+            }
+
+            /// Converts `self` to the native endianness.
+            pub fn to_native(self) -> $float_type {
+                $float_type::from_bits($bits_type::$from_new(self.0))
+            }
+        }
+
+        // SAFETY: Safe because we are using this for implementing ByteValued for endian types
+        // which are POD.
+        unsafe impl ByteValued for $new_type {}
+
+        impl PartialEq for $new_type {
+            fn eq(&self, other: &$new_type) -> bool {
+                self.to_native() == other.to_native()
+            }
+        }
+
+        impl PartialEq<$float_type> for $new_type {
+            fn eq(&self, other: &$float_type) -> bool {
+                self.to_native() == *other
+            }
+        }
+
+        impl PartialEq<$new_type> for $float_type {
+            fn eq(&self, other: &$new_type) -> bool {
+                *self == other.to_native()
+            }
+        }
+
+        impl From<$new_type> for $float_type {
+            fn from(v: $new_type) -> $float_type {
+                v.to_native()
+            }
+        }
+
+        impl From<$float_type> for $new_type {
+            fn from(v: $float_type) -> $new_type {
+                $new_type($bits_type::$to_new(v.to_bits()))
+            }
+        }
+    };
+}
+
 endian_type!(u16, Le16, to_le, from_le);
 endian_type!(u32, Le32, to_le, from_le);
 endian_type!(u64, Le64, to_le, from_le);
 endian_type!(usize, LeSize, to_le, from_le);
+endian_type!(i16, LeI16, to_le, from_le);
+endian_type!(i32, LeI32, to_le, from_le);
+endian_type!(i64, LeI64, to_le, from_le);
+endian_float_type!(f32, u32, LeF32, to_le, from_le);
+endian_float_type!(f64, u64, LeF64, to_le, from_le);
 endian_type!(u16, Be16, to_be, from_be);
 endian_type!(u32, Be32, to_be, from_be);
 endian_type!(u64, Be64, to_be, from_be);
 endian_type!(usize, BeSize, to_be, from_be);
+endian_type!(i16, BeI16, to_be, from_be);
+endian_type!(i32, BeI32, to_be, from_be);
+endian_type!(i64, BeI64, to_be, from_be);
+endian_float_type!(f32, u32, BeF32, to_be, from_be);
+endian_float_type!(f64, u64, BeF64, to_be, from_be);
+
+/// Native types with little and big endian counterparts.
+pub trait EndianPrimitive: Copy {
+    /// Little endian counterpart of the type.
+    type Le: ByteValued + From<Self> + Into<Self>;
+    /// Big endian counterpart of the type.
+    type Be: ByteValued + From<Self> + Into<Self>;
+}
+
+macro_rules! endian_primitive {
+    ($native:ident, $le:ident, $be:ident) => {
+        impl EndianPrimitive for $native {
+            type Le = $le;
+            type Be = $be;
+        }
+    };
+}
+
+endian_primitive!(u16, Le16, Be16);
+endian_primitive!(u32, Le32, Be32);
+endian_primitive!(u64, Le64, Be64);
+endian_primitive!(usize, LeSize, BeSize);
+endian_primitive!(i16, LeI16, BeI16);
+endian_primitive!(i32, LeI32, BeI32);
+endian_primitive!(i64, LeI64, BeI64);
+endian_primitive!(f32, LeF32, BeF32);
+endian_primitive!(f64, LeF64, BeF64);
+
+/// Extension trait reading and writing native values with an explicit endianness, implemented
+/// for every `Bytes` implementation.
+///
+/// # Examples
+///
+/// ```
+/// # use vm_memory::{EndianBytes, VolatileSlice};
+/// #
+/// let mut mem = [0u8; 16];
+/// let slice = VolatileSlice::from(&mut mem[..]);
+///
+/// slice.write_be(0x0102_0304u32, 0).unwrap();
+/// slice.write_le(-2i16, 4).unwrap();
+/// assert_eq!(slice.read_le::<u32>(0).unwrap(), 0x0403_0201);
+/// assert_eq!(slice.read_le::<i16>(4).unwrap(), -2);
+/// drop(slice);
+/// assert_eq!(mem[..6], [1, 2, 3, 4, 0xfe, 0xff]);
+/// ```
+pub trait EndianBytes<A>: Bytes<A> {
+    /// Reads a little endian value of type `T` at `addr`.
+    fn read_le<T: EndianPrimitive>(&self, addr: A) -> Result<T, Self::E> {
+        self.read_obj::<T::Le>(addr).map(Into::into)
+    }
+
+    /// Reads a big endian value of type `T` at `addr`.
+    fn read_be<T: EndianPrimitive>(&self, addr: A) -> Result<T, Self::E> {
+        self.read_obj::<T::Be>(addr).map(Into::into)
+    }
+
+    /// Writes `val` as a little endian value at `addr`.
+    fn write_le<T: EndianPrimitive>(&self, val: T, addr: A) -> Result<(), Self::E> {
+        self.write_obj(T::Le::from(val), addr)
+    }
+
+    /// Writes `val` as a big endian value at `addr`.
+    fn write_be<T: EndianPrimitive>(&self, val: T, addr: A) -> Result<(), Self::E> {
+        self.write_obj(T::Be::from(val), addr)
+    }
+}
+
+impl<A, B: Bytes<A> + ?Sized> EndianBytes<A> for B {}
 
 #[cfg(test)]
 mod tests {
@@ -155,4 +294,73 @@ mod tests {
     endian_test!(u32, Be32, test_be32, NATIVE_BIG);
     endian_test!(u64, Be64, test_be64, NATIVE_BIG);
     endian_test!(usize, BeSize, test_be_size, NATIVE_BIG);
+    endian_test!(i16, LeI16, test_le_i16, NATIVE_LITTLE);
+    endian_test!(i32, LeI32, test_le_i32, NATIVE_LITTLE);
+    endian_test!(i64, LeI64, test_le_i64, NATIVE_LITTLE);
+    endian_test!(i16, BeI16, test_be_i16, NATIVE_BIG);
+    endian_test!(i32, BeI32, test_be_i32, NATIVE_BIG);
+    endian_test!(i64, BeI64, test_be_i64, NATIVE_BIG);
+
+    #[test]
+    fn test_endian_float_types() {
+        LeF32::_assert();
+        LeF64::_assert();
+        BeF32::_assert();
+        BeF64::_assert();
+
+        let v = -1.5f32;
+        let le = LeF32::from(v);
+        let be = BeF32::from(v);
+        let le_bits: u32 = unsafe { transmute(le) };
+        let be_bits: u32 = unsafe { transmute(be) };
+        assert_eq!(u32::from_le(le_bits), v.to_bits());
+        assert_eq!(u32::from_be(be_bits), v.to_bits());
+        assert_eq!(le.to_native(), v);
+        assert_eq!(f32::from(be), v);
+        assert!(le == v && v == be);
+
+        let v = std::f64::consts::E;
+        let le = LeF64::from(v);
+        let be = BeF64::from(v);
+        assert_eq!(le.as_slice(), v.to_le_bytes());
+        assert_eq!(be.as_slice(), v.to_be_bytes());
+        assert_eq!(le, LeF64::from(v));
+        assert_eq!(f64::from(be), v);
+
+        // Comparisons follow floating point semantics.
+        assert_eq!(LeF32::from(0.0), LeF32::from(-0.0));
+        assert_ne!(BeF64::from(f64::NAN), BeF64::from(f64::NAN));
+        assert_eq!(LeF32::default(), 0.0);
+    }
+
+    #[test]
+    fn test_endian_bytes() {
+        use crate::VolatileSlice;
+
+        let mut mem = [0u8; 32];
+        let slice = VolatileSlice::from(&mut mem[..]);
+
+        slice.write_le(0x0102u16, 0).unwrap();
+        slice.write_be(0x0102u16, 2).unwrap();
+        slice.write_le(-2i32, 4).unwrap();
+        slice.write_be(0x0102_0304_0506_0708u64, 8).unwrap();
+        slice.write_le(1.0f64, 16).unwrap();
+        slice.write_be(1.0f32, 24).unwrap();
+        assert!(slice.write_le(0u64, 28).is_err());
+
+        assert_eq!(slice.read_le::<u16>(0).unwrap(), 0x0102);
+        assert_eq!(slice.read_be::<u16>(0).unwrap(), 0x0201);
+        assert_eq!(slice.read_be::<i32>(4).unwrap(), (-2i32).swap_bytes());
+        assert_eq!(slice.read_le::<i32>(4).unwrap(), -2);
+        assert_eq!(slice.read_be::<u64>(8).unwrap(), 0x0102_0304_0506_0708);
+        assert_eq!(slice.read_le::<f64>(16).unwrap(), 1.0);
+        assert_eq!(slice.read_be::<f32>(24).unwrap(), 1.0);
+        assert!(slice.read_be::<u64>(28).is_err());
+
+        assert_eq!(
+            mem[..16],
+            [2, 1, 1, 2, 0xfe, 0xff, 0xff, 0xff, 1, 2, 3, 4, 5, 6, 7, 8]
+        );
+        assert_eq!(mem[24..28], [0x3f, 0x80, 0, 0]);
+    }
 }
@@ -41,6 +41,7 @@
 //! via pointers, references, or slices returned by methods of `GuestMemory`,`GuestMemoryRegion`,
 //! `VolatileSlice`, `VolatileRef`, or `VolatileArrayRef`.
 
+use std::borrow::Borrow;
 use std::convert::From;
 use std::fmt::{self, Display};
 use std::fs::File;
@@ -51,11 +52,12 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use crate::address::{Address, AddressValue};
-use crate::bitmap::{Bitmap, BS, MS};
+use crate::bitmap::{Bitmap, BitmapRange, BS, MS};
 use crate::bytes::{AtomicAccess, Bytes};
+use crate::io::{ReadVolatile, WriteVolatile};
 use crate::volatile_memory::{self, VolatileSlice};
 
-static MAX_ACCESS_CHUNK: usize = 4096;
+pub(crate) const MAX_ACCESS_CHUNK: usize = 4096;
 
 /// Errors associated with handling guest memory accesses.
 #[allow(missing_docs)]
@@ -71,6 +73,10 @@ pub enum Error {
     InvalidBackendAddress,
     /// Host virtual address not available.
     HostAddressNotAvailable,
+    /// Write access to read-only guest memory.
+    ReadOnlyMemory(GuestAddress),
+    /// Failure in translating an IOVA through an IOMMU.
+    TranslationFault(crate::iommu::TranslationFault),
 }
 
 impl From<volatile_memory::Error> for Error {
@@ -115,6 +121,10 @@ impl Display for Error {
             ),
             Error::InvalidBackendAddress => write!(f, "invalid backend address"),
             Error::HostAddressNotAvailable => write!(f, "host virtual address not available"),
+            Error::ReadOnlyMemory(addr) => {
+                write!(f, "write to read-only guest address {}", addr.raw_value())
+            }
+            Error::TranslationFault(fault) => write!(f, "IOMMU translation fault: {}", fault),
         }
     }
 }
@@ -433,6 +443,23 @@ impl<M: GuestMemory> GuestAddressSpace for Arc<M> {
     }
 }
 
+// Finds the region containing `addr` within `regions`, which must be sorted by their starting
+// address and must not overlap. Shared by the `find_region` implementations of the `GuestMemory`
+// backends.
+pub(crate) fn find_sorted_region<T, R>(regions: &[T], addr: GuestAddress) -> Option<&R>
+where
+    T: Borrow<R>,
+    R: GuestMemoryRegion,
+{
+    let index = match regions.binary_search_by_key(&addr, |x| x.borrow().start_addr()) {
+        Ok(x) => Some(x),
+        // Within the closest region with starting address < addr
+        Err(x) if (x > 0 && addr <= regions[x - 1].borrow().last_addr()) => Some(x - 1),
+        _ => None,
+    };
+    index.map(|x| regions[x].borrow())
+}
+
 /// Lifetime generic associated iterators. The actual iterator type is defined through associated
 /// item `Iter`, for example:
 ///
@@ -617,6 +644,18 @@ pub trait GuestMemory {
             .map(|r| (r, r.to_region_addr(addr).unwrap()))
     }
 
+    /// Converts an absolute address to a relative address within the corresponding region, like
+    /// `to_region_addr`, for an access starting at `addr`.
+    ///
+    /// Returns the error the access fails with if `addr` isn't present within the memory of the
+    /// guest, which is `Error::InvalidGuestAddress` by default. The default implementations of
+    /// `get_host_address`, `get_slice` and `get_slices`, as well as the `store` and `load`
+    /// methods of `Bytes<GuestAddress>`, look up their address with this method.
+    fn try_region_addr(&self, addr: GuestAddress) -> Result<(&Self::R, MemoryRegionAddress)> {
+        self.to_region_addr(addr)
+            .ok_or(Error::InvalidGuestAddress(addr))
+    }
+
     /// Returns `true` if the given address is present within the memory of the guest.
     fn address_in_range(&self, addr: GuestAddress) -> bool {
         self.find_region(addr).is_some()
@@ -719,20 +758,185 @@ pub trait GuestMemory {
     /// # }
     /// ```
     fn get_host_address(&self, addr: GuestAddress) -> Result<*mut u8> {
-        self.to_region_addr(addr)
-            .ok_or(Error::InvalidGuestAddress(addr))
+        self.try_region_addr(addr)
             .and_then(|(r, addr)| r.get_host_address(addr))
     }
 
     /// Returns a [`VolatileSlice`](struct.VolatileSlice.html) of `count` bytes starting at
     /// `addr`.
     fn get_slice(&self, addr: GuestAddress, count: usize) -> Result<VolatileSlice<MS<Self>>> {
-        self.to_region_addr(addr)
-            .ok_or(Error::InvalidGuestAddress(addr))
+        self.try_region_addr(addr)
             .and_then(|(r, addr)| r.get_slice(addr, count))
     }
+
+    /// Returns an iterator over [`VolatileSlice`](struct.VolatileSlice.html)s that together
+    /// cover the `count` bytes starting at `addr`, even if the range spans multiple regions.
+    ///
+    /// Each slice covers the part of the range contained in a single region. If the range is
+    /// not completely backed by guest memory, the iterator yields the error of
+    /// [`try_region_addr`](GuestMemory::try_region_addr) for the first address that is not, and
+    /// ends. Ranges running past the end of the address space end with
+    /// `Error::InvalidGuestAddress`.
+    ///
+    /// # Examples (uses the `backend-mmap` feature)
+    ///
+    /// ```
+    /// # #[cfg(feature = "backend-mmap")]
+    /// # {
+    /// # use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap};
+    /// #
+    /// let gm = GuestMemoryMmap::<()>::from_ranges(&[
+    ///     (GuestAddress(0x0), 0x1000),
+    ///     (GuestAddress(0x1000), 0x1000),
+    /// ])
+    /// .expect("Could not create guest memory");
+    ///
+    /// let lens: Vec<usize> = gm
+    ///     .get_slices(GuestAddress(0xf00), 0x200)
+    ///     .map(|slice| slice.expect("Could not get slice").len())
+    ///     .collect();
+    /// assert_eq!(lens, vec![0x100, 0x100]);
+    /// # }
+    /// ```
+    fn get_slices(&self, addr: GuestAddress, count: usize) -> GuestMemorySliceIterator<'_, Self> {
+        GuestMemorySliceIterator {
+            mem: self,
+            addr,
+            count,
+            wrapped: false,
+        }
+    }
+
+    /// Returns the dirty `(address, length)` ranges of guest memory, as tracked by the bitmaps
+    /// of all the regions, in ascending order of address.
+    ///
+    /// Dirty ranges which are contiguous in the guest address space are coalesced, even if they
+    /// belong to different regions.
+    ///
+    /// # Examples (uses the `backend-bitmap` and `backend-mmap` features)
+    ///
+    /// ```
+    /// # #[cfg(all(feature = "backend-bitmap", feature = "backend-mmap"))]
+    /// # {
+    /// # use vm_memory::bitmap::AtomicBitmap;
+    /// # use vm_memory::{Bytes, GuestAddress, GuestMemory, GuestMemoryMmap};
+    /// #
+    /// let gm = GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[
+    ///     (GuestAddress(0), 0x10000),
+    ///     (GuestAddress(0x10000), 0x10000),
+    /// ])
+    /// .expect("Could not create guest memory");
+    ///
+    /// gm.write_slice(&[1; 0x20], GuestAddress(0xfff0)).unwrap();
+    /// let ranges = gm.dirty_ranges();
+    /// assert_eq!(ranges.len(), 1);
+    /// assert!(ranges[0].0 <= GuestAddress(0xfff0));
+    ///
+    /// gm.reset_dirty(GuestAddress(0), 0x20000);
+    /// assert!(gm.dirty_ranges().is_empty());
+    /// # }
+    /// ```
+    fn dirty_ranges(&self) -> Vec<(GuestAddress, usize)>
+    where
+        <Self::R as GuestMemoryRegion>::B: BitmapRange,
+    {
+        let mut regions: Vec<&Self::R> = self.iter().collect();
+        regions.sort_by_key(|region| region.start_addr());
+
+        let mut ranges: Vec<(GuestAddress, usize)> = Vec::new();
+        for region in regions {
+            for (offset, len) in region.bitmap().dirty_ranges_in(0, region.len() as usize) {
+                let addr = region.start_addr().unchecked_add(offset as u64);
+                match ranges.last_mut() {
+                    Some((last, last_len)) if last.checked_add(*last_len as u64) == Some(addr) => {
+                        *last_len += len
+                    }
+                    _ => ranges.push((addr, len)),
+                }
+            }
+        }
+        ranges
+    }
+
+    /// Resets the dirty state of the guest memory range `[addr, addr + len)` in the bitmaps of
+    /// all the regions it intersects. Parts of the range which are not backed by any region are
+    /// ignored.
+    fn reset_dirty(&self, addr: GuestAddress, len: usize)
+    where
+        <Self::R as GuestMemoryRegion>::B: BitmapRange,
+    {
+        if len == 0 {
+            return;
+        }
+        let last = GuestAddress(addr.0.saturating_add(len as u64 - 1));
+        for region in self.iter() {
+            let start = std::cmp::max(addr, region.start_addr());
+            let end = std::cmp::min(last, region.last_addr());
+            if start <= end {
+                region.bitmap().reset_range(
+                    start.unchecked_offset_from(region.start_addr()) as usize,
+                    end.unchecked_offset_from(start) as usize + 1,
+                );
+            }
+        }
+    }
+}
+
+/// Iterator over the [`VolatileSlice`](struct.VolatileSlice.html)s covering a range of guest
+/// memory, as returned by [`GuestMemory::get_slices`].
+#[derive(Debug)]
+pub struct GuestMemorySliceIterator<'a, M: GuestMemory + ?Sized> {
+    mem: &'a M,
+    addr: GuestAddress,
+    count: usize,
+    wrapped: bool,
+}
+
+impl<'a, M: GuestMemory + ?Sized> Iterator for GuestMemorySliceIterator<'a, M> {
+    type Item = Result<VolatileSlice<'a, MS<'a, M>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let addr = self.addr;
+        let lookup = if self.wrapped {
+            Err(Error::InvalidGuestAddress(addr))
+        } else {
+            self.mem.try_region_addr(addr)
+        };
+        let (region, region_addr) = match lookup {
+            Ok(r) => r,
+            Err(e) => {
+                // Stop after reporting the first hole in the range.
+                self.count = 0;
+                return Some(Err(e));
+            }
+        };
+
+        let cap = region.len() - region_addr.raw_value();
+        let len = std::cmp::min(cap, self.count as GuestUsize) as usize;
+        let slice = match region.get_slice(region_addr, len) {
+            Ok(slice) => slice,
+            Err(e) => {
+                self.count = 0;
+                return Some(Err(e));
+            }
+        };
+
+        self.count -= len;
+        // The next lookup fails if the range runs past the end of the address space.
+        let (next, wrapped) = addr.overflowing_add(len as GuestUsize);
+        self.addr = next;
+        self.wrapped = wrapped;
+
+        Some(Ok(slice))
+    }
 }
 
+impl<M: GuestMemory + ?Sized> std::iter::FusedIterator for GuestMemorySliceIterator<'_, M> {}
+
 impl<T: GuestMemory + ?Sized> Bytes<GuestAddress> for T {
     type E = Error;
 
@@ -854,10 +1058,11 @@ impl<T: GuestMemory + ?Sized> Bytes<GuestAddress> for T {
             assert!(offset <= count);
 
             let len = std::cmp::min(len, MAX_ACCESS_CHUNK);
-            let mut buf = vec![0u8; len].into_boxed_slice();
+            let mut buf = [0u8; MAX_ACCESS_CHUNK];
+            let buf = &mut buf[..len];
 
             loop {
-                match src.read(&mut buf[..]) {
+                match src.read(buf) {
                     Ok(bytes_read) => {
                         // We don't need to update the dirty bitmap manually here because it's
                         // expected to be handled by the logic within the `Bytes`
@@ -926,12 +1131,13 @@ impl<T: GuestMemory + ?Sized> Bytes<GuestAddress> for T {
             assert!(offset <= count);
 
             let len = std::cmp::min(len, MAX_ACCESS_CHUNK);
-            let mut buf = vec![0u8; len].into_boxed_slice();
-            let bytes_read = region.read(&mut buf, caddr)?;
+            let mut buf = [0u8; MAX_ACCESS_CHUNK];
+            let buf = &mut buf[..len];
+            let bytes_read = region.read(buf, caddr)?;
             assert_eq!(bytes_read, len);
             // For a non-RAM region, reading could have side effects, so we
             // must use write_all().
-            dst.write_all(&buf).map_err(Error::IOError)?;
+            dst.write_all(buf).map_err(Error::IOError)?;
             Ok(len)
         })
     }
@@ -980,17 +1186,90 @@ impl<T: GuestMemory + ?Sized> Bytes<GuestAddress> for T {
         Ok(())
     }
 
+    /// # Examples
+    ///
+    /// * Read bytes from a file directly into guest memory (uses the `backend-mmap` feature)
+    ///
+    /// ```
+    /// # #[cfg(feature = "backend-mmap")]
+    /// # {
+    /// # use std::io::Cursor;
+    /// # use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+    /// #
+    /// # let start_addr = GuestAddress(0x1000);
+    /// # let gm = GuestMemoryMmap::<()>::from_ranges(&vec![(start_addr, 1024)])
+    /// #    .expect("Could not create guest memory");
+    /// let mut src = Cursor::new(vec![0x5au8; 128]);
+    ///
+    /// gm.read_volatile_from(start_addr, &mut src, 128)
+    ///     .expect("Could not read 128 bytes into the provided address");
+    /// # }
+    /// ```
+    fn read_volatile_from<F>(&self, addr: GuestAddress, src: &mut F, count: usize) -> Result<usize>
+    where
+        F: ReadVolatile,
+    {
+        self.try_access(count, addr, |offset, len, caddr, region| -> Result<usize> {
+            // Check if something bad happened before doing unsafe things.
+            assert!(offset <= count);
+
+            // The region takes care of updating the dirty bitmap for the bytes it receives.
+            region.read_volatile_from(caddr, src, len)
+        })
+    }
+
+    fn read_exact_volatile_from<F>(
+        &self,
+        addr: GuestAddress,
+        src: &mut F,
+        count: usize,
+    ) -> Result<()>
+    where
+        F: ReadVolatile,
+    {
+        let res = self.read_volatile_from(addr, src, count)?;
+        if res != count {
+            return Err(Error::PartialBuffer {
+                expected: count,
+                completed: res,
+            });
+        }
+        Ok(())
+    }
+
+    fn write_volatile_to<F>(&self, addr: GuestAddress, dst: &mut F, count: usize) -> Result<usize>
+    where
+        F: WriteVolatile,
+    {
+        self.try_access(count, addr, |offset, len, caddr, region| -> Result<usize> {
+            // Check if something bad happened before doing unsafe things.
+            assert!(offset <= count);
+
+            region.write_volatile_to(caddr, dst, len)
+        })
+    }
+
+    fn write_all_volatile_to<F>(&self, addr: GuestAddress, dst: &mut F, count: usize) -> Result<()>
+    where
+        F: WriteVolatile,
+    {
+        let res = self.write_volatile_to(addr, dst, count)?;
+        if res != count {
+            return Err(Error::PartialBuffer {
+                expected: count,
+                completed: res,
+            });
+        }
+        Ok(())
+    }
+
     fn store<O: AtomicAccess>(&self, val: O, addr: GuestAddress, order: Ordering) -> Result<()> {
-        // `find_region` should really do what `to_region_addr` is doing right now, except
-        // it should keep returning a `Result`.
-        self.to_region_addr(addr)
-            .ok_or(Error::InvalidGuestAddress(addr))
+        self.try_region_addr(addr)
             .and_then(|(region, region_addr)| region.store(val, region_addr, order))
     }
 
     fn load<O: AtomicAccess>(&self, addr: GuestAddress, order: Ordering) -> Result<O> {
-        self.to_region_addr(addr)
-            .ok_or(Error::InvalidGuestAddress(addr))
+        self.try_region_addr(addr)
             .and_then(|(region, region_addr)| region.load(region_addr, order))
     }
 }
@@ -1230,4 +1509,49 @@ mod tests {
         let r = mem.find_region(addr).unwrap();
         assert_eq!(r.is_hugetlbfs(), None);
     }
+
+    #[cfg(feature = "backend-mmap")]
+    #[test]
+    fn test_dirty_ranges() {
+        use crate::bitmap::AtomicBitmap;
+
+        // Regions are contiguous between 0 and 0x20000, with a hole before the last one.
+        let gm = crate::GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[
+            (GuestAddress(0), 0x10000),
+            (GuestAddress(0x10000), 0x10000),
+            (GuestAddress(0x30000), 0x10000),
+        ])
+        .unwrap();
+        let page_size = gm.iter().next().unwrap().bitmap().page_size();
+        assert!(gm.dirty_ranges().is_empty());
+
+        gm.write_slice(&[1; 0x20], GuestAddress(0xfff0)).unwrap();
+        gm.write_obj(1u8, GuestAddress(0x1ffff)).unwrap();
+        gm.write_obj(1u8, GuestAddress(0x30000)).unwrap();
+        assert_eq!(
+            gm.dirty_ranges(),
+            [
+                (GuestAddress(0x10000 - page_size as u64), 2 * page_size),
+                (GuestAddress(0x20000 - page_size as u64), page_size),
+                (GuestAddress(0x30000), page_size),
+            ]
+        );
+
+        // Resetting spans regions and ignores holes.
+        gm.reset_dirty(GuestAddress(0x10000), 0x20001);
+        assert_eq!(
+            gm.dirty_ranges(),
+            [(GuestAddress(0x10000 - page_size as u64), page_size)]
+        );
+        gm.reset_dirty(GuestAddress(0), 0);
+        assert_eq!(gm.dirty_ranges().len(), 1);
+        gm.reset_dirty(GuestAddress(0), usize::MAX);
+        assert!(gm.dirty_ranges().is_empty());
+
+        // Memory without dirty tracking never reports dirty ranges.
+        let gm = GuestMemoryMmap::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        gm.write_obj(1u8, GuestAddress(0)).unwrap();
+        assert!(gm.dirty_ranges().is_empty());
+        gm.reset_dirty(GuestAddress(0), 0x10000);
+    }
 }
@@ -0,0 +1,414 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Typed pointers to objects in guest memory.
+//!
+//! [`GuestPtr<T>`](struct.GuestPtr.html) and [`GuestSlicePtr<T>`](struct.GuestSlicePtr.html)
+//! attach the type of the pointed-to objects to a `GuestAddress`, so the objects can be read and
+//! written through any `Bytes<GuestAddress>` implementation without repeating the type at each
+//! use site. Pointer arithmetic is checked, and pointers can be turned into `VolatileRef`s or
+//! `VolatileArrayRef`s for repeated accesses to objects within a single region.
+
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem::size_of;
+
+use crate::address::Address;
+use crate::bitmap::MS;
+use crate::bytes::{ByteValued, Bytes};
+use crate::guest_memory::{self, GuestAddress, GuestMemory, GuestUsize, MAX_ACCESS_CHUNK};
+use crate::volatile_memory::{VolatileArrayRef, VolatileRef};
+
+/// Pointer to an object of type `T` in guest memory.
+///
+/// # Examples (uses the `backend-mmap` feature)
+///
+/// ```
+/// # #[cfg(feature = "backend-mmap")]
+/// # {
+/// # use vm_memory::{GuestAddress, GuestMemoryMmap, GuestPtr};
+/// #
+/// let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x1000)])
+///     .expect("Could not create guest memory");
+/// let ptr = GuestPtr::<u32>::new(GuestAddress(0x100));
+///
+/// ptr.write(&gm, 0x1234).unwrap();
+/// assert_eq!(ptr.read(&gm).unwrap(), 0x1234);
+/// assert_eq!(ptr.add(1).unwrap().addr(), GuestAddress(0x104));
+/// # }
+/// ```
+pub struct GuestPtr<T> {
+    addr: GuestAddress,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: ByteValued> GuestPtr<T> {
+    /// Creates a pointer to the object at `addr`.
+    pub fn new(addr: GuestAddress) -> Self {
+        GuestPtr {
+            addr,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Returns the address of the object.
+    pub fn addr(&self) -> GuestAddress {
+        self.addr
+    }
+
+    /// Returns a pointer to the object of type `U` at the same address.
+    pub fn cast<U: ByteValued>(self) -> GuestPtr<U> {
+        GuestPtr::new(self.addr)
+    }
+
+    /// Returns a pointer to the `count`-th object after this one, or `None` if the address
+    /// overflows.
+    #[allow(clippy::should_implement_trait)]
+    pub fn add(self, count: GuestUsize) -> Option<Self> {
+        count
+            .checked_mul(size_of::<T>() as GuestUsize)
+            .and_then(|offset| self.addr.checked_add(offset))
+            .map(GuestPtr::new)
+    }
+
+    /// Returns a pointer to the `count`-th object after (or before, if negative) this one, or
+    /// `None` if the address overflows.
+    pub fn offset(self, count: i64) -> Option<Self> {
+        let offset = count
+            .unsigned_abs()
+            .checked_mul(size_of::<T>() as GuestUsize)?;
+        if count < 0 {
+            self.addr.checked_sub(offset).map(GuestPtr::new)
+        } else {
+            self.addr.checked_add(offset).map(GuestPtr::new)
+        }
+    }
+
+    /// Reads the object from `mem`.
+    pub fn read<M: Bytes<GuestAddress> + ?Sized>(&self, mem: &M) -> Result<T, M::E> {
+        mem.read_obj(self.addr)
+    }
+
+    /// Writes `val` to the object in `mem`.
+    pub fn write<M: Bytes<GuestAddress> + ?Sized>(&self, mem: &M, val: T) -> Result<(), M::E> {
+        mem.write_obj(val, self.addr)
+    }
+
+    /// Returns a `VolatileRef` to the object, which must lie within a single region of `mem`.
+    pub fn as_volatile_ref<'a, M: GuestMemory + ?Sized>(
+        &self,
+        mem: &'a M,
+    ) -> guest_memory::Result<VolatileRef<'a, T, MS<'a, M>>> {
+        mem.get_slice(self.addr, size_of::<T>())?
+            .into_ref(0)
+            .map_err(Into::into)
+    }
+}
+
+impl<T> Clone for GuestPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for GuestPtr<T> {}
+
+impl<T> PartialEq for GuestPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr == other.addr
+    }
+}
+
+impl<T> Eq for GuestPtr<T> {}
+
+impl<T> fmt::Debug for GuestPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("GuestPtr").field(&self.addr).finish()
+    }
+}
+
+/// Pointer to a contiguous sequence of objects of type `T` in guest memory.
+///
+/// # Examples (uses the `backend-mmap` feature)
+///
+/// ```
+/// # #[cfg(feature = "backend-mmap")]
+/// # {
+/// # use vm_memory::{GuestAddress, GuestMemoryMmap, GuestSlicePtr};
+/// #
+/// let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x1000)])
+///     .expect("Could not create guest memory");
+/// let ptr = GuestSlicePtr::<u16>::new(GuestAddress(0x100), 4).unwrap();
+///
+/// ptr.write(&gm, &[1, 2, 3, 4]).unwrap();
+/// assert_eq!(ptr.index(2).unwrap().read(&gm).unwrap(), 3);
+/// assert_eq!(ptr.as_volatile_array_ref(&gm).unwrap().load(3), 4);
+/// # }
+/// ```
+pub struct GuestSlicePtr<T> {
+    addr: GuestAddress,
+    len: usize,
+    phantom: PhantomData<fn() -> T>,
+}
+
+impl<T: ByteValued> GuestSlicePtr<T> {
+    /// Creates a pointer to the `len` objects at `addr`, or returns `None` if they extend past
+    /// the end of the address space.
+    pub fn new(addr: GuestAddress, len: usize) -> Option<Self> {
+        let byte_len = len.checked_mul(size_of::<T>())?;
+        if byte_len > 0 {
+            addr.checked_add(byte_len as GuestUsize - 1)?;
+        }
+        Some(GuestSlicePtr {
+            addr,
+            len,
+            phantom: PhantomData,
+        })
+    }
+
+    /// Returns the address of the first object.
+    pub fn addr(&self) -> GuestAddress {
+        self.addr
+    }
+
+    /// Returns the number of objects.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns whether there are no objects.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the size in bytes of the objects.
+    pub fn byte_len(&self) -> usize {
+        self.len * size_of::<T>()
+    }
+
+    /// Returns a pointer to the object at `index`, or `None` if it is out of bounds.
+    pub fn index(&self, index: usize) -> Option<GuestPtr<T>> {
+        if index < self.len {
+            Some(GuestPtr::new(
+                self.addr
+                    .unchecked_add((index * size_of::<T>()) as GuestUsize),
+            ))
+        } else {
+            None
+        }
+    }
+
+    /// Returns a pointer to the `len` objects starting at `start`, or `None` if they are out of
+    /// bounds.
+    pub fn subslice(&self, start: usize, len: usize) -> Option<Self> {
+        match start.checked_add(len) {
+            Some(end) if end <= self.len => Some(GuestSlicePtr {
+                addr: self
+                    .addr
+                    .unchecked_add((start * size_of::<T>()) as GuestUsize),
+                len,
+                phantom: PhantomData,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Returns an iterator over the pointers to the objects.
+    pub fn iter(&self) -> impl Iterator<Item = GuestPtr<T>> {
+        let ptr = *self;
+        (0..self.len).filter_map(move |index| ptr.index(index))
+    }
+
+    /// Reads the objects from `mem`.
+    ///
+    /// The objects are read in chunks of up to 4096 bytes (or a single object), so that a bogus
+    /// length (e.g. taken from a guest descriptor) fails once the end of `mem` is reached instead
+    /// of allocating memory for all the objects upfront. Errors are reported for the failing
+    /// chunk.
+    pub fn read<M: Bytes<GuestAddress> + ?Sized>(&self, mem: &M) -> Result<Vec<T>, M::E> {
+        let chunk = std::cmp::max(MAX_ACCESS_CHUNK / std::cmp::max(size_of::<T>(), 1), 1);
+        let mut values = Vec::new();
+        for start in (0..self.len).step_by(chunk) {
+            let ptr = self
+                .subslice(start, std::cmp::min(chunk, self.len - start))
+                .unwrap();
+            values.resize(start + ptr.len, T::zeroed());
+            // SAFETY: `T: ByteValued`, so any byte pattern is a valid `T`, and the byte slice
+            // covers exactly the elements of `values` added for this chunk.
+            let buf = unsafe {
+                std::slice::from_raw_parts_mut(
+                    values[start..].as_mut_ptr() as *mut u8,
+                    ptr.byte_len(),
+                )
+            };
+            mem.read_slice(buf, ptr.addr)?;
+        }
+        Ok(values)
+    }
+
+    /// Writes `values` to the objects in `mem`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the length of `values` is not the number of objects.
+    pub fn write<M: Bytes<GuestAddress> + ?Sized>(
+        &self,
+        mem: &M,
+        values: &[T],
+    ) -> Result<(), M::E> {
+        assert_eq!(values.len(), self.len, "mismatched number of objects");
+        // SAFETY: `T: ByteValued`, so the bytes of `values` are initialized.
+        let buf =
+            unsafe { std::slice::from_raw_parts(values.as_ptr() as *const u8, self.byte_len()) };
+        mem.write_slice(buf, self.addr)
+    }
+
+    /// Returns a `VolatileArrayRef` to the objects, which must lie within a single region of
+    /// `mem`.
+    pub fn as_volatile_array_ref<'a, M: GuestMemory + ?Sized>(
+        &self,
+        mem: &'a M,
+    ) -> guest_memory::Result<VolatileArrayRef<'a, T, MS<'a, M>>> {
+        mem.get_slice(self.addr, self.byte_len())?
+            .into_array_ref(0, self.len)
+            .map_err(Into::into)
+    }
+}
+
+impl<T> Clone for GuestSlicePtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for GuestSlicePtr<T> {}
+
+impl<T> PartialEq for GuestSlicePtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.addr == other.addr && self.len == other.len
+    }
+}
+
+impl<T> Eq for GuestSlicePtr<T> {}
+
+impl<T> fmt::Debug for GuestSlicePtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GuestSlicePtr")
+            .field("addr", &self.addr)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+#[cfg(all(test, feature = "backend-mmap"))]
+mod tests {
+    use super::*;
+
+    use matches::assert_matches;
+
+    use crate::bitmap::{AtomicBitmap, Bitmap};
+    use crate::{GuestMemoryMmap, GuestMemoryRegion};
+
+    #[test]
+    fn test_guest_ptr() {
+        let gm = GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[
+            (GuestAddress(0), 0x1000),
+            (GuestAddress(0x1000), 0x1000),
+        ])
+        .unwrap();
+        let ptr = GuestPtr::<u64>::new(GuestAddress(0x0ff8));
+
+        ptr.write(&gm, 0x0102_0304_0506_0708).unwrap();
+        assert_eq!(ptr.read(&gm).unwrap(), 0x0102_0304_0506_0708);
+        assert_eq!(ptr.cast::<u32>().read(&gm).unwrap(), 0x0506_0708);
+
+        // Arithmetic is in units of objects, and checked.
+        let next = ptr.add(1).unwrap();
+        assert_eq!(next.addr(), GuestAddress(0x1000));
+        assert_eq!(next.offset(-1), Some(ptr));
+        assert_eq!(ptr.offset(2).unwrap().addr(), GuestAddress(0x1008));
+        assert_eq!(GuestPtr::<u64>::new(GuestAddress(8)).offset(-2), None);
+        assert_eq!(
+            GuestPtr::<u64>::new(GuestAddress(u64::MAX - 7)).add(1),
+            None
+        );
+        assert_eq!(ptr.add(u64::MAX), None);
+
+        // Objects straddling regions can be read and written, but not referenced.
+        let straddling = ptr.cast::<u8>().add(4).unwrap().cast::<u64>();
+        straddling.write(&gm, !0).unwrap();
+        assert_eq!(straddling.read(&gm).unwrap(), !0);
+        assert_matches!(
+            straddling.as_volatile_ref(&gm),
+            Err(guest_memory::Error::InvalidBackendAddress)
+        );
+        assert!(GuestPtr::<u64>::new(GuestAddress(0x2000))
+            .read(&gm)
+            .is_err());
+
+        let r = next.as_volatile_ref(&gm).unwrap();
+        r.store(42);
+        assert_eq!(next.read(&gm).unwrap(), 42);
+        assert!(gm.iter().nth(1).unwrap().bitmap().dirty_at(0));
+    }
+
+    #[test]
+    fn test_guest_slice_ptr() {
+        let gm = GuestMemoryMmap::<()>::from_ranges(&[
+            (GuestAddress(0), 0x1000),
+            (GuestAddress(0x1000), 0x1000),
+        ])
+        .unwrap();
+        let ptr = GuestSlicePtr::<u32>::new(GuestAddress(0xff8), 4).unwrap();
+        assert_eq!(ptr.len(), 4);
+        assert_eq!(ptr.byte_len(), 16);
+        assert!(GuestSlicePtr::<u32>::new(GuestAddress(u64::MAX - 3), 2).is_none());
+        assert!(GuestSlicePtr::<u32>::new(GuestAddress(u64::MAX - 3), 1).is_some());
+        assert!(GuestSlicePtr::<u32>::new(GuestAddress(u64::MAX), 0).is_some());
+        assert!(GuestSlicePtr::<u32>::new(GuestAddress(0), usize::MAX).is_none());
+
+        ptr.write(&gm, &[1, 2, 3, 4]).unwrap();
+        assert_eq!(ptr.read(&gm).unwrap(), [1, 2, 3, 4]);
+        assert_eq!(ptr.index(3).unwrap().addr(), GuestAddress(0x1004));
+        assert_eq!(ptr.index(3).unwrap().read(&gm).unwrap(), 4);
+        assert_eq!(ptr.index(4), None);
+        let values: Vec<u32> = ptr.iter().map(|p| p.read(&gm).unwrap()).collect();
+        assert_eq!(values, [1, 2, 3, 4]);
+
+        let sub = ptr.subslice(2, 2).unwrap();
+        assert_eq!(sub.addr(), GuestAddress(0x1000));
+        assert_eq!(sub.read(&gm).unwrap(), [3, 4]);
+        assert!(ptr.subslice(3, 2).is_none());
+        assert!(ptr.subslice(usize::MAX, 2).is_none());
+        assert!(ptr.subslice(4, 0).unwrap().is_empty());
+
+        // Array references must lie within a single region.
+        assert!(ptr.as_volatile_array_ref(&gm).is_err());
+        let array = sub.as_volatile_array_ref(&gm).unwrap();
+        assert_eq!(array.len(), 2);
+        array.store(1, 5);
+        assert_eq!(sub.index(1).unwrap().read(&gm).unwrap(), 5);
+
+        assert!(GuestSlicePtr::<u32>::new(GuestAddress(0x1ffc), 2)
+            .unwrap()
+            .read(&gm)
+            .is_err());
+
+        // Reads spanning multiple chunks, and huge bogus lengths failing at the end of memory.
+        let ptr = GuestSlicePtr::<u64>::new(GuestAddress(0x10), 0x3f0).unwrap();
+        let values: Vec<u64> = (0..0x3f0).collect();
+        ptr.write(&gm, &values).unwrap();
+        assert_eq!(ptr.read(&gm).unwrap(), values);
+        assert!(GuestSlicePtr::<u64>::new(GuestAddress(0), usize::MAX / 16)
+            .unwrap()
+            .read(&gm)
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "mismatched number of objects")]
+    fn test_guest_slice_ptr_write_len() {
+        let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x1000)]).unwrap();
+        let ptr = GuestSlicePtr::<u32>::new(GuestAddress(0), 4).unwrap();
+        let _ = ptr.write(&gm, &[1, 2]);
+    }
+}
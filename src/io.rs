@@ -0,0 +1,543 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Versions of the standard library's [`Read`](std::io::Read) and [`Write`](std::io::Write)
+//! traits that operate on [`VolatileSlice`](struct.VolatileSlice.html)s.
+//!
+//! The `Read` and `Write` traits can only transfer data to and from rust-style slices, so
+//! accessing guest memory through them requires bouncing the data through an intermediate
+//! buffer. The `ReadVolatile` and `WriteVolatile` traits defined here allow implementations
+//! such as the ones for `File` or `UnixStream` to access the memory behind a `VolatileSlice`
+//! directly, while still recording write accesses in the associated dirty bitmap.
+
+use std::io::{Cursor, ErrorKind};
+
+use crate::bitmap::BitmapSlice;
+use crate::volatile_memory::{Error, VolatileSlice};
+
+/// A version of the standard library's [`Read`](std::io::Read) trait that operates on volatile
+/// memory instead of slices.
+///
+/// This trait is needed as rust slices (`&[u8]` and `&mut [u8]`) cannot be used when operating on
+/// guest memory.
+pub trait ReadVolatile {
+    /// Tries to read some bytes into the given [`VolatileSlice`] buffer, returning how many bytes
+    /// were read.
+    ///
+    /// The behavior of implementations should be identical to [`Read::read`](std::io::Read::read),
+    /// and the number of bytes read must be marked as dirty in the bitmap of `buf`.
+    fn read_volatile<B: BitmapSlice>(&mut self, buf: &mut VolatileSlice<B>)
+        -> Result<usize, Error>;
+
+    /// Tries to fill the given [`VolatileSlice`] buffer by reading from `self` returning an error
+    /// if insufficient bytes could be read.
+    ///
+    /// The default implementation is identical to that of
+    /// [`Read::read_exact`](std::io::Read::read_exact).
+    fn read_exact_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &mut VolatileSlice<B>,
+    ) -> Result<(), Error> {
+        // Implementation based on https://github.com/rust-lang/rust/blob/7e7483d26e3cec7a44ef00cf7ae6c9c8c918bec6/library/std/src/io/mod.rs#L465
+
+        let mut partial_buf = buf.offset(0)?;
+
+        while !partial_buf.is_empty() {
+            match self.read_volatile(&mut partial_buf) {
+                Err(Error::IOError(err)) if err.kind() == ErrorKind::Interrupted => continue,
+                Ok(0) => {
+                    return Err(Error::IOError(std::io::Error::new(
+                        ErrorKind::UnexpectedEof,
+                        "failed to fill whole buffer",
+                    )))
+                }
+                Ok(bytes_read) => partial_buf = partial_buf.offset(bytes_read)?,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// A version of the standard library's [`Write`](std::io::Write) trait that operates on volatile
+/// memory instead of slices.
+///
+/// This trait is needed as rust slices (`&[u8]` and `&mut [u8]`) cannot be used when operating on
+/// guest memory.
+pub trait WriteVolatile {
+    /// Tries to write some bytes from the given [`VolatileSlice`] buffer, returning how many bytes
+    /// were written.
+    ///
+    /// The behavior of implementations should be identical to
+    /// [`Write::write`](std::io::Write::write).
+    fn write_volatile<B: BitmapSlice>(&mut self, buf: &VolatileSlice<B>) -> Result<usize, Error>;
+
+    /// Tries write the entire content of the given [`VolatileSlice`] buffer to `self` returning an
+    /// error if not all bytes could be written.
+    ///
+    /// The default implementation is identical to that of
+    /// [`Write::write_all`](std::io::Write::write_all).
+    fn write_all_volatile<B: BitmapSlice>(&mut self, buf: &VolatileSlice<B>) -> Result<(), Error> {
+        // Based on https://github.com/rust-lang/rust/blob/7e7483d26e3cec7a44ef00cf7ae6c9c8c918bec6/library/std/src/io/mod.rs#L1570
+
+        let mut partial_buf = buf.offset(0)?;
+
+        while !partial_buf.is_empty() {
+            match self.write_volatile(&partial_buf) {
+                Err(Error::IOError(err)) if err.kind() == ErrorKind::Interrupted => continue,
+                Ok(0) => {
+                    return Err(Error::IOError(std::io::Error::new(
+                        ErrorKind::WriteZero,
+                        "failed to write whole buffer",
+                    )))
+                }
+                Ok(bytes_written) => partial_buf = partial_buf.offset(bytes_written)?,
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+mod raw_fd {
+    use std::os::unix::io::AsRawFd;
+
+    use super::*;
+
+    /// Tries to do a single `read` syscall on the provided file descriptor, storing the data read
+    /// in the given [`VolatileSlice`].
+    ///
+    /// Returns the numbers of bytes read.
+    pub(super) fn read_volatile<F, B>(
+        raw_fd: &F,
+        buf: &mut VolatileSlice<B>,
+    ) -> Result<usize, Error>
+    where
+        F: AsRawFd,
+        B: BitmapSlice,
+    {
+        let fd = raw_fd.as_raw_fd();
+
+        // SAFETY: We got a valid file descriptor from `AsRawFd`. The memory pointed to by `buf`
+        // is valid for writes of length `buf.len()` by the invariants upheld by the constructor
+        // of `VolatileSlice`.
+        let bytes_read = unsafe { libc::read(fd, buf.as_ptr() as *mut libc::c_void, buf.len()) };
+
+        if bytes_read < 0 {
+            Err(Error::IOError(std::io::Error::last_os_error()))
+        } else {
+            let bytes_read = bytes_read as usize;
+            // Mark the memory range that has been written to by the read as dirty.
+            buf.bitmap().mark_dirty(0, bytes_read);
+            Ok(bytes_read)
+        }
+    }
+
+    /// Tries to do a single `write` syscall on the provided file descriptor, attempting to write
+    /// the data stored in the given [`VolatileSlice`].
+    ///
+    /// Returns the numbers of bytes written.
+    pub(super) fn write_volatile<F, B>(raw_fd: &F, buf: &VolatileSlice<B>) -> Result<usize, Error>
+    where
+        F: AsRawFd,
+        B: BitmapSlice,
+    {
+        let fd = raw_fd.as_raw_fd();
+
+        // SAFETY: We got a valid file descriptor from `AsRawFd`. The memory pointed to by `buf`
+        // is valid for reads of length `buf.len()` by the invariants upheld by the constructor
+        // of `VolatileSlice`.
+        let bytes_written =
+            unsafe { libc::write(fd, buf.as_ptr() as *const libc::c_void, buf.len()) };
+
+        if bytes_written < 0 {
+            Err(Error::IOError(std::io::Error::last_os_error()))
+        } else {
+            Ok(bytes_written as usize)
+        }
+    }
+}
+
+macro_rules! impl_read_volatile_for_raw_fd {
+    ($raw_fd_ty:ty) => {
+        #[cfg(unix)]
+        impl ReadVolatile for $raw_fd_ty {
+            fn read_volatile<B: BitmapSlice>(
+                &mut self,
+                buf: &mut VolatileSlice<B>,
+            ) -> Result<usize, Error> {
+                raw_fd::read_volatile(self, buf)
+            }
+        }
+    };
+}
+
+macro_rules! impl_write_volatile_for_raw_fd {
+    ($raw_fd_ty:ty) => {
+        #[cfg(unix)]
+        impl WriteVolatile for $raw_fd_ty {
+            fn write_volatile<B: BitmapSlice>(
+                &mut self,
+                buf: &VolatileSlice<B>,
+            ) -> Result<usize, Error> {
+                raw_fd::write_volatile(self, buf)
+            }
+        }
+    };
+}
+
+impl_read_volatile_for_raw_fd!(std::fs::File);
+impl_read_volatile_for_raw_fd!(std::net::TcpStream);
+impl_read_volatile_for_raw_fd!(std::os::unix::net::UnixStream);
+impl_read_volatile_for_raw_fd!(std::process::ChildStdout);
+impl_read_volatile_for_raw_fd!(std::process::ChildStderr);
+
+impl_write_volatile_for_raw_fd!(std::fs::File);
+impl_write_volatile_for_raw_fd!(std::net::TcpStream);
+impl_write_volatile_for_raw_fd!(std::os::unix::net::UnixStream);
+impl_write_volatile_for_raw_fd!(std::process::ChildStdin);
+
+impl ReadVolatile for &[u8] {
+    fn read_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &mut VolatileSlice<B>,
+    ) -> Result<usize, Error> {
+        let total = buf.len().min(self.len());
+
+        // `copy_from` takes care of marking the written range as dirty.
+        buf.copy_from(&self[..total]);
+        *self = &self[total..];
+
+        Ok(total)
+    }
+}
+
+impl WriteVolatile for &mut [u8] {
+    fn write_volatile<B: BitmapSlice>(&mut self, buf: &VolatileSlice<B>) -> Result<usize, Error> {
+        let total = buf.copy_to(self);
+
+        // Advance the slice, just like the `Write` implementation for `&mut [u8]` does.
+        let (_, rest) = std::mem::take(self).split_at_mut(total);
+        *self = rest;
+
+        Ok(total)
+    }
+}
+
+impl WriteVolatile for Vec<u8> {
+    fn write_volatile<B: BitmapSlice>(&mut self, buf: &VolatileSlice<B>) -> Result<usize, Error> {
+        let old_len = self.len();
+        self.resize(old_len + buf.len(), 0);
+
+        Ok(buf.copy_to(&mut self[old_len..]))
+    }
+}
+
+impl<T: AsRef<[u8]>> ReadVolatile for Cursor<T> {
+    fn read_volatile<B: BitmapSlice>(
+        &mut self,
+        buf: &mut VolatileSlice<B>,
+    ) -> Result<usize, Error> {
+        let inner = self.get_ref().as_ref();
+        let start = std::cmp::min(self.position(), inner.len() as u64) as usize;
+
+        let bytes_read = (&inner[start..]).read_volatile(buf)?;
+        self.set_position(self.position() + bytes_read as u64);
+
+        Ok(bytes_read)
+    }
+}
+
+impl WriteVolatile for Cursor<&mut [u8]> {
+    fn write_volatile<B: BitmapSlice>(&mut self, buf: &VolatileSlice<B>) -> Result<usize, Error> {
+        let start = std::cmp::min(self.position(), self.get_ref().len() as u64) as usize;
+
+        let bytes_written = (&mut self.get_mut()[start..]).write_volatile(buf)?;
+        self.set_position(self.position() + bytes_written as u64);
+
+        Ok(bytes_written)
+    }
+}
+
+/// A batch of [`VolatileSlice`]s described as `iovec`s, for use with vectored I/O system calls
+/// such as `readv`, `writev` or `preadv2`.
+///
+/// The `iovec`s borrow the memory of the slices they were built from, so they can not outlive
+/// it. System calls that store data into the buffers bypass the dirty bitmap of the slices; call
+/// [`mark_dirty`](IoVecs::mark_dirty) with the number of bytes transferred afterwards.
+///
+/// No conversion to [`IoSlice`](std::io::IoSlice) is offered, since that would hand out rust
+/// slices referencing volatile memory.
+///
+/// # Examples (uses the `backend-mmap` feature)
+///
+/// ```
+/// # #[cfg(all(unix, feature = "backend-mmap"))]
+/// # {
+/// # use std::os::unix::io::AsRawFd;
+/// # use std::os::unix::net::UnixStream;
+/// # use vm_memory::io::IoVecs;
+/// # use vm_memory::{GuestAddress, GuestMemory, GuestMemoryMmap};
+/// #
+/// let gm = GuestMemoryMmap::<()>::from_ranges(&[
+///     (GuestAddress(0x0), 0x1000),
+///     (GuestAddress(0x1000), 0x1000),
+/// ])
+/// .expect("Could not create guest memory");
+/// let (mut tx, rx) = UnixStream::pair().unwrap();
+/// std::io::Write::write_all(&mut tx, &[0xa5; 0x100]).unwrap();
+///
+/// let iovecs: IoVecs<_> = gm
+///     .get_slices(GuestAddress(0xf80), 0x100)
+///     .collect::<Result<_, _>>()
+///     .expect("Could not get slices");
+/// let iov = iovecs.as_iovecs();
+///
+/// // SAFETY: The iovecs describe valid guest memory for as long as `iovecs` is alive.
+/// let ret = unsafe { libc::readv(rx.as_raw_fd(), iov.as_ptr(), iov.len() as libc::c_int) };
+/// assert_eq!(ret, 0x100);
+/// iovecs.mark_dirty(ret as usize);
+/// # }
+/// ```
+#[cfg(unix)]
+#[derive(Debug)]
+pub struct IoVecs<'a, B: BitmapSlice> {
+    slices: Vec<VolatileSlice<'a, B>>,
+    iovecs: Vec<libc::iovec>,
+}
+
+#[cfg(unix)]
+impl<'a, B: BitmapSlice> IoVecs<'a, B> {
+    /// Returns the `iovec`s describing the slices, in order.
+    pub fn as_iovecs(&self) -> &[libc::iovec] {
+        &self.iovecs
+    }
+
+    /// Returns the slices the `iovec`s were built from.
+    pub fn slices(&self) -> &[VolatileSlice<'a, B>] {
+        &self.slices
+    }
+
+    /// Returns the total number of bytes covered by the `iovec`s.
+    pub fn len(&self) -> usize {
+        self.slices.iter().map(|s| s.len()).sum()
+    }
+
+    /// Returns `true` if the `iovec`s don't cover any memory.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Marks the first `count` bytes covered by the `iovec`s as dirty.
+    ///
+    /// This should be called with the number of bytes a system call such as `readv` reported
+    /// as transferred into the buffers.
+    pub fn mark_dirty(&self, mut count: usize) {
+        for slice in self.slices.iter() {
+            if count == 0 {
+                break;
+            }
+            let len = std::cmp::min(count, slice.len());
+            slice.bitmap().mark_dirty(0, len);
+            count -= len;
+        }
+    }
+}
+
+#[cfg(unix)]
+impl<'a, B: BitmapSlice> FromIterator<VolatileSlice<'a, B>> for IoVecs<'a, B> {
+    fn from_iter<I: IntoIterator<Item = VolatileSlice<'a, B>>>(iter: I) -> Self {
+        let slices: Vec<_> = iter.into_iter().collect();
+        let iovecs = slices
+            .iter()
+            .map(|s| libc::iovec {
+                iov_base: s.as_ptr() as *mut libc::c_void,
+                iov_len: s.len(),
+            })
+            .collect();
+
+        IoVecs { slices, iovecs }
+    }
+}
+
+#[cfg(unix)]
+impl<'a, B: BitmapSlice> From<Vec<VolatileSlice<'a, B>>> for IoVecs<'a, B> {
+    fn from(slices: Vec<VolatileSlice<'a, B>>) -> Self {
+        slices.into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Seek, Write};
+
+    use vmm_sys_util::tempfile::TempFile;
+
+    use crate::bitmap::tests::{range_is_clean, range_is_dirty};
+    use crate::bitmap::{AtomicBitmap, Bitmap};
+
+    // Runs the given closure on a `VolatileSlice` backed by `mem` whose writes are tracked by a
+    // byte-granular `AtomicBitmap`, and returns the bitmap for inspection.
+    fn with_tracked_slice<F>(mem: &mut [u8], f: F) -> AtomicBitmap
+    where
+        F: FnOnce(&mut VolatileSlice<crate::bitmap::RefSlice<AtomicBitmap>>),
+    {
+        let bitmap = AtomicBitmap::new(mem.len(), 1);
+        {
+            // SAFETY: `mem` is a valid, exclusively borrowed buffer of `mem.len()` bytes.
+            let mut slice = unsafe {
+                VolatileSlice::with_bitmap(mem.as_mut_ptr(), mem.len(), bitmap.slice_at(0))
+            };
+            f(&mut slice);
+        }
+        bitmap
+    }
+
+    #[test]
+    fn test_read_volatile_from_slice() {
+        let src = [1u8, 2, 3, 4, 5];
+        let mut mem = [0u8; 8];
+
+        let bitmap = with_tracked_slice(&mut mem, |slice| {
+            let mut reader = &src[..];
+            assert_eq!(reader.read_volatile(slice).unwrap(), 5);
+            assert!(reader.is_empty());
+            assert_eq!(reader.read_volatile(slice).unwrap(), 0);
+        });
+
+        assert_eq!(mem, [1, 2, 3, 4, 5, 0, 0, 0]);
+        assert!(range_is_dirty(&bitmap, 0, 5));
+        assert!(range_is_clean(&bitmap, 5, 3));
+    }
+
+    #[test]
+    fn test_read_exact_volatile() {
+        let src = [7u8; 4];
+        let mut mem = [0u8; 8];
+
+        with_tracked_slice(&mut mem, |slice| {
+            let mut reader = &src[..];
+            match reader.read_exact_volatile(slice).unwrap_err() {
+                Error::IOError(e) => assert_eq!(e.kind(), ErrorKind::UnexpectedEof),
+                e => panic!("unexpected error {:?}", e),
+            }
+        });
+
+        let mut mem = [0u8; 4];
+        let bitmap = with_tracked_slice(&mut mem, |slice| {
+            let mut reader = Cursor::new(src);
+            reader.read_exact_volatile(slice).unwrap();
+            assert_eq!(reader.position(), 4);
+        });
+        assert_eq!(mem, src);
+        assert!(range_is_dirty(&bitmap, 0, 4));
+    }
+
+    #[test]
+    fn test_write_volatile_to_slice_and_vec() {
+        let mut mem = [1u8, 2, 3, 4, 5];
+        let slice = VolatileSlice::from(&mut mem[..]);
+
+        let mut dst = [0u8; 3];
+        let mut writer = &mut dst[..];
+        assert_eq!(writer.write_volatile(&slice).unwrap(), 3);
+        assert!(writer.is_empty());
+        assert_eq!(dst, [1, 2, 3]);
+
+        let mut dst = vec![9u8];
+        dst.write_all_volatile(&slice).unwrap();
+        assert_eq!(dst, vec![9, 1, 2, 3, 4, 5]);
+
+        let mut buf = [0u8; 8];
+        let mut cursor = Cursor::new(&mut buf[..]);
+        cursor.set_position(6);
+        match cursor.write_all_volatile(&slice).unwrap_err() {
+            Error::IOError(e) => assert_eq!(e.kind(), ErrorKind::WriteZero),
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(buf, [0, 0, 0, 0, 0, 0, 1, 2]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_file_read_write_volatile() {
+        let mut file = TempFile::new().unwrap().into_file();
+        file.write_all(&[1, 2, 3, 4, 5, 6]).unwrap();
+        file.rewind().unwrap();
+
+        let mut mem = [0u8; 4];
+        let bitmap = with_tracked_slice(&mut mem, |slice| {
+            file.read_exact_volatile(slice).unwrap();
+        });
+        assert_eq!(mem, [1, 2, 3, 4]);
+        assert!(range_is_dirty(&bitmap, 0, 4));
+
+        let mut mem = [0u8; 4];
+        with_tracked_slice(&mut mem, |slice| {
+            assert_eq!(file.read_volatile(slice).unwrap(), 2);
+        });
+        assert_eq!(mem, [5, 6, 0, 0]);
+
+        let mut mem = [0xaau8; 2];
+        file.write_all_volatile(&VolatileSlice::from(&mut mem[..]))
+            .unwrap();
+        file.rewind().unwrap();
+        let mut contents = Vec::new();
+        file.read_to_end(&mut contents).unwrap();
+        assert_eq!(contents, vec![1, 2, 3, 4, 5, 6, 0xaa, 0xaa]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_iovecs() {
+        use std::os::unix::io::AsRawFd;
+
+        let mut mem = [0u8; 8];
+        let bitmap = AtomicBitmap::new(mem.len(), 1);
+        // SAFETY: `mem` is a valid, exclusively borrowed buffer of `mem.len()` bytes.
+        let slice =
+            unsafe { VolatileSlice::with_bitmap(mem.as_mut_ptr(), mem.len(), bitmap.slice_at(0)) };
+        let (first, second) = slice.split_at(3).unwrap();
+        let iovecs: IoVecs<_> = vec![first, second.subslice(1, 4).unwrap()].into();
+        assert_eq!(iovecs.len(), 7);
+        assert_eq!(iovecs.as_iovecs().len(), 2);
+        assert_eq!(iovecs.as_iovecs()[1].iov_len, 4);
+
+        let (mut tx, rx) = std::os::unix::net::UnixStream::pair().unwrap();
+        tx.write_all(&[1, 2, 3, 4, 5]).unwrap();
+        let iov = iovecs.as_iovecs();
+        let ret = unsafe { libc::readv(rx.as_raw_fd(), iov.as_ptr(), iov.len() as libc::c_int) };
+        assert_eq!(ret, 5);
+        iovecs.mark_dirty(ret as usize);
+
+        assert_eq!(mem, [1, 2, 3, 0, 4, 5, 0, 0]);
+        assert!(range_is_dirty(&bitmap, 0, 3));
+        assert!(range_is_clean(&bitmap, 3, 1));
+        assert!(range_is_dirty(&bitmap, 4, 2));
+        assert!(range_is_clean(&bitmap, 6, 2));
+
+        let empty: IoVecs<()> = std::iter::empty().collect();
+        assert!(empty.is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_stream_read_write_volatile() {
+        let (mut tx, mut rx) = std::os::unix::net::UnixStream::pair().unwrap();
+
+        let mut src = [1u8, 2, 3, 4];
+        tx.write_all_volatile(&VolatileSlice::from(&mut src[..]))
+            .unwrap();
+
+        let mut mem = [0u8; 4];
+        let bitmap = with_tracked_slice(&mut mem, |slice| {
+            rx.read_exact_volatile(slice).unwrap();
+        });
+        assert_eq!(mem, src);
+        assert!(range_is_dirty(&bitmap, 0, 4));
+    }
+}
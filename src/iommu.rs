@@ -0,0 +1,938 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Guest memory as seen by devices behind an IOMMU.
+//!
+//! Devices behind a (virtual) IOMMU address memory with I/O virtual addresses (IOVAs), which
+//! the IOMMU translates to guest physical addresses. An [`Iotlb`](struct.Iotlb.html) holds the
+//! mappings from IOVA ranges to guest physical ranges along with their permissions, and
+//! [`IommuMemory`](struct.IommuMemory.html) uses it to expose the guest memory in the IOVA
+//! space through the usual `GuestMemory` and `Bytes<GuestAddress>` interfaces, so device code
+//! works the same with and without an IOMMU.
+//!
+//! `IommuMemory` is an immutable snapshot of the mappings; when the IOTLB changes, a new
+//! `IommuMemory` object can be published with
+//! [`GuestMemoryAtomic`](../atomic/struct.GuestMemoryAtomic.html).
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::address::Address;
+use crate::bitmap::BS;
+use crate::guest_memory::{
+    self, GuestAddress, GuestMemory, GuestMemoryIterator, GuestMemoryRegion, GuestUsize,
+    MemoryRegionAddress,
+};
+use crate::io::{ReadVolatile, WriteVolatile};
+use crate::volatile_memory::VolatileSlice;
+use crate::{AtomicAccess, Bytes};
+
+/// Kind of a memory access.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Access {
+    /// The device reads from memory.
+    Read,
+    /// The device writes to memory.
+    Write,
+}
+
+/// Accesses allowed by an IOTLB mapping.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Permissions {
+    /// The device may only read from the mapped memory.
+    ReadOnly,
+    /// The device may only write to the mapped memory.
+    WriteOnly,
+    /// The device may read from and write to the mapped memory.
+    ReadWrite,
+}
+
+impl Permissions {
+    /// Returns whether the permissions allow `access`.
+    pub fn allows(self, access: Access) -> bool {
+        !matches!(
+            (self, access),
+            (Permissions::ReadOnly, Access::Write) | (Permissions::WriteOnly, Access::Read)
+        )
+    }
+}
+
+/// Fault raised when an IOVA can not be translated to a guest physical address.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TranslationFault {
+    /// The IOVA is not mapped.
+    Unmapped(GuestAddress),
+    /// The mapping of the IOVA does not allow the access.
+    PermissionDenied {
+        /// The faulting IOVA.
+        iova: GuestAddress,
+        /// The denied access.
+        access: Access,
+    },
+}
+
+impl Display for TranslationFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranslationFault::Unmapped(iova) => write!(f, "IOVA {:#x} is not mapped", iova.0),
+            TranslationFault::PermissionDenied { iova, access } => {
+                write!(
+                    f,
+                    "{:?} access to IOVA {:#x} is not allowed",
+                    access, iova.0
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TranslationFault {}
+
+/// Mapping of a range of IOVAs to a range of guest physical addresses.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct IotlbEntry {
+    /// First IOVA of the mapping.
+    pub iova: GuestAddress,
+    /// Guest physical address `iova` maps to.
+    pub gpa: GuestAddress,
+    /// Length of the mapping.
+    pub len: GuestUsize,
+    /// Accesses allowed by the mapping.
+    pub perm: Permissions,
+}
+
+impl IotlbEntry {
+    fn last(&self) -> u64 {
+        self.iova.0 + (self.len - 1)
+    }
+}
+
+/// IOTLB mapping ranges of IOVAs to ranges of guest physical addresses.
+///
+/// # Examples
+///
+/// ```
+/// # use vm_memory::iommu::{Access, Iotlb, Permissions, TranslationFault};
+/// # use vm_memory::GuestAddress;
+/// #
+/// let mut iotlb = Iotlb::new();
+/// iotlb
+///     .insert(GuestAddress(0x1000), GuestAddress(0x8000), 0x2000, Permissions::ReadOnly)
+///     .unwrap();
+///
+/// assert_eq!(
+///     iotlb.translate(GuestAddress(0x1800), Access::Read),
+///     Ok((GuestAddress(0x8800), 0x1800))
+/// );
+/// assert_eq!(
+///     iotlb.translate(GuestAddress(0x1800), Access::Write),
+///     Err(TranslationFault::PermissionDenied {
+///         iova: GuestAddress(0x1800),
+///         access: Access::Write
+///     })
+/// );
+///
+/// iotlb.invalidate(GuestAddress(0x1000), 0x1000);
+/// assert_eq!(
+///     iotlb.translate(GuestAddress(0x1800), Access::Read),
+///     Err(TranslationFault::Unmapped(GuestAddress(0x1800)))
+/// );
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Iotlb {
+    // Non-overlapping entries, indexed by their first IOVA.
+    entries: BTreeMap<u64, IotlbEntry>,
+}
+
+impl Iotlb {
+    /// Creates an empty IOTLB.
+    pub fn new() -> Self {
+        Iotlb::default()
+    }
+
+    /// Maps the `len` bytes at `iova` to the guest physical addresses starting at `gpa`, with
+    /// the permissions `perm`. The mapping replaces the existing ones for the same IOVAs.
+    ///
+    /// Returns `GuestMemoryError::InvalidGuestAddress` if either range extends past the end of
+    /// the address space. Empty mappings are ignored.
+    pub fn insert(
+        &mut self,
+        iova: GuestAddress,
+        gpa: GuestAddress,
+        len: GuestUsize,
+        perm: Permissions,
+    ) -> guest_memory::Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        if iova.checked_add(len - 1).is_none() {
+            return Err(guest_memory::Error::InvalidGuestAddress(iova));
+        }
+        if gpa.checked_add(len - 1).is_none() {
+            return Err(guest_memory::Error::InvalidGuestAddress(gpa));
+        }
+
+        self.invalidate(iova, len);
+        self.entries.insert(
+            iova.0,
+            IotlbEntry {
+                iova,
+                gpa,
+                len,
+                perm,
+            },
+        );
+        Ok(())
+    }
+
+    /// Unmaps the `len` bytes at `iova`, splitting the mappings that are only partially covered
+    /// by the range.
+    pub fn invalidate(&mut self, iova: GuestAddress, len: GuestUsize) {
+        if len == 0 {
+            return;
+        }
+        let start = iova.0;
+        let last = start.saturating_add(len - 1);
+
+        // The entries are sorted and don't overlap, so their last IOVAs are sorted as well.
+        let overlapping: Vec<IotlbEntry> = self
+            .entries
+            .range(..=last)
+            .rev()
+            .map(|(_, entry)| *entry)
+            .take_while(|entry| entry.last() >= start)
+            .collect();
+
+        for entry in overlapping {
+            self.entries.remove(&entry.iova.0);
+            if entry.iova.0 < start {
+                let head = IotlbEntry {
+                    len: start - entry.iova.0,
+                    ..entry
+                };
+                self.entries.insert(head.iova.0, head);
+            }
+            if entry.last() > last {
+                let offset = last + 1 - entry.iova.0;
+                let tail = IotlbEntry {
+                    iova: GuestAddress(last + 1),
+                    gpa: entry.gpa.unchecked_add(offset),
+                    len: entry.len - offset,
+                    perm: entry.perm,
+                };
+                self.entries.insert(tail.iova.0, tail);
+            }
+        }
+    }
+
+    /// Translates `iova` for an `access` by the device, returning the guest physical address
+    /// it maps to and the number of bytes mapped contiguously from there.
+    pub fn translate(
+        &self,
+        iova: GuestAddress,
+        access: Access,
+    ) -> Result<(GuestAddress, GuestUsize), TranslationFault> {
+        let entry = self
+            .entries
+            .range(..=iova.0)
+            .next_back()
+            .map(|(_, entry)| entry)
+            .filter(|entry| entry.last() >= iova.0)
+            .ok_or(TranslationFault::Unmapped(iova))?;
+        if !entry.perm.allows(access) {
+            return Err(TranslationFault::PermissionDenied { iova, access });
+        }
+        let offset = iova.0 - entry.iova.0;
+        Ok((entry.gpa.unchecked_add(offset), entry.len - offset))
+    }
+
+    /// Returns an iterator over the mappings, in ascending order of IOVA.
+    pub fn entries(&self) -> impl Iterator<Item = &IotlbEntry> {
+        self.entries.values()
+    }
+}
+
+/// [`GuestMemoryRegion`](../trait.GuestMemoryRegion.html) implementation covering the IOVAs of
+/// an IOTLB mapping, as part of an [`IommuMemory`](struct.IommuMemory.html).
+///
+/// Accesses are checked against the permissions of the mapping, and forwarded to the guest
+/// memory at the translated addresses, which take care of dirty tracking. The bitmap of the
+/// region itself is never dirtied.
+pub struct IommuRegion<M: GuestMemory> {
+    entry: IotlbEntry,
+    mem: Arc<M>,
+    bitmap: <M::R as GuestMemoryRegion>::B,
+}
+
+impl<M: GuestMemory> fmt::Debug for IommuRegion<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IommuRegion")
+            .field("entry", &self.entry)
+            .finish()
+    }
+}
+
+impl<M: GuestMemory> IommuRegion<M> {
+    /// Returns the IOTLB mapping covered by the region.
+    pub fn entry(&self) -> &IotlbEntry {
+        &self.entry
+    }
+
+    // Checks that `access` is allowed, and that `count` bytes at `addr` are within the region,
+    // returning the guest physical address of the access.
+    fn translate(
+        &self,
+        addr: MemoryRegionAddress,
+        count: usize,
+        access: Access,
+    ) -> guest_memory::Result<GuestAddress> {
+        if !self.entry.perm.allows(access) {
+            return Err(guest_memory::Error::TranslationFault(
+                TranslationFault::PermissionDenied {
+                    iova: self.entry.iova.unchecked_add(addr.0),
+                    access,
+                },
+            ));
+        }
+        match self.entry.len.checked_sub(addr.0) {
+            Some(available) if available >= count as GuestUsize => {
+                Ok(self.entry.gpa.unchecked_add(addr.0))
+            }
+            _ => Err(guest_memory::Error::InvalidBackendAddress),
+        }
+    }
+
+    // Like `translate`, for accesses which are truncated at the end of the region. Returns the
+    // number of bytes to access along with their address.
+    fn translate_partial(
+        &self,
+        addr: MemoryRegionAddress,
+        count: usize,
+        access: Access,
+    ) -> guest_memory::Result<(GuestAddress, usize)> {
+        let available = self.entry.len.saturating_sub(addr.0);
+        if available == 0 && count > 0 {
+            return Err(guest_memory::Error::InvalidBackendAddress);
+        }
+        let count = std::cmp::min(count as GuestUsize, available) as usize;
+        self.translate(addr, count, access).map(|gpa| (gpa, count))
+    }
+}
+
+impl<M: GuestMemory> Bytes<MemoryRegionAddress> for IommuRegion<M> {
+    type E = guest_memory::Error;
+
+    fn write(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
+        let (gpa, count) = self.translate_partial(addr, buf.len(), Access::Write)?;
+        self.mem.write(&buf[..count], gpa)
+    }
+
+    fn read(&self, buf: &mut [u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
+        let (gpa, count) = self.translate_partial(addr, buf.len(), Access::Read)?;
+        self.mem.read(&mut buf[..count], gpa)
+    }
+
+    fn write_slice(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
+        let gpa = self.translate(addr, buf.len(), Access::Write)?;
+        self.mem.write_slice(buf, gpa)
+    }
+
+    fn read_slice(&self, buf: &mut [u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
+        let gpa = self.translate(addr, buf.len(), Access::Read)?;
+        self.mem.read_slice(buf, gpa)
+    }
+
+    fn read_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: Read,
+    {
+        let gpa = self.translate(addr, count, Access::Write)?;
+        self.mem.read_from(gpa, src, count)
+    }
+
+    fn read_exact_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: Read,
+    {
+        let gpa = self.translate(addr, count, Access::Write)?;
+        self.mem.read_exact_from(gpa, src, count)
+    }
+
+    fn write_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: Write,
+    {
+        let gpa = self.translate(addr, count, Access::Read)?;
+        self.mem.write_to(gpa, dst, count)
+    }
+
+    fn write_all_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: Write,
+    {
+        let gpa = self.translate(addr, count, Access::Read)?;
+        self.mem.write_all_to(gpa, dst, count)
+    }
+
+    fn read_volatile_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: ReadVolatile,
+    {
+        let gpa = self.translate(addr, count, Access::Write)?;
+        self.mem.read_volatile_from(gpa, src, count)
+    }
+
+    fn read_exact_volatile_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: ReadVolatile,
+    {
+        let gpa = self.translate(addr, count, Access::Write)?;
+        self.mem.read_exact_volatile_from(gpa, src, count)
+    }
+
+    fn write_volatile_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: WriteVolatile,
+    {
+        let gpa = self.translate(addr, count, Access::Read)?;
+        self.mem.write_volatile_to(gpa, dst, count)
+    }
+
+    fn write_all_volatile_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: WriteVolatile,
+    {
+        let gpa = self.translate(addr, count, Access::Read)?;
+        self.mem.write_all_volatile_to(gpa, dst, count)
+    }
+
+    fn store<T: AtomicAccess>(
+        &self,
+        val: T,
+        addr: MemoryRegionAddress,
+        order: Ordering,
+    ) -> guest_memory::Result<()> {
+        let gpa = self.translate(addr, size_of::<T>(), Access::Write)?;
+        self.mem.store(val, gpa, order)
+    }
+
+    fn load<T: AtomicAccess>(
+        &self,
+        addr: MemoryRegionAddress,
+        order: Ordering,
+    ) -> guest_memory::Result<T> {
+        let gpa = self.translate(addr, size_of::<T>(), Access::Read)?;
+        self.mem.load(gpa, order)
+    }
+}
+
+impl<M: GuestMemory> GuestMemoryRegion for IommuRegion<M> {
+    type B = <M::R as GuestMemoryRegion>::B;
+
+    fn len(&self) -> GuestUsize {
+        self.entry.len
+    }
+
+    fn start_addr(&self) -> GuestAddress {
+        self.entry.iova
+    }
+
+    fn bitmap(&self) -> &Self::B {
+        &self.bitmap
+    }
+
+    /// Translates the IOVA at `addr` to the host virtual address of the guest memory it maps
+    /// to. The returned pointer is not subject to the permissions of the mapping.
+    fn get_host_address(&self, addr: MemoryRegionAddress) -> guest_memory::Result<*mut u8> {
+        let gpa = self
+            .translate(addr, 0, Access::Read)
+            .or_else(|_| self.translate(addr, 0, Access::Write))?;
+        self.mem.get_host_address(gpa)
+    }
+
+    /// Returns a `VolatileSlice` of the guest memory the `count` bytes at `offset` map to.
+    /// Slices allow both reading and writing, so the mapping has to allow both.
+    fn get_slice(
+        &self,
+        offset: MemoryRegionAddress,
+        count: usize,
+    ) -> guest_memory::Result<VolatileSlice<'_, BS<'_, Self::B>>> {
+        self.translate(offset, count, Access::Read)?;
+        let gpa = self.translate(offset, count, Access::Write)?;
+        self.mem.get_slice(gpa, count)
+    }
+}
+
+/// [`GuestMemory`](../trait.GuestMemory.html) implementation exposing the guest memory `M` in
+/// the IOVA space defined by an [`Iotlb`](struct.Iotlb.html).
+///
+/// Every IOTLB mapping is a region of the `IommuMemory` object, so accesses are split at the
+/// boundaries of the mappings. Accesses to unmapped IOVAs, or which are not allowed by the
+/// permissions of the mappings, fail with `GuestMemoryError::TranslationFault`.
+///
+/// # Examples (uses the `backend-mmap` feature)
+///
+/// ```
+/// # #[cfg(feature = "backend-mmap")]
+/// # {
+/// # use std::sync::Arc;
+/// # use vm_memory::iommu::{Access, IommuMemory, Iotlb, Permissions, TranslationFault};
+/// # use vm_memory::{Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap};
+/// #
+/// let gm = Arc::new(
+///     GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x10000)])
+///         .expect("Could not create guest memory"),
+/// );
+/// let mut iotlb = Iotlb::new();
+/// iotlb
+///     .insert(GuestAddress(0xf000_0000), GuestAddress(0x8000), 0x1000, Permissions::ReadWrite)
+///     .unwrap();
+/// let iommu = IommuMemory::new(gm.clone(), iotlb);
+///
+/// iommu.write_obj(0x1234u32, GuestAddress(0xf000_0010)).unwrap();
+/// assert_eq!(gm.read_obj::<u32>(GuestAddress(0x8010)).unwrap(), 0x1234);
+/// assert!(matches!(
+///     iommu.read_obj::<u32>(GuestAddress(0x8010)),
+///     Err(GuestMemoryError::TranslationFault(TranslationFault::Unmapped(_)))
+/// ));
+/// # }
+/// ```
+pub struct IommuMemory<M: GuestMemory> {
+    mem: Arc<M>,
+    iotlb: Iotlb,
+    regions: Vec<IommuRegion<M>>,
+}
+
+impl<M: GuestMemory> fmt::Debug for IommuMemory<M> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IommuMemory")
+            .field("iotlb", &self.iotlb)
+            .finish()
+    }
+}
+
+impl<M: GuestMemory> IommuMemory<M>
+where
+    <M::R as GuestMemoryRegion>::B: Default,
+{
+    /// Creates a view of `mem` in the IOVA space defined by `iotlb`.
+    pub fn new(mem: Arc<M>, iotlb: Iotlb) -> Self {
+        let regions = iotlb
+            .entries()
+            .map(|entry| IommuRegion {
+                entry: *entry,
+                mem: mem.clone(),
+                bitmap: Default::default(),
+            })
+            .collect();
+        IommuMemory {
+            mem,
+            iotlb,
+            regions,
+        }
+    }
+}
+
+impl<M: GuestMemory> IommuMemory<M> {
+    /// Returns the guest memory seen through the IOMMU.
+    pub fn inner(&self) -> &Arc<M> {
+        &self.mem
+    }
+
+    /// Returns the IOTLB defining the IOVA space.
+    pub fn iotlb(&self) -> &Iotlb {
+        &self.iotlb
+    }
+}
+
+/// An iterator over the regions of an `IommuMemory`.
+///
+/// This struct is created by `GuestMemory::iter()`. See its documentation for more.
+pub struct Iter<'a, M: GuestMemory>(std::slice::Iter<'a, IommuRegion<M>>);
+
+impl<'a, M: GuestMemory> Iterator for Iter<'a, M> {
+    type Item = &'a IommuRegion<M>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next()
+    }
+}
+
+impl<'a, M: GuestMemory + 'a> GuestMemoryIterator<'a, IommuRegion<M>> for IommuMemory<M> {
+    type Iter = Iter<'a, M>;
+}
+
+impl<M: GuestMemory + 'static> GuestMemory for IommuMemory<M> {
+    type R = IommuRegion<M>;
+
+    type I = Self;
+
+    fn num_regions(&self) -> usize {
+        self.regions.len()
+    }
+
+    fn find_region(&self, addr: GuestAddress) -> Option<&IommuRegion<M>> {
+        guest_memory::find_sorted_region(&self.regions, addr)
+    }
+
+    fn iter(&self) -> Iter<'_, M> {
+        Iter(self.regions.iter())
+    }
+
+    /// Translates `addr` to its region, or fails with a translation fault if it is unmapped.
+    fn try_region_addr(
+        &self,
+        addr: GuestAddress,
+    ) -> guest_memory::Result<(&IommuRegion<M>, MemoryRegionAddress)> {
+        self.to_region_addr(addr)
+            .ok_or(guest_memory::Error::TranslationFault(
+                TranslationFault::Unmapped(addr),
+            ))
+    }
+
+    /// Like [`GuestMemory::try_access`](../trait.GuestMemory.html#method.try_access), except
+    /// that accesses running into unmapped IOVAs fail with a translation fault instead of being
+    /// truncated.
+    fn try_access<F>(
+        &self,
+        count: usize,
+        addr: GuestAddress,
+        mut f: F,
+    ) -> guest_memory::Result<usize>
+    where
+        F: FnMut(usize, usize, MemoryRegionAddress, &Self::R) -> guest_memory::Result<usize>,
+    {
+        let mut cur = addr;
+        let mut total = 0;
+        while total < count {
+            let (region, start) = self.try_region_addr(cur)?;
+            let cap = region.len() - start.raw_value();
+            let len = std::cmp::min(cap, (count - total) as GuestUsize);
+            match f(total, len as usize, start, region)? {
+                // no more data
+                0 => break,
+                len => {
+                    total += len;
+                    // Accesses wrapping around the IOVA space fault at address 0, unless it is
+                    // mapped.
+                    cur = cur.overflowing_add(len as GuestUsize).0;
+                }
+            }
+        }
+        Ok(total)
+    }
+}
+
+#[cfg(all(test, feature = "backend-mmap"))]
+mod tests {
+    use super::*;
+
+    use matches::assert_matches;
+
+    use crate::bitmap::{AtomicBitmap, Bitmap};
+    use crate::GuestMemoryMmap;
+
+    fn fault(e: guest_memory::Error) -> TranslationFault {
+        match e {
+            guest_memory::Error::TranslationFault(fault) => fault,
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[test]
+    fn test_iotlb() {
+        let mut iotlb = Iotlb::new();
+        iotlb
+            .insert(
+                GuestAddress(0x1000),
+                GuestAddress(0x10000),
+                0x3000,
+                Permissions::ReadWrite,
+            )
+            .unwrap();
+        iotlb
+            .insert(
+                GuestAddress(0x5000),
+                GuestAddress(0x20000),
+                0x1000,
+                Permissions::WriteOnly,
+            )
+            .unwrap();
+        iotlb
+            .insert(GuestAddress(0), GuestAddress(0), 0, Permissions::ReadOnly)
+            .unwrap();
+        assert!(iotlb
+            .insert(
+                GuestAddress(u64::MAX),
+                GuestAddress(0),
+                2,
+                Permissions::ReadOnly
+            )
+            .is_err());
+        assert!(iotlb
+            .insert(
+                GuestAddress(0),
+                GuestAddress(u64::MAX),
+                2,
+                Permissions::ReadOnly
+            )
+            .is_err());
+        assert_eq!(iotlb.entries().count(), 2);
+
+        assert_eq!(
+            iotlb.translate(GuestAddress(0x3fff), Access::Write),
+            Ok((GuestAddress(0x12fff), 1))
+        );
+        assert_eq!(
+            iotlb.translate(GuestAddress(0x4000), Access::Read),
+            Err(TranslationFault::Unmapped(GuestAddress(0x4000)))
+        );
+        assert_eq!(
+            iotlb.translate(GuestAddress(0x5000), Access::Read),
+            Err(TranslationFault::PermissionDenied {
+                iova: GuestAddress(0x5000),
+                access: Access::Read
+            })
+        );
+
+        // Overlapping mappings replace the parts of the existing ones they cover.
+        iotlb
+            .insert(
+                GuestAddress(0x2000),
+                GuestAddress(0x30000),
+                0x800,
+                Permissions::ReadOnly,
+            )
+            .unwrap();
+        let entries: Vec<_> = iotlb
+            .entries()
+            .map(|e| (e.iova.0, e.gpa.0, e.len))
+            .collect();
+        assert_eq!(
+            entries,
+            [
+                (0x1000, 0x10000, 0x1000),
+                (0x2000, 0x30000, 0x800),
+                (0x2800, 0x11800, 0x1800),
+                (0x5000, 0x20000, 0x1000)
+            ]
+        );
+
+        iotlb.invalidate(GuestAddress(0x1800), 0x3800);
+        let entries: Vec<_> = iotlb.entries().map(|e| (e.iova.0, e.len)).collect();
+        assert_eq!(entries, [(0x1000, 0x800), (0x5000, 0x1000)]);
+        iotlb.invalidate(GuestAddress(0), u64::MAX);
+        assert_eq!(iotlb.entries().count(), 0);
+    }
+
+    #[test]
+    fn test_iommu_memory() {
+        let gm = Arc::new(
+            GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[
+                (GuestAddress(0), 0x4000),
+                (GuestAddress(0x10000), 0x4000),
+            ])
+            .unwrap(),
+        );
+        let mut iotlb = Iotlb::new();
+        // Contiguous IOVAs mapping to scattered guest memory, across guest regions.
+        iotlb
+            .insert(
+                GuestAddress(0x10_0000),
+                GuestAddress(0x3000),
+                0x1000,
+                Permissions::ReadWrite,
+            )
+            .unwrap();
+        iotlb
+            .insert(
+                GuestAddress(0x10_1000),
+                GuestAddress(0x10000),
+                0x1000,
+                Permissions::ReadWrite,
+            )
+            .unwrap();
+        iotlb
+            .insert(
+                GuestAddress(0x10_2000),
+                GuestAddress(0x1000),
+                0x1000,
+                Permissions::ReadOnly,
+            )
+            .unwrap();
+        iotlb
+            .insert(
+                GuestAddress(0x20_0000),
+                GuestAddress(0x3ffc),
+                0x8,
+                Permissions::ReadWrite,
+            )
+            .unwrap();
+        let iommu = IommuMemory::new(gm.clone(), iotlb);
+        assert_eq!(iommu.num_regions(), 4);
+
+        // Accesses are split at mapping boundaries.
+        iommu
+            .write_slice(&[0xa5; 0x10], GuestAddress(0x10_0ff8))
+            .unwrap();
+        assert_eq!(
+            gm.read_obj::<u64>(GuestAddress(0x3ff8)).unwrap(),
+            !0 / 0xff * 0xa5
+        );
+        assert_eq!(
+            gm.read_obj::<u64>(GuestAddress(0x10000)).unwrap(),
+            !0 / 0xff * 0xa5
+        );
+        assert!(gm.iter().next().unwrap().bitmap().dirty_at(0x3ff8));
+        let mut buf = [0u8; 0x10];
+        iommu.read_slice(&mut buf, GuestAddress(0x10_0ff8)).unwrap();
+        assert_eq!(buf, [0xa5; 0x10]);
+
+        // Permissions are enforced, including for the parts of accesses spanning mappings.
+        gm.write_obj(7u32, GuestAddress(0x1000)).unwrap();
+        assert_eq!(iommu.read_obj::<u32>(GuestAddress(0x10_2000)).unwrap(), 7);
+        assert_eq!(
+            fault(iommu.write_obj(1u32, GuestAddress(0x10_2000)).unwrap_err()),
+            TranslationFault::PermissionDenied {
+                iova: GuestAddress(0x10_2000),
+                access: Access::Write
+            }
+        );
+        assert_eq!(
+            fault(
+                iommu
+                    .write_slice(&[1; 8], GuestAddress(0x10_1ffc))
+                    .unwrap_err()
+            ),
+            TranslationFault::PermissionDenied {
+                iova: GuestAddress(0x10_2000),
+                access: Access::Write
+            }
+        );
+        assert_eq!(
+            fault(
+                iommu
+                    .store(1u32, GuestAddress(0x10_2000), Ordering::Relaxed)
+                    .unwrap_err()
+            ),
+            TranslationFault::PermissionDenied {
+                iova: GuestAddress(0x10_2000),
+                access: Access::Write
+            }
+        );
+        assert_eq!(
+            fault(iommu.get_slice(GuestAddress(0x10_2000), 4).unwrap_err()),
+            TranslationFault::PermissionDenied {
+                iova: GuestAddress(0x10_2000),
+                access: Access::Write
+            }
+        );
+        assert!(iommu.get_host_address(GuestAddress(0x10_2000)).is_ok());
+
+        // Unmapped IOVAs fault, even after part of the access was carried out.
+        assert_eq!(
+            fault(iommu.read_obj::<u32>(GuestAddress(0x10_3000)).unwrap_err()),
+            TranslationFault::Unmapped(GuestAddress(0x10_3000))
+        );
+        assert_eq!(
+            fault(
+                iommu
+                    .read_slice(&mut buf, GuestAddress(0x10_2ffc))
+                    .unwrap_err()
+            ),
+            TranslationFault::Unmapped(GuestAddress(0x10_3000))
+        );
+        assert_eq!(
+            fault(iommu.get_host_address(GuestAddress(0)).unwrap_err()),
+            TranslationFault::Unmapped(GuestAddress(0))
+        );
+        assert_eq!(
+            fault(
+                iommu
+                    .load::<u32>(GuestAddress(0), Ordering::Relaxed)
+                    .unwrap_err()
+            ),
+            TranslationFault::Unmapped(GuestAddress(0))
+        );
+        assert_eq!(
+            fault(
+                iommu
+                    .store(1u32, GuestAddress(0x10_3000), Ordering::Relaxed)
+                    .unwrap_err()
+            ),
+            TranslationFault::Unmapped(GuestAddress(0x10_3000))
+        );
+        let mut slices = iommu.get_slices(GuestAddress(0x10_3000), 0x1000);
+        assert_eq!(
+            fault(slices.next().unwrap().unwrap_err()),
+            TranslationFault::Unmapped(GuestAddress(0x10_3000))
+        );
+        assert!(slices.next().is_none());
+
+        // Slices come from the guest memory, with dirty tracking.
+        let slice = iommu.get_slice(GuestAddress(0x10_1800), 4).unwrap();
+        slice.write_obj(1u32, 0).unwrap();
+        assert_eq!(gm.read_obj::<u32>(GuestAddress(0x10800)).unwrap(), 1);
+        assert!(gm.iter().nth(1).unwrap().bitmap().dirty_at(0x800));
+        assert_eq!(
+            iommu.get_host_address(GuestAddress(0x10_1800)).unwrap(),
+            gm.get_host_address(GuestAddress(0x10800)).unwrap()
+        );
+
+        // Errors of the guest memory are passed through.
+        assert_matches!(
+            iommu.read_obj::<u64>(GuestAddress(0x20_0000)),
+            Err(guest_memory::Error::InvalidGuestAddress(GuestAddress(
+                0x4000
+            )))
+        );
+        assert_eq!(
+            iommu
+                .write_volatile_to(GuestAddress(0x10_0000), &mut Vec::new(), 0x1800)
+                .unwrap(),
+            0x1800
+        );
+    }
+}
@@ -35,9 +35,14 @@ pub mod bitmap;
 
 pub mod bytes;
 pub use bytes::{AtomicAccess, ByteValued, Bytes};
+#[cfg(feature = "derive")]
+pub use vm_memory_derive::ByteValued;
 
 pub mod endian;
-pub use endian::{Be16, Be32, Be64, BeSize, Le16, Le32, Le64, LeSize};
+pub use endian::{
+    Be16, Be32, Be64, BeF32, BeF64, BeI16, BeI32, BeI64, BeSize, EndianBytes, EndianPrimitive,
+    Le16, Le32, Le64, LeF32, LeF64, LeI16, LeI32, LeI64, LeSize,
+};
 
 pub mod guest_memory;
 pub use guest_memory::{
@@ -45,6 +50,12 @@ pub use guest_memory::{
     GuestMemoryRegion, GuestUsize, MemoryRegionAddress, Result as GuestMemoryResult,
 };
 
+pub mod guest_ptr;
+pub use guest_ptr::{GuestPtr, GuestSlicePtr};
+
+pub mod io;
+pub use io::{ReadVolatile, WriteVolatile};
+
 #[cfg(all(feature = "backend-mmap", unix))]
 mod mmap_unix;
 
@@ -56,6 +67,18 @@ pub mod mmap;
 #[cfg(feature = "backend-mmap")]
 pub use mmap::{Error, GuestMemoryMmap, GuestRegionMmap, MmapRegion};
 
+pub mod iommu;
+pub use iommu::{IommuMemory, Iotlb};
+
+pub mod mmio;
+#[cfg(feature = "backend-mmap")]
+pub use mmio::{GuestMemoryHybrid, GuestRegionHybrid};
+pub use mmio::{MmioHandler, MmioRegion};
+
+pub mod page_table;
+
+pub mod snapshot;
+
 pub mod volatile_memory;
 pub use volatile_memory::{
     Error as VolatileMemoryError, Result as VolatileMemoryResult, VolatileArrayRef, VolatileMemory,
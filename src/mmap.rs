@@ -15,13 +15,17 @@
 use std::borrow::Borrow;
 use std::error;
 use std::fmt;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 #[cfg(unix)]
 use std::io::{Seek, SeekFrom};
 use std::ops::Deref;
 use std::result;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use std::sync::Mutex;
 
 use crate::address::Address;
 use crate::bitmap::{Bitmap, BS};
@@ -29,17 +33,23 @@ use crate::guest_memory::{
     self, FileOffset, GuestAddress, GuestMemory, GuestMemoryIterator, GuestMemoryRegion,
     GuestUsize, MemoryRegionAddress,
 };
-use crate::volatile_memory::{VolatileMemory, VolatileSlice};
+use crate::io::{ReadVolatile, WriteVolatile};
+use crate::volatile_memory::{self, VolatileMemory, VolatileSlice};
 use crate::{AtomicAccess, Bytes};
 
 #[cfg(unix)]
 pub use crate::mmap_unix::{Error as MmapRegionError, MmapRegion, MmapRegionBuilder};
+#[cfg(target_os = "linux")]
+pub use crate::mmap_unix::{MemfdOptions, MemoryAdvice};
 
 #[cfg(windows)]
 pub use crate::mmap_windows::MmapRegion;
 #[cfg(windows)]
 pub use std::io::Error as MmapRegionError;
 
+// Size of the scratch buffer consuming the data of the writes discarded by read-only regions.
+const DISCARD_CHUNK_SIZE: usize = 4096;
+
 /// A `Bitmap` that can be created starting from an initial size.
 pub trait NewBitmap: Bitmap + Default {
     /// Create a new object based on the specified length in bytes.
@@ -64,6 +74,44 @@ pub enum Error {
     MemoryRegionOverlap,
     /// The provided memory regions haven't been sorted.
     UnsortedMemoryRegions,
+    /// The guest memory range is not completely backed by memory regions.
+    InvalidGuestRange {
+        /// Start of the part of the range that is not backed by memory.
+        addr: GuestAddress,
+        /// Length of the part of the range that is not backed by memory.
+        len: usize,
+    },
+    /// Advising the kernel about the use of a guest memory range failed.
+    Madvise {
+        /// Start of the (page aligned) range.
+        addr: GuestAddress,
+        /// Length of the (page aligned) range.
+        len: usize,
+        /// The error returned by `madvise`.
+        error: io::Error,
+    },
+    /// Punching a hole into the file backing a guest memory range failed.
+    PunchHole {
+        /// Start of the (page aligned) range.
+        addr: GuestAddress,
+        /// Length of the (page aligned) range.
+        len: usize,
+        /// The error returned by `fallocate`.
+        error: io::Error,
+    },
+    /// Populating a guest memory range failed.
+    Populate {
+        /// Start of the (page aligned) range.
+        addr: GuestAddress,
+        /// Length of the (page aligned) range.
+        len: usize,
+        /// The error returned by `madvise`.
+        error: io::Error,
+    },
+    /// Populating some of the regions failed, with one error for each of them.
+    PopulateRegions(Vec<Error>),
+    /// Discarding was requested for a read-only region with `AccessPolicy::ReadOnlyError`.
+    ReadOnlyMemory(GuestAddress),
 }
 
 impl fmt::Display for Error {
@@ -82,6 +130,45 @@ impl fmt::Display for Error {
             Error::UnsortedMemoryRegions => {
                 write!(f, "The provided memory regions haven't been sorted")
             }
+            Error::InvalidGuestRange { addr, len } => write!(
+                f,
+                "The guest memory range at {:#x} of length {:#x} is not backed by memory",
+                addr.raw_value(),
+                len
+            ),
+            Error::Madvise { addr, len, error } => write!(
+                f,
+                "Error advising the guest memory range at {:#x} of length {:#x}: {}",
+                addr.raw_value(),
+                len,
+                error
+            ),
+            Error::PunchHole { addr, len, error } => write!(
+                f,
+                "Error punching a hole for the guest memory range at {:#x} of length {:#x}: {}",
+                addr.raw_value(),
+                len,
+                error
+            ),
+            Error::Populate { addr, len, error } => write!(
+                f,
+                "Error populating the guest memory range at {:#x} of length {:#x}: {}",
+                addr.raw_value(),
+                len,
+                error
+            ),
+            Error::PopulateRegions(errors) => {
+                write!(f, "Error populating {} memory region(s)", errors.len())?;
+                for e in errors {
+                    write!(f, "; {}", e)?;
+                }
+                Ok(())
+            }
+            Error::ReadOnlyMemory(addr) => write!(
+                f,
+                "Can not discard read-only guest memory at {:#x}",
+                addr.raw_value()
+            ),
         }
     }
 }
@@ -116,6 +203,31 @@ pub fn check_file_offset(
     Ok(())
 }
 
+/// Policy applied by a [`GuestRegionMmap`](struct.GuestRegionMmap.html) to the writes done
+/// through its `Bytes` implementation.
+///
+/// `VolatileSlice`s allow writing to the memory they cover, so read-only regions refuse to hand
+/// them out: `GuestMemoryRegion::get_slice` and `as_volatile_slice`, as well as the
+/// `GuestMemory` methods built on top of them such as `get_slice` and `get_slices`, fail with
+/// `GuestMemoryError::ReadOnlyMemory` for these regions, even when the slices would only be
+/// read from. Reading from read-only regions (e.g. for a device doing DMA from ROM) has to go
+/// through the `Bytes` methods instead.
+///
+/// The policy only covers these interfaces and is not a memory protection. The underlying
+/// `MmapRegion`, which `GuestRegionMmap` dereferences to, still hands out writable slices and
+/// pointers (e.g. through `VolatileMemory::get_slice` or `as_ptr`). Regions that must not be
+/// written to at all should also be mapped without `PROT_WRITE`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AccessPolicy {
+    /// The region is writable.
+    #[default]
+    ReadWrite,
+    /// The region is read-only, and writes fail with `GuestMemoryError::ReadOnlyMemory`.
+    ReadOnlyError,
+    /// The region is read-only, and writes are silently discarded (e.g. for x86 shadow ROMs).
+    ReadOnlyIgnore,
+}
+
 /// [`GuestMemoryRegion`](trait.GuestMemoryRegion.html) implementation that mmaps the guest's
 /// memory region in the current process.
 ///
@@ -125,6 +237,7 @@ pub fn check_file_offset(
 pub struct GuestRegionMmap<B = ()> {
     mapping: MmapRegion<B>,
     guest_base: GuestAddress,
+    access_policy: AccessPolicy,
 }
 
 impl<B> Deref for GuestRegionMmap<B> {
@@ -142,11 +255,189 @@ impl<B: Bitmap> GuestRegionMmap<B> {
             return Err(Error::InvalidGuestRegion);
         }
 
+        #[cfg(unix)]
+        let access_policy = mapping.access_policy();
+        #[cfg(windows)]
+        let access_policy = AccessPolicy::ReadWrite;
+
         Ok(GuestRegionMmap {
             mapping,
             guest_base,
+            access_policy,
         })
     }
+
+    /// Returns the access policy of the region.
+    pub fn access_policy(&self) -> AccessPolicy {
+        self.access_policy
+    }
+
+    /// Sets the access policy of the region, overriding the one of the underlying mapping.
+    ///
+    /// Read-only regions reject requests for `VolatileSlice`s (`get_slice` and the
+    /// `GuestMemory` methods built on top of it), since these allow writing to the region. The
+    /// policy doesn't apply to the underlying `MmapRegion`, see [`AccessPolicy`].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use vm_memory::mmap::AccessPolicy;
+    /// # use vm_memory::{Bytes, GuestAddress, GuestMemoryError, GuestMemoryMmap, GuestRegionMmap};
+    /// #
+    /// let rom = GuestRegionMmap::<()>::from_range(GuestAddress(0xf0000), 0x10000, None)
+    ///     .expect("Could not create region")
+    ///     .with_access_policy(AccessPolicy::ReadOnlyError);
+    /// let gm = GuestMemoryMmap::from_regions(vec![rom]).expect("Could not create guest memory");
+    ///
+    /// assert!(matches!(
+    ///     gm.write_obj(1u32, GuestAddress(0xffff0)),
+    ///     Err(GuestMemoryError::ReadOnlyMemory(GuestAddress(0xffff0)))
+    /// ));
+    /// assert_eq!(gm.read_obj::<u32>(GuestAddress(0xffff0)).unwrap(), 0);
+    /// ```
+    pub fn with_access_policy(mut self, access_policy: AccessPolicy) -> Self {
+        self.access_policy = access_policy;
+        self
+    }
+
+    // Checks a write to `addr` against the access policy of the region, returning whether the
+    // write must be carried out (`true`) or silently discarded (`false`).
+    fn check_write(&self, addr: MemoryRegionAddress) -> guest_memory::Result<bool> {
+        match self.access_policy {
+            AccessPolicy::ReadWrite => Ok(true),
+            AccessPolicy::ReadOnlyError => Err(self
+                .check_address(addr)
+                .map_or(guest_memory::Error::InvalidBackendAddress, |addr| {
+                    guest_memory::Error::ReadOnlyMemory(self.guest_base.unchecked_add(addr.0))
+                })),
+            AccessPolicy::ReadOnlyIgnore => Ok(false),
+        }
+    }
+
+    // Carries out a write of `count` bytes at `offset` discarded by `AccessPolicy::ReadOnlyIgnore`
+    // by passing `read` a scratch slice of at most `DISCARD_CHUNK_SIZE` bytes and the number of
+    // bytes to read into it, once or (if `exact` is set) until `count` bytes have been read.
+    // Returns the number of bytes read, and fails like the discarded write would if the range is
+    // not contained within the region.
+    fn discard_write<F>(
+        &self,
+        offset: usize,
+        count: usize,
+        exact: bool,
+        mut read: F,
+    ) -> guest_memory::Result<usize>
+    where
+        F: FnMut(&VolatileSlice, usize) -> volatile_memory::Result<usize>,
+    {
+        self.mapping.get_slice(offset, count)?;
+        let mut scratch = [0u8; DISCARD_CHUNK_SIZE];
+        let mut done = 0;
+        loop {
+            let len = std::cmp::min(count - done, scratch.len());
+            done += read(&VolatileSlice::from(&mut scratch[..len]), len)?;
+            if !exact || done == count {
+                return Ok(done);
+            }
+        }
+    }
+
+    /// Gives the kernel `advice` about the use of the `len` bytes at `addr` in the region.
+    ///
+    /// The range is extended to cover whole pages of the region, see
+    /// [`MmapRegion::page_size`](struct.MmapRegion.html#method.page_size).
+    #[cfg(target_os = "linux")]
+    pub fn advise(
+        &self,
+        addr: MemoryRegionAddress,
+        len: usize,
+        advice: MemoryAdvice,
+    ) -> result::Result<(), Error> {
+        self.mapping
+            .advise(addr.raw_value() as usize, len, advice)
+            .map_err(|e| self.to_guest_error(e))
+    }
+
+    /// Discards the contents of the `len` bytes at `addr` in the region, returning the memory
+    /// backing them to the host. Subsequent reads of the range return zeroes.
+    ///
+    /// See [`MmapRegion::discard`](struct.MmapRegion.html#method.discard) for how the memory is
+    /// given back depending on the backing of the region. Discarding is treated like a write
+    /// by the access policy of the region: it fails with `Error::ReadOnlyMemory` for
+    /// `AccessPolicy::ReadOnlyError`, and leaves the region untouched for
+    /// `AccessPolicy::ReadOnlyIgnore`.
+    #[cfg(target_os = "linux")]
+    pub fn discard_range(
+        &self,
+        addr: MemoryRegionAddress,
+        len: usize,
+    ) -> result::Result<(), Error> {
+        if !self.check_discard(addr, len)? {
+            return Ok(());
+        }
+        self.mapping
+            .discard(addr.raw_value() as usize, len)
+            .map_err(|e| self.to_guest_error(e))
+    }
+
+    // Checks discarding the `len` bytes at `addr` against the access policy of the region,
+    // returning whether the range must be discarded (`true`) or left untouched (`false`).
+    #[cfg(target_os = "linux")]
+    fn check_discard(&self, addr: MemoryRegionAddress, len: usize) -> result::Result<bool, Error> {
+        if self.access_policy == AccessPolicy::ReadWrite {
+            return Ok(true);
+        }
+        let end = (addr.raw_value() as usize).checked_add(len);
+        if !matches!(end, Some(end) if end <= self.mapping.size()) {
+            return Err(self.to_guest_error(MmapRegionError::InvalidRange {
+                offset: addr.raw_value() as usize,
+                len,
+            }));
+        }
+        match self.access_policy {
+            AccessPolicy::ReadOnlyError => Err(Error::ReadOnlyMemory(
+                self.guest_base.unchecked_add(addr.raw_value()),
+            )),
+            _ => Ok(false),
+        }
+    }
+
+    /// Faults in the pages backing the `len` bytes at `addr` in the region.
+    ///
+    /// See [`MmapRegion::populate`](struct.MmapRegion.html#method.populate).
+    #[cfg(target_os = "linux")]
+    pub fn populate(&self, addr: MemoryRegionAddress, len: usize) -> result::Result<(), Error> {
+        self.mapping
+            .populate(addr.raw_value() as usize, len)
+            .map_err(|e| self.to_guest_error(e))
+    }
+
+    // Translates the region offsets carried by a `MmapRegionError` into guest addresses.
+    #[cfg(target_os = "linux")]
+    fn to_guest_error(&self, error: MmapRegionError) -> Error {
+        let guest_addr = |offset: usize| self.guest_base.unchecked_add(offset as GuestUsize);
+        match error {
+            MmapRegionError::InvalidRange { offset, len } => Error::InvalidGuestRange {
+                addr: guest_addr(offset),
+                len,
+            },
+            MmapRegionError::Madvise { offset, len, error } => Error::Madvise {
+                addr: guest_addr(offset),
+                len,
+                error,
+            },
+            MmapRegionError::PunchHole { offset, len, error } => Error::PunchHole {
+                addr: guest_addr(offset),
+                len,
+                error,
+            },
+            MmapRegionError::Populate { offset, len, error } => Error::Populate {
+                addr: guest_addr(offset),
+                len,
+                error,
+            },
+            e => Error::MmapRegion(e),
+        }
+    }
 }
 
 impl<B: NewBitmap> GuestRegionMmap<B> {
@@ -156,12 +447,46 @@ impl<B: NewBitmap> GuestRegionMmap<B> {
         size: usize,
         file: Option<FileOffset>,
     ) -> result::Result<Self, Error> {
-        let region = if let Some(ref f_off) = file {
-            MmapRegion::from_file(f_off.clone(), size)
-        } else {
-            MmapRegion::new(size)
+        #[cfg(unix)]
+        {
+            Self::from_range_with_guard_pages(addr, size, file, 0)
         }
-        .map_err(Error::MmapRegion)?;
+
+        #[cfg(windows)]
+        {
+            let region = if let Some(ref f_off) = file {
+                MmapRegion::from_file(f_off.clone(), size)
+            } else {
+                MmapRegion::new(size)
+            }
+            .map_err(Error::MmapRegion)?;
+
+            Self::new(region, addr)
+        }
+    }
+
+    /// Create a new memory-mapped memory region from guest's physical memory, size and file,
+    /// surrounded by `guard_pages` inaccessible pages on each side of the mapping.
+    ///
+    /// See [`MmapRegionBuilder::with_guard_pages`](struct.MmapRegionBuilder.html#method.with_guard_pages).
+    #[cfg(unix)]
+    pub fn from_range_with_guard_pages(
+        addr: GuestAddress,
+        size: usize,
+        file: Option<FileOffset>,
+        guard_pages: usize,
+    ) -> result::Result<Self, Error> {
+        let builder = MmapRegionBuilder::new_with_bitmap(size, B::with_len(size))
+            .with_mmap_prot(libc::PROT_READ | libc::PROT_WRITE)
+            .with_guard_pages(guard_pages);
+        let builder = if let Some(f_off) = file {
+            builder
+                .with_file_offset(f_off)
+                .with_mmap_flags(libc::MAP_NORESERVE | libc::MAP_SHARED)
+        } else {
+            builder.with_mmap_flags(libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_PRIVATE)
+        };
+        let region = builder.build().map_err(Error::MmapRegion)?;
 
         Self::new(region, addr)
     }
@@ -187,8 +512,14 @@ impl<B: Bitmap> Bytes<MemoryRegionAddress> for GuestRegionMmap<B> {
     /// ```
     fn write(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
         let maddr = addr.raw_value() as usize;
-        self.as_volatile_slice()
-            .unwrap()
+        if !self.check_write(addr)? {
+            if !buf.is_empty() && maddr >= self.mapping.size() {
+                return Err(guest_memory::Error::InvalidBackendAddress);
+            }
+            return Ok(std::cmp::min(buf.len(), self.mapping.size() - maddr));
+        }
+        self.mapping
+            .as_volatile_slice()
             .write(buf, maddr)
             .map_err(Into::into)
     }
@@ -211,24 +542,34 @@ impl<B: Bitmap> Bytes<MemoryRegionAddress> for GuestRegionMmap<B> {
     /// ```
     fn read(&self, buf: &mut [u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
         let maddr = addr.raw_value() as usize;
-        self.as_volatile_slice()
-            .unwrap()
+        self.mapping
+            .as_volatile_slice()
             .read(buf, maddr)
             .map_err(Into::into)
     }
 
     fn write_slice(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
         let maddr = addr.raw_value() as usize;
-        self.as_volatile_slice()
-            .unwrap()
+        if !self.check_write(addr)? {
+            let len = self.write(buf, addr)?;
+            if len != buf.len() {
+                return Err(guest_memory::Error::PartialBuffer {
+                    expected: buf.len(),
+                    completed: len,
+                });
+            }
+            return Ok(());
+        }
+        self.mapping
+            .as_volatile_slice()
             .write_slice(buf, maddr)
             .map_err(Into::into)
     }
 
     fn read_slice(&self, buf: &mut [u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
         let maddr = addr.raw_value() as usize;
-        self.as_volatile_slice()
-            .unwrap()
+        self.mapping
+            .as_volatile_slice()
             .read_slice(buf, maddr)
             .map_err(Into::into)
     }
@@ -272,8 +613,13 @@ impl<B: Bitmap> Bytes<MemoryRegionAddress> for GuestRegionMmap<B> {
         F: Read,
     {
         let maddr = addr.raw_value() as usize;
-        self.as_volatile_slice()
-            .unwrap()
+        if !self.check_write(addr)? {
+            return self.discard_write(maddr, count, false, |scratch, len| {
+                scratch.read_from::<F>(0, src, len)
+            });
+        }
+        self.mapping
+            .as_volatile_slice()
             .read_from::<F>(maddr, src, count)
             .map_err(Into::into)
     }
@@ -317,8 +663,15 @@ impl<B: Bitmap> Bytes<MemoryRegionAddress> for GuestRegionMmap<B> {
         F: Read,
     {
         let maddr = addr.raw_value() as usize;
-        self.as_volatile_slice()
-            .unwrap()
+        if !self.check_write(addr)? {
+            return self
+                .discard_write(maddr, count, true, |scratch, len| {
+                    scratch.read_exact_from::<F>(0, src, len).map(|()| len)
+                })
+                .map(|_| ());
+        }
+        self.mapping
+            .as_volatile_slice()
             .read_exact_from::<F>(maddr, src, count)
             .map_err(Into::into)
     }
@@ -362,8 +715,8 @@ impl<B: Bitmap> Bytes<MemoryRegionAddress> for GuestRegionMmap<B> {
         F: Write,
     {
         let maddr = addr.raw_value() as usize;
-        self.as_volatile_slice()
-            .unwrap()
+        self.mapping
+            .as_volatile_slice()
             .write_to::<F>(maddr, dst, count)
             .map_err(Into::into)
     }
@@ -407,22 +760,125 @@ impl<B: Bitmap> Bytes<MemoryRegionAddress> for GuestRegionMmap<B> {
         F: Write,
     {
         let maddr = addr.raw_value() as usize;
-        self.as_volatile_slice()
-            .unwrap()
+        self.mapping
+            .as_volatile_slice()
             .write_all_to::<F>(maddr, dst, count)
             .map_err(Into::into)
     }
 
+    /// Reads up to `count` bytes from a [`ReadVolatile`] object directly into the region.
+    ///
+    /// # Examples
+    ///
+    /// * Read bytes from a file straight into guest memory
+    ///
+    /// ```
+    /// # use std::io::Cursor;
+    /// # use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+    /// #
+    /// # let start_addr = GuestAddress(0x1000);
+    /// # let gm = GuestMemoryMmap::<()>::from_ranges(&vec![(start_addr, 0x400)])
+    /// #    .expect("Could not create guest memory");
+    /// let mut src = Cursor::new(vec![0xa5u8; 128]);
+    ///
+    /// let bytes_read = gm
+    ///     .read_volatile_from(start_addr, &mut src, 128)
+    ///     .expect("Could not read into guest memory");
+    /// assert_eq!(bytes_read, 128);
+    /// ```
+    fn read_volatile_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: ReadVolatile,
+    {
+        let maddr = addr.raw_value() as usize;
+        if !self.check_write(addr)? {
+            return self.discard_write(maddr, count, false, |scratch, len| {
+                scratch.read_volatile_from::<F>(0, src, len)
+            });
+        }
+        self.mapping
+            .as_volatile_slice()
+            .read_volatile_from::<F>(maddr, src, count)
+            .map_err(Into::into)
+    }
+
+    fn read_exact_volatile_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: ReadVolatile,
+    {
+        let maddr = addr.raw_value() as usize;
+        if !self.check_write(addr)? {
+            return self
+                .discard_write(maddr, count, true, |scratch, len| {
+                    scratch
+                        .read_exact_volatile_from::<F>(0, src, len)
+                        .map(|()| len)
+                })
+                .map(|_| ());
+        }
+        self.mapping
+            .as_volatile_slice()
+            .read_exact_volatile_from::<F>(maddr, src, count)
+            .map_err(Into::into)
+    }
+
+    fn write_volatile_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: WriteVolatile,
+    {
+        let maddr = addr.raw_value() as usize;
+        self.mapping
+            .as_volatile_slice()
+            .write_volatile_to::<F>(maddr, dst, count)
+            .map_err(Into::into)
+    }
+
+    fn write_all_volatile_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: WriteVolatile,
+    {
+        let maddr = addr.raw_value() as usize;
+        self.mapping
+            .as_volatile_slice()
+            .write_all_volatile_to::<F>(maddr, dst, count)
+            .map_err(Into::into)
+    }
+
     fn store<T: AtomicAccess>(
         &self,
         val: T,
         addr: MemoryRegionAddress,
         order: Ordering,
     ) -> guest_memory::Result<()> {
-        self.as_volatile_slice().and_then(|s| {
-            s.store(val, addr.raw_value() as usize, order)
-                .map_err(Into::into)
-        })
+        let maddr = addr.raw_value() as usize;
+        if !self.check_write(addr)? {
+            self.mapping.get_atomic_ref::<T::A>(maddr)?;
+            return Ok(());
+        }
+        self.mapping
+            .as_volatile_slice()
+            .store(val, maddr, order)
+            .map_err(Into::into)
     }
 
     fn load<T: AtomicAccess>(
@@ -430,8 +886,10 @@ impl<B: Bitmap> Bytes<MemoryRegionAddress> for GuestRegionMmap<B> {
         addr: MemoryRegionAddress,
         order: Ordering,
     ) -> guest_memory::Result<T> {
-        self.as_volatile_slice()
-            .and_then(|s| s.load(addr.raw_value() as usize, order).map_err(Into::into))
+        self.mapping
+            .as_volatile_slice()
+            .load(addr.raw_value() as usize, order)
+            .map_err(Into::into)
     }
 }
 
@@ -472,6 +930,12 @@ impl<B: Bitmap> GuestMemoryRegion for GuestRegionMmap<B> {
         count: usize,
     ) -> guest_memory::Result<VolatileSlice<BS<B>>> {
         let slice = self.mapping.get_slice(offset.raw_value() as usize, count)?;
+        // Slices allow writing to the region, so they are not handed out for read-only regions.
+        if self.access_policy != AccessPolicy::ReadWrite {
+            return Err(guest_memory::Error::ReadOnlyMemory(
+                self.guest_base.unchecked_add(offset.raw_value()),
+            ));
+        }
         Ok(slice)
     }
 
@@ -481,6 +945,29 @@ impl<B: Bitmap> GuestMemoryRegion for GuestRegionMmap<B> {
     }
 }
 
+// Checks that `regions` is not empty, and that the regions are sorted by their starting address
+// and don't overlap. Shared by the constructors of the `GuestMemory` backends.
+pub(crate) fn check_regions<R: GuestMemoryRegion>(regions: &[Arc<R>]) -> result::Result<(), Error> {
+    if regions.is_empty() {
+        return Err(Error::NoMemoryRegion);
+    }
+
+    for window in regions.windows(2) {
+        let prev = &window[0];
+        let next = &window[1];
+
+        if prev.start_addr() > next.start_addr() {
+            return Err(Error::UnsortedMemoryRegions);
+        }
+
+        if prev.last_addr() >= next.start_addr() {
+            return Err(Error::MemoryRegionOverlap);
+        }
+    }
+
+    Ok(())
+}
+
 /// [`GuestMemory`](trait.GuestMemory.html) implementation that mmaps the guest's memory
 /// in the current process.
 ///
@@ -505,6 +992,23 @@ impl<B: NewBitmap> GuestMemoryMmap<B> {
         Self::from_ranges_with_files(ranges.iter().map(|r| (r.0, r.1, None)))
     }
 
+    /// Creates a container and allocates anonymous memory for guest memory regions, each of
+    /// them surrounded by `guard_pages` inaccessible pages on both sides.
+    ///
+    /// Valid memory regions are specified as a slice of (Address, Size) tuples sorted by Address.
+    #[cfg(unix)]
+    pub fn from_ranges_with_guard_pages(
+        ranges: &[(GuestAddress, usize)],
+        guard_pages: usize,
+    ) -> result::Result<Self, Error> {
+        Self::from_regions(
+            ranges
+                .iter()
+                .map(|r| GuestRegionMmap::from_range_with_guard_pages(r.0, r.1, None, guard_pages))
+                .collect::<result::Result<Vec<_>, Error>>()?,
+        )
+    }
+
     /// Creates a container and allocates anonymous memory for guest memory regions.
     ///
     /// Valid memory regions are specified as a sequence of (Address, Size, Option<FileOffset>)
@@ -550,23 +1054,7 @@ impl<B: Bitmap> GuestMemoryMmap<B> {
     ///               The regions shouldn't overlap and they should be sorted
     ///               by the starting address.
     pub fn from_arc_regions(regions: Vec<Arc<GuestRegionMmap<B>>>) -> result::Result<Self, Error> {
-        if regions.is_empty() {
-            return Err(Error::NoMemoryRegion);
-        }
-
-        for window in regions.windows(2) {
-            let prev = &window[0];
-            let next = &window[1];
-
-            if prev.start_addr() > next.start_addr() {
-                return Err(Error::UnsortedMemoryRegions);
-            }
-
-            if prev.last_addr() >= next.start_addr() {
-                return Err(Error::MemoryRegionOverlap);
-            }
-        }
-
+        check_regions(&regions)?;
         Ok(Self { regions })
     }
 
@@ -634,13 +1122,7 @@ impl<B: Bitmap + 'static> GuestMemory for GuestMemoryMmap<B> {
     }
 
     fn find_region(&self, addr: GuestAddress) -> Option<&GuestRegionMmap<B>> {
-        let index = match self.regions.binary_search_by_key(&addr, |x| x.start_addr()) {
-            Ok(x) => Some(x),
-            // Within the closest region with starting address < addr
-            Err(x) if (x > 0 && addr <= self.regions[x - 1].last_addr()) => Some(x - 1),
-            _ => None,
-        };
-        index.map(|x| self.regions[x].as_ref())
+        guest_memory::find_sorted_region(&self.regions, addr)
     }
 
     fn iter(&self) -> Iter<B> {
@@ -648,6 +1130,160 @@ impl<B: Bitmap + 'static> GuestMemory for GuestMemoryMmap<B> {
     }
 }
 
+// The part of a guest memory range contained within a region, as the region, the start
+// address within it, and the length.
+#[cfg(target_os = "linux")]
+type RegionRange<'a, B> = (&'a GuestRegionMmap<B>, MemoryRegionAddress, usize);
+
+#[cfg(target_os = "linux")]
+impl<B: Bitmap + 'static> GuestMemoryMmap<B> {
+    /// Gives the kernel `advice` about the use of the `len` bytes of guest memory at `addr`.
+    ///
+    /// The range may span multiple regions, and the part of the range within each region is
+    /// extended to cover whole pages of that region. The advice is only given once the whole
+    /// range has been checked to be backed by memory.
+    pub fn advise(
+        &self,
+        addr: GuestAddress,
+        len: usize,
+        advice: MemoryAdvice,
+    ) -> result::Result<(), Error> {
+        for (region, region_addr, count) in self.region_ranges(addr, len)? {
+            region.advise(region_addr, count, advice)?;
+        }
+        Ok(())
+    }
+
+    /// Discards the contents of the `len` bytes of guest memory at `addr`, returning the memory
+    /// backing them to the host, e.g. for memory ballooning or free page reporting. Subsequent
+    /// reads of the range return zeroes.
+    ///
+    /// The range may span multiple regions, and each part of it is discarded using the
+    /// mechanism matching the backing of its region, see
+    /// [`MmapRegion::discard`](struct.MmapRegion.html#method.discard). Parts of the range in
+    /// read-only regions are handled according to their access policy, see
+    /// [`GuestRegionMmap::discard_range`](struct.GuestRegionMmap.html#method.discard_range).
+    /// Nothing is discarded unless the whole range is backed by memory and none of it is in a
+    /// region with `AccessPolicy::ReadOnlyError`.
+    pub fn discard_range(&self, addr: GuestAddress, len: usize) -> result::Result<(), Error> {
+        let ranges = self.region_ranges(addr, len)?;
+        for (region, region_addr, count) in ranges.iter() {
+            region.check_discard(*region_addr, *count)?;
+        }
+        for (region, region_addr, count) in ranges {
+            region.discard_range(region_addr, count)?;
+        }
+        Ok(())
+    }
+
+    /// Faults in the pages backing all regions, splitting the work across `threads` threads.
+    ///
+    /// Each region is split into page aligned chunks that are populated using
+    /// [`MmapRegion::populate`](struct.MmapRegion.html#method.populate). Once a chunk of a
+    /// region fails, the rest of that region is skipped and populating continues with the other
+    /// regions. Failures are reported through `Error::PopulateRegions`, holding the error of the
+    /// first failing chunk of every failed region, ordered by address. Errors are only reported
+    /// on kernels supporting `MADV_POPULATE_*`; with the fallback of `MmapRegion::populate`,
+    /// a failure raises `SIGBUS` instead.
+    pub fn populate(&self, threads: usize) -> result::Result<(), Error>
+    where
+        B: Send + Sync,
+    {
+        let threads = std::cmp::max(threads, 1);
+
+        // Split every region into one chunk per thread, so that a single large region is shared
+        // among all threads as well.
+        let mut chunks = Vec::new();
+        for (index, region) in self.regions.iter().enumerate() {
+            let len = region.len() as usize;
+            let page_size = region.page_size();
+            let chunk_size =
+                std::cmp::max((len / threads).div_ceil(page_size) * page_size, page_size);
+            let mut offset = 0;
+            while offset < len {
+                let count = std::cmp::min(chunk_size, len - offset);
+                chunks.push((index, offset, count));
+                offset += count;
+            }
+        }
+
+        let next_chunk = AtomicUsize::new(0);
+        let failures: Mutex<Vec<Option<(usize, Error)>>> =
+            Mutex::new((0..self.regions.len()).map(|_| None).collect());
+        let worker = || {
+            while let Some(&(index, offset, count)) =
+                chunks.get(next_chunk.fetch_add(1, Ordering::Relaxed))
+            {
+                if failures.lock().unwrap()[index].is_some() {
+                    continue;
+                }
+                let region = &self.regions[index];
+                if let Err(e) = region.populate(MemoryRegionAddress(offset as u64), count) {
+                    let failure = &mut failures.lock().unwrap()[index];
+                    // Keep the error of the first failing chunk of the region.
+                    if !matches!(failure, Some((o, _)) if *o < offset) {
+                        *failure = Some((offset, e));
+                    }
+                }
+            }
+        };
+
+        if threads == 1 {
+            worker();
+        } else {
+            std::thread::scope(|s| {
+                for _ in 0..std::cmp::min(threads, chunks.len()) {
+                    s.spawn(worker);
+                }
+            });
+        }
+
+        let errors: Vec<Error> = failures
+            .into_inner()
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .map(|(_, e)| e)
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::PopulateRegions(errors))
+        }
+    }
+
+    // Splits the `len` bytes of guest memory at `addr` into the parts contained within each
+    // region, failing if part of the range is not backed by any region.
+    fn region_ranges(
+        &self,
+        addr: GuestAddress,
+        len: usize,
+    ) -> result::Result<Vec<RegionRange<'_, B>>, Error> {
+        let mut ranges = Vec::new();
+        let mut cur = addr;
+        let mut remaining = len;
+
+        while remaining > 0 {
+            let region = self.find_region(cur).ok_or(Error::InvalidGuestRange {
+                addr: cur,
+                len: remaining,
+            })?;
+            let region_addr = region.to_region_addr(cur).unwrap();
+            let count = std::cmp::min(
+                region.len() - region_addr.raw_value(),
+                remaining as GuestUsize,
+            ) as usize;
+
+            ranges.push((region, region_addr, count));
+            remaining -= count;
+            // This can't overflow, as any remaining bytes are located after the current region.
+            cur = cur.unchecked_add(count as GuestUsize);
+        }
+
+        Ok(ranges)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
@@ -659,7 +1295,9 @@ mod tests {
     use crate::bitmap::AtomicBitmap;
     use crate::GuestAddressSpace;
 
+    use matches::assert_matches;
     use std::fs::File;
+    use std::io::Seek;
     use std::mem;
     use std::path::Path;
     use vmm_sys_util::tempfile::TempFile;
@@ -1120,6 +1758,63 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_read_write_large_objects() {
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0), 0x1000),
+            (GuestAddress(0x1000), 0x1000),
+        ])
+        .unwrap();
+
+        // Arrays of any length round-trip, including across region boundaries.
+        let config_space: [u8; 64] = std::array::from_fn(|i| i as u8);
+        gm.write_obj(config_space, GuestAddress(0xfe0)).unwrap();
+        assert_eq!(
+            gm.read_obj::<[u8; 64]>(GuestAddress(0xfe0)).unwrap(),
+            config_space
+        );
+
+        let table: [u32; 256] = std::array::from_fn(|i| (i as u32) << 16 | 0xaa55);
+        gm.write_obj(table, GuestAddress(0xa00)).unwrap();
+        assert_eq!(
+            gm.read_obj::<[u32; 256]>(GuestAddress(0xa00)).unwrap(),
+            table
+        );
+        assert_eq!(
+            gm.read_obj::<u32>(GuestAddress(0xa00 + 4 * 255)).unwrap(),
+            0x00ff_aa55
+        );
+
+        let descriptors: [[crate::Le64; 2]; 128] =
+            std::array::from_fn(|i| [crate::Le64::from(i as u64), crate::Le64::from(!0)]);
+        gm.write_obj(descriptors, GuestAddress(0x800)).unwrap();
+        assert_eq!(
+            gm.read_obj::<[[crate::Le64; 2]; 128]>(GuestAddress(0x800))
+                .unwrap(),
+            descriptors
+        );
+
+        gm.write_obj(u128::MAX - 1, GuestAddress(0x10)).unwrap();
+        assert_eq!(
+            gm.read_obj::<u128>(GuestAddress(0x10)).unwrap(),
+            u128::MAX - 1
+        );
+        gm.write_obj([1.5f32, -2.25], GuestAddress(0x20)).unwrap();
+        assert_eq!(
+            gm.read_obj::<[f32; 2]>(GuestAddress(0x20)).unwrap(),
+            [1.5, -2.25]
+        );
+        gm.write_obj(std::f64::consts::PI, GuestAddress(0x28))
+            .unwrap();
+        assert_eq!(
+            gm.read_obj::<f64>(GuestAddress(0x28)).unwrap(),
+            std::f64::consts::PI
+        );
+
+        // Objects running past the end of guest memory are not read.
+        assert!(gm.read_obj::<[u8; 64]>(GuestAddress(0x1fe0)).is_err());
+    }
+
     #[test]
     fn write_and_read() {
         let f = TempFile::new().unwrap().into_file();
@@ -1278,6 +1973,306 @@ mod tests {
         }
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_from_ranges_with_guard_pages() {
+        let page_size = crate::mmap_unix::page_size();
+        let gm = GuestMemoryMmap::from_ranges_with_guard_pages(
+            &[(GuestAddress(0x0), 0x1000), (GuestAddress(0x1000), 0x2000)],
+            1,
+        )
+        .unwrap();
+
+        for region in gm.iter() {
+            assert_eq!(region.guard_size(), page_size);
+        }
+
+        let sample_buf = &[1, 2, 3, 4, 5];
+        assert_eq!(gm.write(sample_buf, GuestAddress(0xffc)).unwrap(), 5);
+        let buf = &mut [0u8; 5];
+        assert_eq!(gm.read(buf, GuestAddress(0xffc)).unwrap(), 5);
+        assert_eq!(buf, sample_buf);
+
+        let f = TempFile::new().unwrap().into_file();
+        f.set_len(0x1000).unwrap();
+        let region = GuestRegionMmap::from_range_with_guard_pages(
+            GuestAddress(0x1000),
+            0x1000,
+            Some(FileOffset::new(f, 0)),
+            2,
+        )
+        .unwrap();
+        assert_eq!(region.guard_size(), 2 * page_size);
+        assert_eq!(region.flags(), libc::MAP_NORESERVE | libc::MAP_SHARED);
+        assert!(region.file_offset().is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_guest_memory_advise() {
+        use crate::mmap_unix::tests::mapping_vm_flags;
+
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0x0), 0x1000),
+            (GuestAddress(0x1000), 0x2000),
+            (GuestAddress(0x4000), 0x1000),
+        ])
+        .unwrap();
+        let host_addr = |addr| gm.get_host_address(GuestAddress(addr)).unwrap() as usize;
+        let dont_dump = |addr| mapping_vm_flags(host_addr(addr)).contains(&"dd".to_owned());
+
+        gm.advise(GuestAddress(0xff0), 0x20, MemoryAdvice::DontDump)
+            .unwrap();
+        assert!(dont_dump(0x0));
+        assert!(dont_dump(0x1000));
+        assert!(!dont_dump(0x2000));
+        assert!(!dont_dump(0x4000));
+
+        // Nothing is advised if the range isn't completely backed by memory.
+        match gm
+            .advise(GuestAddress(0x2000), 0x2100, MemoryAdvice::DontDump)
+            .unwrap_err()
+        {
+            Error::InvalidGuestRange { addr, len } => {
+                assert_eq!(addr, GuestAddress(0x3000));
+                assert_eq!(len, 0x1100);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert!(!dont_dump(0x2000));
+
+        let region = gm.find_region(GuestAddress(0x4000)).unwrap();
+        region
+            .advise(MemoryRegionAddress(0), 0x1000, MemoryAdvice::DontDump)
+            .unwrap();
+        assert!(dont_dump(0x4000));
+        match region
+            .advise(MemoryRegionAddress(0x800), 0x1000, MemoryAdvice::DontDump)
+            .unwrap_err()
+        {
+            Error::InvalidGuestRange { addr, len } => {
+                assert_eq!(addr, GuestAddress(0x4800));
+                assert_eq!(len, 0x1000);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_guest_memory_discard_range() {
+        let f = TempFile::new().unwrap().into_file();
+        f.set_len(0x2000).unwrap();
+        let gm = GuestMemoryMmap::from_ranges_with_files(&[
+            (GuestAddress(0x0), 0x2000, None),
+            (GuestAddress(0x2000), 0x2000, Some(FileOffset::new(f, 0))),
+            (GuestAddress(0x5000), 0x1000, None),
+        ])
+        .unwrap();
+
+        gm.write_slice(&[0xffu8; 0x4000], GuestAddress(0x0))
+            .unwrap();
+        gm.write_slice(&[0xffu8; 0x1000], GuestAddress(0x5000))
+            .unwrap();
+
+        gm.discard_range(GuestAddress(0x1000), 0x2000).unwrap();
+        let mut buf = [0u8; 0x4000];
+        gm.read_slice(&mut buf, GuestAddress(0x0)).unwrap();
+        assert!(buf[..0x1000].iter().all(|&b| b == 0xff));
+        assert!(buf[0x1000..0x3000].iter().all(|&b| b == 0));
+        assert!(buf[0x3000..].iter().all(|&b| b == 0xff));
+
+        // Nothing is discarded if the range isn't completely backed by memory.
+        match gm.discard_range(GuestAddress(0x3000), 0x3000).unwrap_err() {
+            Error::InvalidGuestRange { addr, len } => {
+                assert_eq!(addr, GuestAddress(0x4000));
+                assert_eq!(len, 0x2000);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x3000)).unwrap(), 0xff);
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x5000)).unwrap(), 0xff);
+
+        let region = gm.find_region(GuestAddress(0x5000)).unwrap();
+        region
+            .discard_range(MemoryRegionAddress(0x10), 0x20)
+            .unwrap();
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x500f)).unwrap(), 0xff);
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x5010)).unwrap(), 0);
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x502f)).unwrap(), 0);
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x5030)).unwrap(), 0xff);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_guest_memory_populate() {
+        use crate::mmap_unix::tests::resident_pages;
+
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0x0), 0x10000),
+            (GuestAddress(0x10000), 0x3000),
+            (GuestAddress(0x20000), 0x1000),
+        ])
+        .unwrap();
+        gm.populate(3).unwrap();
+        for region in gm.iter() {
+            assert!(resident_pages(region.as_ptr(), region.size())
+                .iter()
+                .all(|&p| p));
+        }
+
+        // Failures are only reported by `MADV_POPULATE_WRITE`, the fallback would kill the test
+        // with `SIGBUS`.
+        if !crate::mmap_unix::tests::populate_write_supported() {
+            return;
+        }
+
+        let good = TempFile::new().unwrap().into_file();
+        good.set_len(0x4000).unwrap();
+        let bad = Arc::new(TempFile::new().unwrap().into_file());
+        bad.set_len(0x4000).unwrap();
+        let gm = GuestMemoryMmap::from_ranges_with_files(&[
+            (GuestAddress(0x0), 0x4000, Some(FileOffset::new(good, 0))),
+            (
+                GuestAddress(0x4000),
+                0x4000,
+                Some(FileOffset::from_arc(bad.clone(), 0)),
+            ),
+            (GuestAddress(0x8000), 0x4000, None),
+        ])
+        .unwrap();
+        bad.set_len(0x2000).unwrap();
+
+        for threads in [1, 4] {
+            match gm.populate(threads).unwrap_err() {
+                Error::PopulateRegions(errors) => {
+                    assert_eq!(errors.len(), 1);
+                    match &errors[0] {
+                        Error::Populate { addr, .. } => {
+                            assert!(*addr >= GuestAddress(0x4000) && *addr < GuestAddress(0x8000))
+                        }
+                        e => panic!("unexpected error {:?}", e),
+                    }
+                }
+                e => panic!("unexpected error {:?}", e),
+            }
+            // The other regions are still populated.
+            let region = gm.find_region(GuestAddress(0x8000)).unwrap();
+            assert!(resident_pages(region.as_ptr(), region.size())
+                .iter()
+                .all(|&p| p));
+        }
+
+        let region = gm.find_region(GuestAddress(0x4000)).unwrap();
+        region.populate(MemoryRegionAddress(0), 0x2000).unwrap();
+    }
+
+    #[test]
+    fn test_get_slices() {
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0x0), 0x1000),
+            (GuestAddress(0x1000), 0x1000),
+            (GuestAddress(0x3000), 0x1000),
+        ])
+        .unwrap();
+
+        let slices: Vec<_> = gm
+            .get_slices(GuestAddress(0xff0), 0x20)
+            .map(|s| s.unwrap())
+            .collect();
+        assert_eq!(slices.len(), 2);
+        assert_eq!(slices[0].len(), 0x10);
+        assert_eq!(slices[1].len(), 0x10);
+        assert_eq!(
+            slices[0].as_ptr(),
+            gm.get_host_address(GuestAddress(0xff0)).unwrap()
+        );
+        assert_eq!(
+            slices[1].as_ptr(),
+            gm.get_host_address(GuestAddress(0x1000)).unwrap()
+        );
+
+        assert_eq!(gm.get_slices(GuestAddress(0x100), 0).count(), 0);
+
+        // The iterator reports the hole between the second and third regions, then stops.
+        let mut iter = gm.get_slices(GuestAddress(0x1f00), 0x1200);
+        assert_eq!(iter.next().unwrap().unwrap().len(), 0x100);
+        assert_matches!(
+            iter.next(),
+            Some(Err(guest_memory::Error::InvalidGuestAddress(GuestAddress(
+                0x2000
+            ))))
+        );
+        assert!(iter.next().is_none());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_get_slices_dirty_tracking() {
+        let gm = super::GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[
+            (GuestAddress(0x0), 0x2000),
+            (GuestAddress(0x2000), 0x2000),
+        ])
+        .unwrap();
+
+        let iovecs: crate::io::IoVecs<_> = gm
+            .get_slices(GuestAddress(0x1800), 0x1000)
+            .collect::<guest_memory::Result<_>>()
+            .unwrap();
+        assert_eq!(iovecs.as_iovecs().len(), 2);
+        assert_eq!(iovecs.len(), 0x1000);
+
+        iovecs.mark_dirty(0x900);
+        let r0 = gm.find_region(GuestAddress(0)).unwrap();
+        let r1 = gm.find_region(GuestAddress(0x2000)).unwrap();
+        assert!(r0.bitmap().dirty_at(0x1800));
+        assert!(!r0.bitmap().dirty_at(0x0));
+        assert!(r1.bitmap().dirty_at(0x0));
+        assert!(!r1.bitmap().dirty_at(0x1000));
+    }
+
+    #[test]
+    fn test_volatile_io_cross_boundary() {
+        let start_addr1 = GuestAddress(0x0);
+        let start_addr2 = GuestAddress(0x1000);
+        let gm =
+            GuestMemoryMmap::from_ranges(&[(start_addr1, 0x1000), (start_addr2, 0x1000)]).unwrap();
+
+        let mut file = TempFile::new().unwrap().into_file();
+        file.write_all(&[0xa5; 0x20]).unwrap();
+        file.rewind().unwrap();
+
+        // Read straight from the file into a range spanning both regions.
+        gm.read_exact_volatile_from(GuestAddress(0xff0), &mut file, 0x20)
+            .unwrap();
+        let mut buf = [0u8; 0x20];
+        gm.read_slice(&mut buf, GuestAddress(0xff0)).unwrap();
+        assert_eq!(buf, [0xa5; 0x20]);
+
+        // Hitting the end of the file leaves the rest of the request untouched.
+        assert_matches!(
+            gm.read_exact_volatile_from(GuestAddress(0xff0), &mut file, 0x20),
+            Err(guest_memory::Error::PartialBuffer {
+                expected: 0x20,
+                completed: 0
+            })
+        );
+
+        let mut dst = Vec::new();
+        gm.write_all_volatile_to(GuestAddress(0xff8), &mut dst, 0x10)
+            .unwrap();
+        assert_eq!(dst, vec![0xa5; 0x10]);
+
+        // Writes past the end of guest memory are truncated.
+        let mut dst = Vec::new();
+        assert_eq!(
+            gm.write_volatile_to(GuestAddress(0x1ff8), &mut dst, 0x10)
+                .unwrap(),
+            0x8
+        );
+        assert_eq!(dst.len(), 0x8);
+    }
+
     #[test]
     fn test_retrieve_fd_backing_memory_region() {
         let f = TempFile::new().unwrap().into_file();
@@ -1530,5 +2525,208 @@ mod tests {
             crate::GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[(GuestAddress(0), 0x1_0000)])
                 .unwrap()
         });
+        test_guest_memory_and_region(|| {
+            crate::GuestMemoryMmap::<crate::bitmap::SparseBitmap>::from_ranges(&[(
+                GuestAddress(0),
+                0x1_0000,
+            )])
+            .unwrap()
+        });
+    }
+
+    #[test]
+    fn test_merge_dirty_log() {
+        let region =
+            super::GuestRegionMmap::<AtomicBitmap>::from_range(GuestAddress(0), 0x10_0000, None)
+                .unwrap();
+        let bitmap = region.bitmap();
+        region.write_obj(1u8, MemoryRegionAddress(0x8000)).unwrap();
+
+        // A KVM dirty log for the region, with pages 1 and 2 dirty.
+        let log = [0b110u64];
+        bitmap.merge_dirty_log(&log, 0x1000);
+        let dirty: Vec<_> = bitmap.dirty_ranges().collect();
+        assert_eq!(dirty.len(), 2);
+        assert!(bitmap.dirty_at(0x1000));
+        assert!(bitmap.dirty_at(0x2fff));
+        assert!(!bitmap.dirty_at(0x3000));
+        assert!(bitmap.dirty_at(0x8000));
+    }
+
+    #[test]
+    fn test_access_policy() {
+        use std::io::Cursor;
+
+        let rom = |addr, policy| {
+            super::GuestRegionMmap::<AtomicBitmap>::from_range(addr, 0x1000, None)
+                .unwrap()
+                .with_access_policy(policy)
+        };
+        let ram = super::GuestRegionMmap::<AtomicBitmap>::from_range(GuestAddress(0), 0x1000, None)
+            .unwrap();
+        assert_eq!(ram.access_policy(), AccessPolicy::ReadWrite);
+        let gm = super::GuestMemoryMmap::from_regions(vec![
+            ram,
+            rom(GuestAddress(0x1000), AccessPolicy::ReadOnlyError),
+            rom(GuestAddress(0x2000), AccessPolicy::ReadOnlyIgnore),
+        ])
+        .unwrap();
+
+        let addr = GuestAddress(0x1800);
+        let read_only = |e| assert_matches!(e, guest_memory::Error::ReadOnlyMemory(a) if a == addr);
+        read_only(gm.write(&[1; 4], addr).unwrap_err());
+        read_only(gm.write_slice(&[1; 4], addr).unwrap_err());
+        read_only(gm.write_obj(1u32, addr).unwrap_err());
+        read_only(gm.store(1u32, addr, Ordering::Relaxed).unwrap_err());
+        read_only(
+            gm.read_from(addr, &mut Cursor::new([1u8; 4]), 4)
+                .unwrap_err(),
+        );
+        read_only(
+            gm.read_exact_volatile_from(addr, &mut &[1u8; 4][..], 4)
+                .unwrap_err(),
+        );
+        read_only(gm.get_slice(addr, 4).unwrap_err());
+        assert!(gm.get_host_address(addr).is_ok());
+
+        // Writes spanning regions stop at the read-only one.
+        assert_matches!(
+            gm.write_slice(&[1; 0x10], GuestAddress(0xff8)),
+            Err(guest_memory::Error::ReadOnlyMemory(GuestAddress(0x1000)))
+        );
+        assert_eq!(gm.read_obj::<u64>(GuestAddress(0xff8)).unwrap(), !0 / 0xff);
+
+        // Discarded writes succeed, and consume their source.
+        let addr = GuestAddress(0x2800);
+        assert_eq!(gm.write(&[1; 4], addr).unwrap(), 4);
+        gm.write_slice(&[1; 4], addr).unwrap();
+        gm.store(1u32, addr, Ordering::Relaxed).unwrap();
+        assert!(gm
+            .store(1u32, addr.unchecked_add(1), Ordering::Relaxed)
+            .is_err());
+        let mut src = Cursor::new([1u8; 8]);
+        gm.read_exact_from(addr, &mut src, 4).unwrap();
+        assert_eq!(src.position(), 4);
+        let mut src = &[1u8; 8][..];
+        assert_eq!(gm.read_volatile_from(addr, &mut src, 8).unwrap(), 8);
+        assert!(src.is_empty());
+        assert_matches!(
+            gm.get_slice(addr, 4),
+            Err(guest_memory::Error::ReadOnlyMemory(a)) if a == addr
+        );
+
+        // Writes past the end of a region behave as usual.
+        assert_matches!(
+            gm.write_slice(&[1; 0x10], GuestAddress(0x2ff8)),
+            Err(guest_memory::Error::PartialBuffer {
+                expected: 0x10,
+                completed: 8
+            })
+        );
+
+        // Read-only regions are left untouched and clean.
+        for region in gm.iter().skip(1) {
+            let mut buf = [0xffu8; 0x1000];
+            region.read_slice(&mut buf, MemoryRegionAddress(0)).unwrap();
+            assert!(buf.iter().all(|&b| b == 0));
+            assert_eq!(region.bitmap().dirty_ranges().count(), 0);
+        }
+    }
+
+    #[test]
+    fn test_access_policy_discard_large_write() {
+        use std::io::Cursor;
+
+        let rom = GuestRegionMmap::from_range(GuestAddress(0), 0x4000, None)
+            .unwrap()
+            .with_access_policy(AccessPolicy::ReadOnlyIgnore);
+
+        // Discarded writes larger than the scratch buffer consume all of their source.
+        let mut src = Cursor::new(vec![1u8; 0x3800]);
+        rom.read_exact_from(MemoryRegionAddress(0x800), &mut src, 0x3000)
+            .unwrap();
+        assert_eq!(src.position(), 0x3000);
+        let mut src = &vec![1u8; 0x3800][..];
+        rom.read_exact_volatile_from(MemoryRegionAddress(0), &mut src, 0x3000)
+            .unwrap();
+        assert_eq!(src.len(), 0x800);
+        // Short sources and ranges past the end of the region still fail.
+        assert!(rom
+            .read_exact_from(MemoryRegionAddress(0), &mut Cursor::new([1u8; 0x10]), 0x20)
+            .is_err());
+        assert!(rom
+            .read_exact_from(MemoryRegionAddress(0x3800), &mut src, 0x1000)
+            .is_err());
+        assert_eq!(src.len(), 0x800);
+        assert_eq!(rom.read_obj::<u64>(MemoryRegionAddress(0x800)).unwrap(), 0);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_access_policy_discard_range() {
+        let region = |addr, policy| {
+            GuestRegionMmap::from_range(addr, 0x1000, None)
+                .unwrap()
+                .with_access_policy(policy)
+        };
+        let gm = GuestMemoryMmap::from_regions(vec![
+            region(GuestAddress(0), AccessPolicy::ReadWrite),
+            region(GuestAddress(0x1000), AccessPolicy::ReadOnlyError),
+            region(GuestAddress(0x2000), AccessPolicy::ReadOnlyIgnore),
+        ])
+        .unwrap();
+        for region in gm.iter() {
+            region
+                .mapping
+                .get_slice(0, 0x1000)
+                .unwrap()
+                .copy_from(&[0xffu8; 0x1000]);
+        }
+
+        assert_matches!(
+            gm.discard_range(GuestAddress(0x1800), 0x100).unwrap_err(),
+            Error::ReadOnlyMemory(GuestAddress(0x1800))
+        );
+        // Nothing is discarded if part of the range is in a region refusing it.
+        assert_matches!(
+            gm.discard_range(GuestAddress(0), 0x2000).unwrap_err(),
+            Error::ReadOnlyMemory(GuestAddress(0x1000))
+        );
+        gm.discard_range(GuestAddress(0x2000), 0x1000).unwrap();
+        assert_matches!(
+            gm.iter()
+                .nth(2)
+                .unwrap()
+                .discard_range(MemoryRegionAddress(0x800), 0x1000)
+                .unwrap_err(),
+            Error::InvalidGuestRange { .. }
+        );
+
+        for region in gm.iter() {
+            let mut buf = [0u8; 0x1000];
+            region.read_slice(&mut buf, MemoryRegionAddress(0)).unwrap();
+            assert!(buf.iter().all(|&b| b == 0xff));
+        }
+
+        gm.discard_range(GuestAddress(0), 0x1000).unwrap();
+        assert_eq!(gm.read_obj::<u64>(GuestAddress(0x800)).unwrap(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_access_policy_read_only_mapping() {
+        let mapping = MmapRegionBuilder::<()>::new(0x1000)
+            .with_mmap_prot(libc::PROT_READ)
+            .with_mmap_flags(libc::MAP_ANONYMOUS | libc::MAP_PRIVATE)
+            .with_access_policy(AccessPolicy::ReadOnlyIgnore)
+            .build()
+            .unwrap();
+        assert_eq!(mapping.access_policy(), AccessPolicy::ReadOnlyIgnore);
+        let region = GuestRegionMmap::new(mapping, GuestAddress(0)).unwrap();
+        assert_eq!(region.access_policy(), AccessPolicy::ReadOnlyIgnore);
+
+        // Writing would fault if the policy were not enforced.
+        region.write_obj(1u64, MemoryRegionAddress(0)).unwrap();
+        assert_eq!(region.read_obj::<u64>(MemoryRegionAddress(0)).unwrap(), 0);
     }
 }
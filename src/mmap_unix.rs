@@ -11,15 +11,23 @@
 //! Helper structure for working with mmaped memory regions in Unix.
 
 use std::error;
+#[cfg(target_os = "linux")]
+use std::ffi::CString;
 use std::fmt;
+#[cfg(target_os = "linux")]
+use std::fs::File;
 use std::io;
 use std::os::unix::io::AsRawFd;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::FromRawFd;
 use std::ptr::null_mut;
 use std::result;
+#[cfg(target_os = "linux")]
+use std::sync::atomic::{AtomicU8, Ordering};
 
 use crate::bitmap::{Bitmap, BS};
 use crate::guest_memory::FileOffset;
-use crate::mmap::{check_file_offset, NewBitmap};
+use crate::mmap::{check_file_offset, AccessPolicy, NewBitmap};
 use crate::volatile_memory::{self, VolatileMemory, VolatileSlice};
 
 /// Error conditions that may arise when creating a new `MmapRegion` object.
@@ -41,6 +49,52 @@ pub enum Error {
     SeekEnd(io::Error),
     /// Seeking the start of the file returned an error.
     SeekStart(io::Error),
+    /// Creating, resizing or sealing the memfd backing the region returned an error.
+    Memfd(io::Error),
+    /// A memfd backing was requested together with a `FileOffset`.
+    MemfdWithFileOffset,
+    /// The requested huge page size is not a power of two.
+    InvalidHugePageSize(usize),
+    /// The size of the mapping including its guard pages overflows.
+    InvalidGuardPages,
+    /// Discarding memory was requested for a private file backed mapping.
+    DiscardPrivateFileMapping,
+    /// Discarding memory was requested for a mapping without `PROT_WRITE`.
+    DiscardReadOnlyMapping,
+    /// The specified range is not contained within the region.
+    InvalidRange {
+        /// Offset of the range within the region.
+        offset: usize,
+        /// Length of the range.
+        len: usize,
+    },
+    /// The `madvise` call returned an error for the (page aligned) range.
+    Madvise {
+        /// Offset of the range within the region.
+        offset: usize,
+        /// Length of the range.
+        len: usize,
+        /// The error returned by `madvise`.
+        error: io::Error,
+    },
+    /// Punching a hole into the file backing the (page aligned) range returned an error.
+    PunchHole {
+        /// Offset of the range within the region.
+        offset: usize,
+        /// Length of the range.
+        len: usize,
+        /// The error returned by `fallocate`.
+        error: io::Error,
+    },
+    /// Populating the (page aligned) range returned an error.
+    Populate {
+        /// Offset of the range within the region.
+        offset: usize,
+        /// Length of the range.
+        len: usize,
+        /// The error returned by `madvise`.
+        error: io::Error,
+    },
 }
 
 impl fmt::Display for Error {
@@ -66,6 +120,43 @@ impl fmt::Display for Error {
             Error::Mmap(error) => write!(f, "{}", error),
             Error::SeekEnd(error) => write!(f, "Error seeking the end of the file: {}", error),
             Error::SeekStart(error) => write!(f, "Error seeking the start of the file: {}", error),
+            Error::Memfd(error) => write!(f, "Error setting up the memfd backing: {}", error),
+            Error::MemfdWithFileOffset => {
+                write!(f, "A memfd backing can not be combined with a file offset")
+            }
+            Error::InvalidHugePageSize(size) => {
+                write!(f, "The huge page size {:#x} is not a power of two", size)
+            }
+            Error::InvalidGuardPages => write!(
+                f,
+                "The size of the mapping including its guard pages overflows"
+            ),
+            Error::DiscardPrivateFileMapping => {
+                write!(f, "Private file backed mappings can not be discarded")
+            }
+            Error::DiscardReadOnlyMapping => {
+                write!(f, "Mappings without `PROT_WRITE` can not be discarded")
+            }
+            Error::InvalidRange { offset, len } => write!(
+                f,
+                "The range at offset {:#x} of length {:#x} is not contained within the region",
+                offset, len
+            ),
+            Error::Madvise { offset, len, error } => write!(
+                f,
+                "Error advising the range at offset {:#x} of length {:#x}: {}",
+                offset, len, error
+            ),
+            Error::PunchHole { offset, len, error } => write!(
+                f,
+                "Error punching a hole for the range at offset {:#x} of length {:#x}: {}",
+                offset, len, error
+            ),
+            Error::Populate { offset, len, error } => write!(
+                f,
+                "Error populating the range at offset {:#x} of length {:#x}: {}",
+                offset, len, error
+            ),
         }
     }
 }
@@ -74,6 +165,170 @@ impl error::Error for Error {}
 
 pub type Result<T> = result::Result<T, Error>;
 
+/// Options for backing a `MmapRegion` with an anonymous file created by `memfd_create`.
+///
+/// Unlike anonymous mappings, memfd backed regions carry a [`FileOffset`] whose file
+/// descriptor can be passed to other processes (e.g. vhost-user backends) to map the same
+/// memory.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Debug, Default)]
+pub struct MemfdOptions {
+    name: Option<String>,
+    hugetlb: bool,
+    huge_page_size: Option<usize>,
+    seal_shrink: bool,
+    seal_grow: bool,
+}
+
+#[cfg(target_os = "linux")]
+impl MemfdOptions {
+    /// Create a new `MemfdOptions` object for a regular, unsealed memfd.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use `name` for the memfd, which shows up in `/proc/<pid>/fd` and `/proc/<pid>/maps`.
+    pub fn with_name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    /// Back the memfd with huge pages (`MFD_HUGETLB`).
+    ///
+    /// `page_size` selects the huge page size in bytes; when it is `None` the default huge page
+    /// size of the system is used. The region size must be a multiple of the huge page size.
+    pub fn with_hugetlb(mut self, page_size: Option<usize>) -> Self {
+        self.hugetlb = true;
+        self.huge_page_size = page_size;
+        self
+    }
+
+    /// Prevent the memfd from shrinking (`F_SEAL_SHRINK`) once it has been sized.
+    pub fn with_seal_shrink(mut self, seal: bool) -> Self {
+        self.seal_shrink = seal;
+        self
+    }
+
+    /// Prevent the memfd from growing (`F_SEAL_GROW`) once it has been sized.
+    pub fn with_seal_grow(mut self, seal: bool) -> Self {
+        self.seal_grow = seal;
+        self
+    }
+
+    fn memfd_flags(&self) -> Result<libc::c_uint> {
+        let mut flags = libc::MFD_CLOEXEC;
+        if self.seal_shrink || self.seal_grow {
+            flags |= libc::MFD_ALLOW_SEALING;
+        }
+        if self.hugetlb {
+            flags |= libc::MFD_HUGETLB;
+            if let Some(page_size) = self.huge_page_size {
+                if !page_size.is_power_of_two() {
+                    return Err(Error::InvalidHugePageSize(page_size));
+                }
+                flags |= page_size.trailing_zeros() << libc::MFD_HUGE_SHIFT;
+            }
+        }
+        Ok(flags)
+    }
+
+    fn seals(&self) -> libc::c_int {
+        let mut seals = 0;
+        if self.seal_shrink {
+            seals |= libc::F_SEAL_SHRINK;
+        }
+        if self.seal_grow {
+            seals |= libc::F_SEAL_GROW;
+        }
+        seals
+    }
+
+    // Creates a memfd of `size` bytes and applies the requested seals.
+    fn create(&self, size: usize) -> Result<FileOffset> {
+        let name = CString::new(self.name.as_deref().unwrap_or("vm-memory"))
+            .map_err(|e| Error::Memfd(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+
+        // SAFETY: `name` is a valid NUL-terminated string, and we check the return value.
+        let fd = unsafe { libc::memfd_create(name.as_ptr(), self.memfd_flags()?) };
+        if fd < 0 {
+            return Err(Error::Memfd(io::Error::last_os_error()));
+        }
+        // SAFETY: `fd` is a freshly created file descriptor we exclusively own.
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        file.set_len(size as u64).map_err(Error::Memfd)?;
+
+        let seals = self.seals();
+        if seals != 0 {
+            // SAFETY: `file` holds a valid file descriptor, and we check the return value.
+            let ret = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) };
+            if ret < 0 {
+                return Err(Error::Memfd(io::Error::last_os_error()));
+            }
+        }
+
+        Ok(FileOffset::new(file, 0))
+    }
+}
+
+/// Advice about the expected use of a range of memory, passed on to `madvise`.
+///
+/// Destructive advice such as `MADV_DONTNEED` is deliberately not part of this type.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemoryAdvice {
+    /// No special treatment (`MADV_NORMAL`).
+    Normal,
+    /// Expect page references in random order (`MADV_RANDOM`).
+    Random,
+    /// Expect page references in sequential order (`MADV_SEQUENTIAL`).
+    Sequential,
+    /// Expect access in the near future (`MADV_WILLNEED`).
+    WillNeed,
+    /// Exclude the range from core dumps (`MADV_DONTDUMP`).
+    DontDump,
+    /// Undo the effect of `DontDump` (`MADV_DODUMP`).
+    DoDump,
+    /// Enable kernel samepage merging for the range (`MADV_MERGEABLE`).
+    Mergeable,
+    /// Undo the effect of `Mergeable` (`MADV_UNMERGEABLE`).
+    Unmergeable,
+    /// Enable transparent huge pages for the range (`MADV_HUGEPAGE`).
+    HugePage,
+    /// Prevent transparent huge pages for the range (`MADV_NOHUGEPAGE`).
+    NoHugePage,
+    /// Do not make the range available to the child after a `fork` (`MADV_DONTFORK`).
+    DontFork,
+    /// Undo the effect of `DontFork` (`MADV_DOFORK`).
+    DoFork,
+    /// Deactivate the range, making it a reclaim candidate (`MADV_COLD`).
+    Cold,
+    /// Reclaim the range right away (`MADV_PAGEOUT`).
+    PageOut,
+}
+
+#[cfg(target_os = "linux")]
+impl MemoryAdvice {
+    fn as_raw(self) -> libc::c_int {
+        match self {
+            MemoryAdvice::Normal => libc::MADV_NORMAL,
+            MemoryAdvice::Random => libc::MADV_RANDOM,
+            MemoryAdvice::Sequential => libc::MADV_SEQUENTIAL,
+            MemoryAdvice::WillNeed => libc::MADV_WILLNEED,
+            MemoryAdvice::DontDump => libc::MADV_DONTDUMP,
+            MemoryAdvice::DoDump => libc::MADV_DODUMP,
+            MemoryAdvice::Mergeable => libc::MADV_MERGEABLE,
+            MemoryAdvice::Unmergeable => libc::MADV_UNMERGEABLE,
+            MemoryAdvice::HugePage => libc::MADV_HUGEPAGE,
+            MemoryAdvice::NoHugePage => libc::MADV_NOHUGEPAGE,
+            MemoryAdvice::DontFork => libc::MADV_DONTFORK,
+            MemoryAdvice::DoFork => libc::MADV_DOFORK,
+            MemoryAdvice::Cold => libc::MADV_COLD,
+            MemoryAdvice::PageOut => libc::MADV_PAGEOUT,
+        }
+    }
+}
+
 /// A factory struct to build `MmapRegion` objects.
 pub struct MmapRegionBuilder<B = ()> {
     size: usize,
@@ -82,6 +337,10 @@ pub struct MmapRegionBuilder<B = ()> {
     file_offset: Option<FileOffset>,
     raw_ptr: Option<*mut u8>,
     hugetlbfs: Option<bool>,
+    #[cfg(target_os = "linux")]
+    memfd: Option<MemfdOptions>,
+    guard_pages: usize,
+    access_policy: AccessPolicy,
     bitmap: B,
 }
 
@@ -107,6 +366,10 @@ impl<B: Bitmap> MmapRegionBuilder<B> {
             file_offset: None,
             raw_ptr: None,
             hugetlbfs: None,
+            #[cfg(target_os = "linux")]
+            memfd: None,
+            guard_pages: 0,
+            access_policy: AccessPolicy::ReadWrite,
             bitmap,
         }
     }
@@ -135,6 +398,56 @@ impl<B: Bitmap> MmapRegionBuilder<B> {
         self
     }
 
+    /// Create the `MmapRegion` object backed by a new memfd, configured by `options`.
+    ///
+    /// The memfd is sized to the region and mapped with `MAP_SHARED` (`MAP_ANONYMOUS` and
+    /// `MAP_PRIVATE` are dropped from the mmap flags), and the resulting region exposes it
+    /// through [`MmapRegion::file_offset`]. The `hugetlbfs` flag of the region is set depending
+    /// on whether `MFD_HUGETLB` was requested. This can not be combined with
+    /// [`with_file_offset`](MmapRegionBuilder::with_file_offset).
+    #[cfg(target_os = "linux")]
+    pub fn with_memfd(mut self, options: MemfdOptions) -> Self {
+        self.memfd = Some(options);
+        self
+    }
+
+    /// Create the `MmapRegion` object surrounded by `pages` inaccessible (`PROT_NONE`) guard
+    /// pages on each side, so that accesses overrunning the region fault immediately.
+    ///
+    /// The guard pages use the base page size of the system, so regions backed by huge pages
+    /// may fail to map unless `pages` covers a whole number of huge pages. This setting has no
+    /// effect for regions created with
+    /// [`with_raw_mmap_pointer`](MmapRegionBuilder::with_raw_mmap_pointer).
+    pub fn with_guard_pages(mut self, pages: usize) -> Self {
+        self.guard_pages = pages;
+        self
+    }
+
+    /// Create the `MmapRegion` object with the specified `access_policy` for the writes done
+    /// through the guest memory interfaces, see [`AccessPolicy`](enum.AccessPolicy.html).
+    ///
+    /// The policy is independent of the `prot` flags of the mapping; read-only policies allow
+    /// mapping read-only memory with `PROT_READ` without writes through `Bytes` faulting.
+    pub fn with_access_policy(mut self, access_policy: AccessPolicy) -> Self {
+        self.access_policy = access_policy;
+        self
+    }
+
+    /// Create the `MmapRegion` object with all of its pages faulted in by `mmap`
+    /// (`MAP_POPULATE`).
+    ///
+    /// Note that `mmap` does not report failures to populate the mapping; use
+    /// [`MmapRegion::populate`] to find out about them.
+    #[cfg(target_os = "linux")]
+    pub fn with_populate(mut self, populate: bool) -> Self {
+        if populate {
+            self.flags |= libc::MAP_POPULATE;
+        } else {
+            self.flags &= !libc::MAP_POPULATE;
+        }
+        self
+    }
+
     /// Create the `MmapRegion` object with pre-mmapped raw pointer.
     ///
     /// # Safety
@@ -147,7 +460,7 @@ impl<B: Bitmap> MmapRegionBuilder<B> {
     }
 
     /// Build the `MmapRegion` object.
-    pub fn build(self) -> Result<MmapRegion<B>> {
+    pub fn build(mut self) -> Result<MmapRegion<B>> {
         if self.raw_ptr.is_some() {
             return self.build_raw();
         }
@@ -158,6 +471,17 @@ impl<B: Bitmap> MmapRegionBuilder<B> {
             return Err(Error::MapFixed);
         }
 
+        #[cfg(target_os = "linux")]
+        if let Some(memfd) = self.memfd.take() {
+            if self.file_offset.is_some() {
+                return Err(Error::MemfdWithFileOffset);
+            }
+            self.file_offset = Some(memfd.create(self.size)?);
+            self.flags =
+                (self.flags & !(libc::MAP_ANONYMOUS | libc::MAP_PRIVATE)) | libc::MAP_SHARED;
+            self.hugetlbfs = Some(memfd.hugetlb);
+        }
+
         let (fd, offset) = if let Some(ref f_off) = self.file_offset {
             check_file_offset(f_off, self.size)?;
             (f_off.file().as_raw_fd(), f_off.start())
@@ -165,6 +489,10 @@ impl<B: Bitmap> MmapRegionBuilder<B> {
             (-1, 0)
         };
 
+        if self.guard_pages > 0 {
+            return self.build_guarded(fd, offset);
+        }
+
         // SAFETY: This is safe because we're not allowing MAP_FIXED, and invalid parameters
         // cannot break Rust safety guarantees (things may change if we're mapping /dev/mem or
         // some wacky file).
@@ -186,19 +514,87 @@ impl<B: Bitmap> MmapRegionBuilder<B> {
         Ok(MmapRegion {
             addr: addr as *mut u8,
             size: self.size,
+            page_size: region_page_size(self.file_offset.as_ref(), self.flags),
+            bitmap: self.bitmap,
+            file_offset: self.file_offset,
+            prot: self.prot,
+            flags: self.flags,
+            owned: true,
+            hugetlbfs: self.hugetlbfs,
+            guard_size: 0,
+            access_policy: self.access_policy,
+        })
+    }
+
+    // Reserves the region together with its guard pages using an inaccessible mapping, and then
+    // maps the region itself over the middle part of the reservation.
+    fn build_guarded(self, fd: libc::c_int, offset: u64) -> Result<MmapRegion<B>> {
+        let guard_size = self
+            .guard_pages
+            .checked_mul(page_size())
+            .ok_or(Error::InvalidGuardPages)?;
+        let total_size = guard_size
+            .checked_mul(2)
+            .and_then(|s| s.checked_add(self.size))
+            .ok_or(Error::InvalidGuardPages)?;
+
+        // SAFETY: This is safe because we're not using MAP_FIXED, and an anonymous `PROT_NONE`
+        // mapping can not be used to access any memory.
+        let reserved = unsafe {
+            libc::mmap(
+                null_mut(),
+                total_size,
+                libc::PROT_NONE,
+                libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_PRIVATE,
+                -1,
+                0,
+            )
+        };
+
+        if reserved == libc::MAP_FAILED {
+            return Err(Error::Mmap(io::Error::last_os_error()));
+        }
+
+        // SAFETY: `MAP_FIXED` only replaces the part of the reservation we just created, which
+        // nobody else is using. The offset is within the bounds of the reservation.
+        let addr = unsafe {
+            libc::mmap(
+                (reserved as *mut u8).add(guard_size) as *mut libc::c_void,
+                self.size,
+                self.prot,
+                self.flags | libc::MAP_FIXED,
+                fd,
+                offset as libc::off_t,
+            )
+        };
+
+        if addr == libc::MAP_FAILED {
+            let err = io::Error::last_os_error();
+            // SAFETY: This is safe because we created the reservation above, and nobody else is
+            // holding a reference to it.
+            unsafe {
+                libc::munmap(reserved, total_size);
+            }
+            return Err(Error::Mmap(err));
+        }
+
+        Ok(MmapRegion {
+            addr: addr as *mut u8,
+            size: self.size,
+            page_size: region_page_size(self.file_offset.as_ref(), self.flags),
             bitmap: self.bitmap,
             file_offset: self.file_offset,
             prot: self.prot,
             flags: self.flags,
             owned: true,
             hugetlbfs: self.hugetlbfs,
+            guard_size,
+            access_policy: self.access_policy,
         })
     }
 
     fn build_raw(self) -> Result<MmapRegion<B>> {
-        // SAFETY: Safe because this call just returns the page size and doesn't have any side
-        // effects.
-        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+        let page_size = page_size();
         let addr = self.raw_ptr.unwrap();
 
         // Check that the pointer to the mapping is page-aligned.
@@ -209,12 +605,15 @@ impl<B: Bitmap> MmapRegionBuilder<B> {
         Ok(MmapRegion {
             addr,
             size: self.size,
+            page_size: region_page_size(self.file_offset.as_ref(), self.flags),
             bitmap: self.bitmap,
             file_offset: self.file_offset,
             prot: self.prot,
             flags: self.flags,
             owned: false,
             hugetlbfs: self.hugetlbfs,
+            guard_size: 0,
+            access_policy: self.access_policy,
         })
     }
 }
@@ -238,6 +637,9 @@ pub struct MmapRegion<B = ()> {
     flags: i32,
     owned: bool,
     hugetlbfs: Option<bool>,
+    page_size: usize,
+    guard_size: usize,
+    access_policy: AccessPolicy,
 }
 
 // SAFETY: Send and Sync aren't automatically inherited for the raw address pointer.
@@ -360,6 +762,16 @@ impl<B: Bitmap> MmapRegion<B> {
         self.owned
     }
 
+    /// Returns the size in bytes of the guard area reserved on each side of the region.
+    pub fn guard_size(&self) -> usize {
+        self.guard_size
+    }
+
+    /// Returns the access policy of the region.
+    pub fn access_policy(&self) -> AccessPolicy {
+        self.access_policy
+    }
+
     /// Checks whether this region and `other` are backed by overlapping
     /// [`FileOffset`](struct.FileOffset.html) objects.
     ///
@@ -399,6 +811,229 @@ impl<B: Bitmap> MmapRegion<B> {
     pub fn bitmap(&self) -> &B {
         &self.bitmap
     }
+
+    /// Returns the size of the pages backing this region.
+    ///
+    /// This is the huge page size for regions backed by hugetlbfs files or created with
+    /// `MAP_HUGETLB`, and the base page size of the system otherwise. It is determined once,
+    /// when the region is created.
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    /// Extends the range of `len` bytes at `offset` to cover whole pages of the region.
+    ///
+    /// Returns the offset and length of the extended range.
+    #[cfg(target_os = "linux")]
+    fn page_aligned_range(&self, offset: usize, len: usize) -> Result<(usize, usize)> {
+        match offset.checked_add(len) {
+            Some(end) if end <= self.size => {
+                let page_size = self.page_size();
+                let start = offset & !(page_size - 1);
+                // The mapping itself always covers whole pages.
+                let end = std::cmp::min(align_up(end, page_size), align_up(self.size, page_size));
+                Ok((start, end - start))
+            }
+            _ => Err(Error::InvalidRange { offset, len }),
+        }
+    }
+
+    /// Gives the kernel `advice` about the use of the `len` bytes at `offset` in the region.
+    ///
+    /// The range is extended to cover whole pages of the region, see
+    /// [`page_size`](MmapRegion::page_size).
+    #[cfg(target_os = "linux")]
+    pub fn advise(&self, offset: usize, len: usize, advice: MemoryAdvice) -> Result<()> {
+        let (offset, len) = self.page_aligned_range(offset, len)?;
+
+        // SAFETY: The range is contained within our mapping, and none of the advice we pass
+        // changes the contents of the memory.
+        let ret = unsafe {
+            libc::madvise(
+                self.addr.add(offset) as *mut libc::c_void,
+                len,
+                advice.as_raw(),
+            )
+        };
+        if ret < 0 {
+            return Err(Error::Madvise {
+                offset,
+                len,
+                error: io::Error::last_os_error(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Discards the contents of the `len` bytes at `offset` in the region, returning the
+    /// memory backing them to the host. Subsequent reads of the range return zeroes.
+    ///
+    /// The mechanism depends on how the region is backed:
+    /// - anonymous private mappings use `MADV_DONTNEED`;
+    /// - anonymous shared mappings use `MADV_REMOVE`;
+    /// - shared file backed mappings (including memfd and hugetlbfs ones) punch a hole into the
+    ///   file at the corresponding offset with `fallocate`;
+    ///
+    /// Private file backed mappings are rejected with
+    /// [`Error::DiscardPrivateFileMapping`], because dropping their pages would make them read
+    /// back the contents of the file rather than zeroes. Mappings without `PROT_WRITE` are
+    /// rejected with [`Error::DiscardReadOnlyMapping`]. Nothing is modified in either case.
+    ///
+    /// Only whole pages of the region (see [`page_size`](MmapRegion::page_size)) are given
+    /// back; partial pages at either end of the range are zeroed instead. The whole range is
+    /// marked as dirty in the bitmap of the region.
+    #[cfg(target_os = "linux")]
+    pub fn discard(&self, offset: usize, len: usize) -> Result<()> {
+        let end = match offset.checked_add(len) {
+            Some(end) if end <= self.size => end,
+            _ => return Err(Error::InvalidRange { offset, len }),
+        };
+        if self.prot & libc::PROT_WRITE == 0 {
+            return Err(Error::DiscardReadOnlyMapping);
+        }
+        if self.file_offset().is_some() && self.flags & libc::MAP_SHARED == 0 {
+            return Err(Error::DiscardPrivateFileMapping);
+        }
+
+        let page_size = self.page_size();
+        let start = std::cmp::min(align_up(offset, page_size), end);
+        // The mapping always covers whole pages, so a range ending with the region doesn't leave
+        // a partial page behind.
+        let aligned_end = if end == self.size {
+            align_up(end, page_size)
+        } else {
+            end & !(page_size - 1)
+        };
+        let aligned_end = std::cmp::max(aligned_end, start);
+
+        self.zero_range(offset, start - offset);
+        if aligned_end > start {
+            self.discard_pages(start, aligned_end - start)?;
+        }
+        if aligned_end < end {
+            self.zero_range(aligned_end, end - aligned_end);
+        }
+
+        self.bitmap.mark_dirty(offset, len);
+        Ok(())
+    }
+
+    // Gives the memory backing the page aligned range at `offset` back to the host. Private file
+    // backed mappings are rejected by the caller.
+    #[cfg(target_os = "linux")]
+    fn discard_pages(&self, offset: usize, len: usize) -> Result<()> {
+        let shared = self.flags & libc::MAP_SHARED != 0;
+        let advice = match self.file_offset() {
+            Some(f_off) => {
+                // SAFETY: The file descriptor is valid and we check the return value. The range
+                // of the file being punched out is the one backing our shared mapping, so this
+                // only affects memory we own.
+                let ret = unsafe {
+                    libc::fallocate(
+                        f_off.file().as_raw_fd(),
+                        libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                        (f_off.start() + offset as u64) as libc::off_t,
+                        len as libc::off_t,
+                    )
+                };
+                if ret < 0 {
+                    return Err(Error::PunchHole {
+                        offset,
+                        len,
+                        error: io::Error::last_os_error(),
+                    });
+                }
+                return Ok(());
+            }
+            None if shared => libc::MADV_REMOVE,
+            None => libc::MADV_DONTNEED,
+        };
+
+        // SAFETY: The range is contained within our mapping. Discarding the pages replaces their
+        // contents with zeroes, which is a valid value for guest memory that is only ever
+        // accessed through volatile accessors.
+        let ret = unsafe { libc::madvise(self.addr.add(offset) as *mut libc::c_void, len, advice) };
+        if ret < 0 {
+            return Err(Error::Madvise {
+                offset,
+                len,
+                error: io::Error::last_os_error(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Faults in the pages backing the `len` bytes at `offset` in the region, so that the first
+    /// access by the guest doesn't have to.
+    ///
+    /// Writable regions are populated with `MADV_POPULATE_WRITE`, and read-only ones with
+    /// `MADV_POPULATE_READ`, which report failures such as running out of huge pages as an
+    /// error. On kernels lacking support for these (before Linux 5.14), every page is touched
+    /// instead, without modifying its contents. This fallback reports no errors at all: a page
+    /// that can't be faulted in raises `SIGBUS`, which kills the process unless it is handled.
+    ///
+    /// The range is extended to cover whole pages of the region, see
+    /// [`page_size`](MmapRegion::page_size).
+    #[cfg(target_os = "linux")]
+    pub fn populate(&self, offset: usize, len: usize) -> Result<()> {
+        let (offset, len) = self.page_aligned_range(offset, len)?;
+        if len == 0 {
+            return Ok(());
+        }
+
+        let writable = self.prot & libc::PROT_WRITE != 0;
+        let advice = if writable {
+            libc::MADV_POPULATE_WRITE
+        } else {
+            libc::MADV_POPULATE_READ
+        };
+
+        // SAFETY: The range is contained within our mapping, and populating it doesn't change
+        // the contents of the memory.
+        let ret = unsafe { libc::madvise(self.addr.add(offset) as *mut libc::c_void, len, advice) };
+        if ret == 0 {
+            return Ok(());
+        }
+
+        let error = io::Error::last_os_error();
+        if error.raw_os_error() != Some(libc::EINVAL) {
+            return Err(Error::Populate { offset, len, error });
+        }
+
+        // The kernel doesn't know about `MADV_POPULATE_*`, so touch every page instead.
+        let page_size = self.page_size();
+        for page in (offset..offset + len).step_by(page_size) {
+            // SAFETY: The page is contained within our mapping. The atomic access is valid for
+            // any alignment, and leaves the contents of the memory unchanged even when racing
+            // with other accesses.
+            unsafe {
+                let byte = &*(self.addr.add(page) as *const AtomicU8);
+                if writable {
+                    byte.fetch_or(0, Ordering::Relaxed);
+                } else {
+                    byte.load(Ordering::Relaxed);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Writes zeroes to the `len` bytes at `offset`, which must be within the region.
+    #[cfg(target_os = "linux")]
+    fn zero_range(&self, offset: usize, len: usize) {
+        const ZEROES: [u8; 4096] = [0; 4096];
+        let mut done = 0;
+        while done < len {
+            let count = std::cmp::min(len - done, ZEROES.len());
+            self.get_slice(offset + done, count)
+                .unwrap()
+                .copy_from(&ZEROES[..count]);
+            done += count;
+        }
+    }
 }
 
 impl<B: Bitmap> VolatileMemory for MmapRegion<B> {
@@ -432,17 +1067,75 @@ impl<B: Bitmap> VolatileMemory for MmapRegion<B> {
 impl<B> Drop for MmapRegion<B> {
     fn drop(&mut self) {
         if self.owned {
-            // SAFETY: This is safe because we mmap the area at addr ourselves, and nobody
-            // else is holding a reference to it.
+            // SAFETY: This is safe because we mmap the area at addr ourselves, together with
+            // the guard pages around it, and nobody else is holding a reference to it.
             unsafe {
-                libc::munmap(self.addr as *mut libc::c_void, self.size);
+                libc::munmap(
+                    self.addr.sub(self.guard_size) as *mut libc::c_void,
+                    self.size + 2 * self.guard_size,
+                );
             }
         }
     }
 }
 
+// Returns the base page size of the system.
+pub(crate) fn page_size() -> usize {
+    // SAFETY: Safe because this call just returns the page size and doesn't have any side
+    // effects.
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+// Returns the size of the pages backing a mapping of `file_offset` created with `flags`.
+#[cfg(target_os = "linux")]
+fn region_page_size(file_offset: Option<&FileOffset>, flags: i32) -> usize {
+    if let Some(f_off) = file_offset {
+        // SAFETY: `statfs` is a plain C struct for which all zeroes is a valid value.
+        let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+        // SAFETY: The file descriptor is valid, and `stat` is a valid buffer for the result.
+        let ret = unsafe { libc::fstatfs(f_off.file().as_raw_fd(), &mut stat) };
+        if ret == 0 && stat.f_type == libc::HUGETLBFS_MAGIC {
+            return stat.f_bsize as usize;
+        }
+    }
+
+    if flags & libc::MAP_HUGETLB != 0 {
+        let shift = (flags >> libc::MAP_HUGE_SHIFT) & libc::MAP_HUGE_MASK;
+        if shift != 0 {
+            return 1 << shift;
+        }
+        if let Some(size) = default_huge_page_size() {
+            return size;
+        }
+    }
+
+    page_size()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn region_page_size(_file_offset: Option<&FileOffset>, _flags: i32) -> usize {
+    page_size()
+}
+
+// Returns the default huge page size of the system, as reported by `/proc/meminfo`.
+#[cfg(target_os = "linux")]
+fn default_huge_page_size() -> Option<usize> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find(|line| line.starts_with("Hugepagesize:"))?;
+    let kib = line.split_whitespace().nth(1)?.parse::<usize>().ok()?;
+    kib.checked_mul(1024)
+}
+
+// Rounds `value` up to a multiple of `align`, which must be a power of two.
+#[cfg(target_os = "linux")]
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
+
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     #![allow(clippy::undocumented_unsafe_blocks)]
     use super::*;
 
@@ -609,6 +1302,432 @@ mod tests {
         crate::bitmap::tests::test_volatile_memory(&(builder.build().unwrap()));
     }
 
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mmap_region_memfd() {
+        let size = 0x2000;
+        let r = MmapRegionBuilder::<()>::new(size)
+            .with_mmap_prot(libc::PROT_READ | libc::PROT_WRITE)
+            .with_memfd(MemfdOptions::new().with_name("test-memfd"))
+            .build()
+            .unwrap();
+        assert_eq!(r.size(), size);
+        assert_eq!(r.flags(), libc::MAP_SHARED);
+        assert_eq!(r.is_hugetlbfs(), Some(false));
+
+        let f_off = r.file_offset().unwrap().clone();
+        assert_eq!(f_off.start(), 0);
+        assert_eq!(f_off.file().metadata().unwrap().len(), size as u64);
+
+        // A second mapping of the memfd sees the writes done through the first one.
+        r.get_slice(0x1000, 4).unwrap().copy_from(&[1u8, 2, 3, 4]);
+        let r2 = MmapRegion::from_file(f_off, size).unwrap();
+        let mut buf = [0u8; 4];
+        r2.get_slice(0x1000, 4).unwrap().copy_to(&mut buf[..]);
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        // Unsealed memfds can be resized.
+        r.file_offset().unwrap().file().set_len(0x3000).unwrap();
+
+        let f_off = FileOffset::new(TempFile::new().unwrap().into_file(), 0);
+        let r = MmapRegionBuilder::<()>::new(size)
+            .with_memfd(MemfdOptions::new())
+            .with_file_offset(f_off)
+            .build();
+        assert_eq!(format!("{:?}", r.unwrap_err()), "MemfdWithFileOffset");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mmap_region_memfd_seals() {
+        let size = 0x2000;
+        let r = MmapRegionBuilder::<()>::new(size)
+            .with_mmap_prot(libc::PROT_READ | libc::PROT_WRITE)
+            .with_memfd(
+                MemfdOptions::new()
+                    .with_seal_shrink(true)
+                    .with_seal_grow(true),
+            )
+            .build()
+            .unwrap();
+
+        let file = r.file_offset().unwrap().file();
+        let seals = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_GET_SEALS) };
+        assert_eq!(seals, libc::F_SEAL_SHRINK | libc::F_SEAL_GROW);
+        assert!(file.set_len(0x1000).is_err());
+        assert!(file.set_len(0x3000).is_err());
+        assert_eq!(file.metadata().unwrap().len(), size as u64);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_memfd_options_flags() {
+        let opts = MemfdOptions::new();
+        assert_eq!(opts.memfd_flags().unwrap(), libc::MFD_CLOEXEC);
+        assert_eq!(opts.seals(), 0);
+
+        let opts = MemfdOptions::new().with_seal_grow(true);
+        assert_eq!(
+            opts.memfd_flags().unwrap(),
+            libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING
+        );
+        assert_eq!(opts.seals(), libc::F_SEAL_GROW);
+
+        let opts = MemfdOptions::new().with_hugetlb(None);
+        assert_eq!(
+            opts.memfd_flags().unwrap(),
+            libc::MFD_CLOEXEC | libc::MFD_HUGETLB
+        );
+
+        let opts = MemfdOptions::new().with_hugetlb(Some(0x20_0000));
+        assert_eq!(
+            opts.memfd_flags().unwrap(),
+            libc::MFD_CLOEXEC | libc::MFD_HUGETLB | libc::MFD_HUGE_2MB
+        );
+
+        let opts = MemfdOptions::new().with_hugetlb(Some(0x4000_0000));
+        assert_eq!(
+            opts.memfd_flags().unwrap(),
+            libc::MFD_CLOEXEC | libc::MFD_HUGETLB | libc::MFD_HUGE_1GB
+        );
+
+        let opts = MemfdOptions::new().with_hugetlb(Some(0x30_0000));
+        assert_eq!(
+            format!("{:?}", opts.memfd_flags().unwrap_err()),
+            "InvalidHugePageSize(3145728)"
+        );
+    }
+
+    // Returns the permissions of the mapping that contains `addr`, as listed in
+    // `/proc/self/maps`.
+    #[cfg(target_os = "linux")]
+    fn mapping_perms(addr: usize) -> Option<String> {
+        std::fs::read_to_string("/proc/self/maps")
+            .unwrap()
+            .lines()
+            .find_map(|line| {
+                let mut fields = line.split_whitespace();
+                let (start, end) = fields.next()?.split_once('-')?;
+                let start = usize::from_str_radix(start, 16).ok()?;
+                let end = usize::from_str_radix(end, 16).ok()?;
+                if (start..end).contains(&addr) {
+                    fields.next().map(str::to_owned)
+                } else {
+                    None
+                }
+            })
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mmap_region_guard_pages() {
+        let page_size = page_size();
+        let size = 3 * page_size;
+
+        let r = MmapRegionBuilder::<()>::new(size)
+            .with_mmap_prot(libc::PROT_READ | libc::PROT_WRITE)
+            .with_mmap_flags(libc::MAP_ANONYMOUS | libc::MAP_NORESERVE | libc::MAP_PRIVATE)
+            .with_guard_pages(2)
+            .build()
+            .unwrap();
+        assert_eq!(r.size(), size);
+        assert_eq!(r.guard_size(), 2 * page_size);
+
+        let start = r.as_ptr() as usize;
+        let end = start + size;
+        assert_eq!(mapping_perms(start - 2 * page_size).unwrap(), "---p");
+        assert_eq!(mapping_perms(start - 1).unwrap(), "---p");
+        assert_eq!(mapping_perms(start).unwrap(), "rw-p");
+        assert_eq!(mapping_perms(end - 1).unwrap(), "rw-p");
+        assert_eq!(mapping_perms(end).unwrap(), "---p");
+        assert_eq!(mapping_perms(end + 2 * page_size - 1).unwrap(), "---p");
+        r.get_slice(size - 4, 4).unwrap().copy_from(&[1u8, 2, 3, 4]);
+
+        // File backed regions can be guarded as well.
+        let f = TempFile::new().unwrap().into_file();
+        f.set_len(size as u64).unwrap();
+        let r = MmapRegionBuilder::<()>::new(size)
+            .with_mmap_prot(libc::PROT_READ | libc::PROT_WRITE)
+            .with_mmap_flags(libc::MAP_NORESERVE | libc::MAP_SHARED)
+            .with_file_offset(FileOffset::new(f, 0))
+            .with_guard_pages(1)
+            .build()
+            .unwrap();
+        let start = r.as_ptr() as usize;
+        assert_eq!(mapping_perms(start - 1).unwrap(), "---p");
+        assert_eq!(mapping_perms(start).unwrap(), "rw-s");
+        assert_eq!(mapping_perms(start + size).unwrap(), "---p");
+
+        let r = MmapRegionBuilder::<()>::new(size)
+            .with_guard_pages(usize::MAX)
+            .build();
+        assert_eq!(format!("{:?}", r.unwrap_err()), "InvalidGuardPages");
+
+        let r = MmapRegion::new(size).unwrap();
+        assert_eq!(r.guard_size(), 0);
+    }
+
+    // Returns the `VmFlags` of the mapping that contains `addr`, as listed in
+    // `/proc/self/smaps`.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn mapping_vm_flags(addr: usize) -> Vec<String> {
+        let smaps = std::fs::read_to_string("/proc/self/smaps").unwrap();
+        let mut found = false;
+        for line in smaps.lines() {
+            let first = line.split_whitespace().next().unwrap_or("");
+            if let Some((start, end)) = first.split_once('-') {
+                if let (Ok(start), Ok(end)) = (
+                    usize::from_str_radix(start, 16),
+                    usize::from_str_radix(end, 16),
+                ) {
+                    found = (start..end).contains(&addr);
+                    continue;
+                }
+            }
+            if found {
+                if let Some(flags) = line.strip_prefix("VmFlags:") {
+                    return flags.split_whitespace().map(str::to_owned).collect();
+                }
+            }
+        }
+        Vec::new()
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mmap_region_advise() {
+        let page_size = page_size();
+        let size = 4 * page_size;
+        let r = MmapRegion::new(size).unwrap();
+        assert_eq!(r.page_size(), page_size);
+
+        assert_eq!(r.page_aligned_range(1, 1).unwrap(), (0, page_size));
+        assert_eq!(
+            r.page_aligned_range(page_size - 1, 2).unwrap(),
+            (0, 2 * page_size)
+        );
+        assert_eq!(r.page_aligned_range(size, 0).unwrap(), (size, 0));
+
+        r.advise(page_size + 1, page_size, MemoryAdvice::DontDump)
+            .unwrap();
+        let addr = r.as_ptr() as usize;
+        assert!(!mapping_vm_flags(addr).contains(&"dd".to_owned()));
+        assert!(mapping_vm_flags(addr + page_size).contains(&"dd".to_owned()));
+        assert!(mapping_vm_flags(addr + 2 * page_size).contains(&"dd".to_owned()));
+        assert!(!mapping_vm_flags(addr + 3 * page_size).contains(&"dd".to_owned()));
+
+        r.advise(0, size, MemoryAdvice::DoDump).unwrap();
+        assert!(!mapping_vm_flags(addr + page_size).contains(&"dd".to_owned()));
+
+        r.advise(0, size, MemoryAdvice::WillNeed).unwrap();
+        r.advise(0, size, MemoryAdvice::DontFork).unwrap();
+        r.advise(0, size, MemoryAdvice::DoFork).unwrap();
+
+        match r.advise(size - 1, 2, MemoryAdvice::WillNeed).unwrap_err() {
+            Error::InvalidRange { offset, len } => {
+                assert_eq!(offset, size - 1);
+                assert_eq!(len, 2);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+        assert!(r.advise(usize::MAX, 2, MemoryAdvice::WillNeed).is_err());
+    }
+
+    // Fills the region with `0xff`, discards the range from the middle of the first page to
+    // the middle of the third page, and checks the contents of the region afterwards.
+    #[cfg(target_os = "linux")]
+    fn check_discard<B: Bitmap>(r: &super::MmapRegion<B>) {
+        let page_size = page_size();
+        let size = r.size();
+        let slice = r.get_slice(0, size).unwrap();
+        slice.copy_from(&vec![0xffu8; size]);
+
+        let start = page_size / 2;
+        let end = 2 * page_size + page_size / 2;
+        r.discard(start, end - start).unwrap();
+
+        let mut buf = vec![0u8; size];
+        slice.copy_to(&mut buf[..]);
+        assert!(buf[..start].iter().all(|&b| b == 0xff));
+        assert!(buf[start..end].iter().all(|&b| b == 0));
+        assert!(buf[end..].iter().all(|&b| b == 0xff));
+
+        // Discarding up to the end of the region takes care of the last page as well.
+        r.discard(end, size - end).unwrap();
+        slice.copy_to(&mut buf[..]);
+        assert!(buf[end..].iter().all(|&b| b == 0));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mmap_region_discard() {
+        let page_size = page_size();
+        let size = 4 * page_size;
+
+        // Anonymous private mapping.
+        let r = MmapRegion::new(size).unwrap();
+        check_discard(&r);
+
+        // Anonymous shared mapping.
+        let r = MmapRegion::build(
+            None,
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_ANONYMOUS | libc::MAP_SHARED,
+        )
+        .unwrap();
+        check_discard(&r);
+
+        // File backed shared mapping, at an offset into the file.
+        let f = Arc::new(TempFile::new().unwrap().into_file());
+        f.set_len((size + page_size) as u64).unwrap();
+        let r =
+            MmapRegion::from_file(FileOffset::from_arc(f.clone(), page_size as u64), size).unwrap();
+        check_discard(&r);
+        assert_eq!(f.metadata().unwrap().len(), (size + page_size) as u64);
+
+        // File backed private mapping, whose discarded pages would read back the file contents.
+        // The mapping is left untouched.
+        let tmp = TempFile::new().unwrap();
+        tmp.as_file().write_all(&vec![0xaau8; size]).unwrap();
+        let r = MmapRegion::build(
+            Some(FileOffset::new(File::open(tmp.as_path()).unwrap(), 0)),
+            size,
+            libc::PROT_READ | libc::PROT_WRITE,
+            libc::MAP_PRIVATE,
+        )
+        .unwrap();
+        r.get_slice(0, size).unwrap().copy_from(&vec![0xffu8; size]);
+        assert!(matches!(
+            r.discard(page_size / 2, page_size).unwrap_err(),
+            Error::DiscardPrivateFileMapping
+        ));
+        let mut buf = vec![0u8; size];
+        r.get_slice(0, size).unwrap().copy_to(&mut buf[..]);
+        assert_eq!(buf, vec![0xffu8; size]);
+
+        // Mapping without write access, which can't be zeroed.
+        let r = MmapRegion::build(
+            None,
+            size,
+            libc::PROT_READ,
+            libc::MAP_ANONYMOUS | libc::MAP_PRIVATE,
+        )
+        .unwrap();
+        assert!(matches!(
+            r.discard(page_size / 2, page_size).unwrap_err(),
+            Error::DiscardReadOnlyMapping
+        ));
+
+        // Memfd backed mapping.
+        let r = MmapRegionBuilder::<()>::new(size)
+            .with_mmap_prot(libc::PROT_READ | libc::PROT_WRITE)
+            .with_memfd(MemfdOptions::new())
+            .build()
+            .unwrap();
+        check_discard(&r);
+
+        match r.discard(size - 1, 2).unwrap_err() {
+            Error::InvalidRange { offset, len } => {
+                assert_eq!(offset, size - 1);
+                assert_eq!(len, 2);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mmap_region_discard_dirty_tracking() {
+        let r = crate::MmapRegion::<AtomicBitmap>::new(0x4000).unwrap();
+        r.discard(0x1800, 0x1000).unwrap();
+
+        assert!(!r.bitmap().is_addr_set(0x0));
+        assert!(r.bitmap().is_addr_set(0x1000));
+        assert!(r.bitmap().is_addr_set(0x2000));
+        assert!(!r.bitmap().is_addr_set(0x3000));
+    }
+
+    // Returns whether each page of the `len` bytes at `addr` is resident in memory.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn resident_pages(addr: *mut u8, len: usize) -> Vec<bool> {
+        let page_size = page_size();
+        let mut vec = vec![0u8; len.div_ceil(page_size)];
+        assert_eq!(
+            unsafe { libc::mincore(addr as *mut libc::c_void, len, vec.as_mut_ptr()) },
+            0
+        );
+        vec.iter().map(|v| v & 1 != 0).collect()
+    }
+
+    // Returns whether the kernel supports `MADV_POPULATE_WRITE`. Without it, populating falls
+    // back to touching the pages, and failures raise `SIGBUS` instead of returning an error.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn populate_write_supported() -> bool {
+        let r = MmapRegion::new(page_size()).unwrap();
+        unsafe {
+            libc::madvise(
+                r.as_ptr() as *mut libc::c_void,
+                page_size(),
+                libc::MADV_POPULATE_WRITE,
+            ) == 0
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_mmap_region_populate() {
+        let page_size = page_size();
+        let size = 16 * page_size;
+
+        let r = MmapRegion::new(size).unwrap();
+        assert!(resident_pages(r.as_ptr(), size).iter().all(|&p| !p));
+        r.populate(page_size + 1, 2 * page_size).unwrap();
+        let resident = resident_pages(r.as_ptr(), size);
+        assert_eq!(&resident[..4], &[false, true, true, true]);
+        assert!(resident[4..].iter().all(|&p| !p));
+
+        // Populating must not modify the contents of the memory.
+        r.get_slice(0, 4).unwrap().copy_from(&[1u8, 2, 3, 4]);
+        r.populate(0, size).unwrap();
+        assert!(resident_pages(r.as_ptr(), size).iter().all(|&p| p));
+        let mut buf = [0u8; 4];
+        r.get_slice(0, 4).unwrap().copy_to(&mut buf[..]);
+        assert_eq!(buf, [1, 2, 3, 4]);
+
+        let r = MmapRegionBuilder::<()>::new(size)
+            .with_mmap_prot(libc::PROT_READ | libc::PROT_WRITE)
+            .with_mmap_flags(libc::MAP_ANONYMOUS | libc::MAP_PRIVATE)
+            .with_populate(true)
+            .build()
+            .unwrap();
+        assert_ne!(r.flags() & libc::MAP_POPULATE, 0);
+        assert!(resident_pages(r.as_ptr(), size).iter().all(|&p| p));
+
+        assert!(matches!(
+            r.populate(size, 1).unwrap_err(),
+            Error::InvalidRange { .. }
+        ));
+
+        // Pages past the end of the backing file can't be populated. This is only reported as
+        // an error by `MADV_POPULATE_WRITE`, the fallback would kill the test with `SIGBUS`.
+        if !populate_write_supported() {
+            return;
+        }
+        let f = Arc::new(TempFile::new().unwrap().into_file());
+        f.set_len(size as u64).unwrap();
+        let r = MmapRegion::from_file(FileOffset::from_arc(f.clone(), 0), size).unwrap();
+        f.set_len((size / 2) as u64).unwrap();
+        r.populate(0, size / 2).unwrap();
+        match r.populate(0, size).unwrap_err() {
+            Error::Populate { offset, len, .. } => {
+                assert_eq!(offset, 0);
+                assert_eq!(len, size);
+            }
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
     #[test]
     fn test_mmap_region_build_raw() {
         let addr = 0;
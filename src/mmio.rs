@@ -0,0 +1,920 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Guest memory regions backed by device emulation code.
+//!
+//! An [`MmioRegion`](struct.MmioRegion.html) covers a range of guest physical addresses whose
+//! accesses are forwarded to an [`MmioHandler`](trait.MmioHandler.html), instead of being
+//! backed by host memory. Such regions can be combined with mmap'ed guest RAM in a single
+//! address space with [`GuestMemoryHybrid`](struct.GuestMemoryHybrid.html).
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::mem::size_of;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use crate::address::Address;
+use crate::bitmap::Bitmap;
+use crate::guest_memory::{
+    self, GuestAddress, GuestMemoryRegion, GuestUsize, MemoryRegionAddress, MAX_ACCESS_CHUNK,
+};
+use crate::io::{ReadVolatile, WriteVolatile};
+use crate::volatile_memory::VolatileSlice;
+use crate::{AtomicAccess, Bytes};
+
+#[cfg(feature = "backend-mmap")]
+use crate::bitmap::BS;
+#[cfg(feature = "backend-mmap")]
+use crate::guest_memory::{FileOffset, GuestMemory, GuestMemoryIterator};
+#[cfg(feature = "backend-mmap")]
+use crate::mmap::{check_regions, Error, GuestRegionMmap};
+
+/// Device emulation code handling the accesses to an [`MmioRegion`](struct.MmioRegion.html).
+pub trait MmioHandler: Send + Sync {
+    /// Handles a read of `data.len()` bytes at `offset` within the region.
+    fn read(&self, offset: u64, data: &mut [u8]);
+
+    /// Handles a write of `data` at `offset` within the region.
+    fn write(&self, offset: u64, data: &[u8]);
+}
+
+/// [`GuestMemoryRegion`](trait.GuestMemoryRegion.html) implementation which forwards all the
+/// accesses to an [`MmioHandler`](trait.MmioHandler.html).
+///
+/// Accesses through the `Bytes` interface are passed to the handler in a single call per region
+/// (e.g. `write_obj` results in one `MmioHandler::write` call with the bytes of the object),
+/// except for the accesses to `Read`/`Write` objects, which are split into calls of at most
+/// 4096 bytes. The region is not backed by host memory, so `get_slice` and `get_host_address`
+/// return `GuestMemoryError::HostAddressNotAvailable`. The bitmap of the region is never dirtied.
+pub struct MmioRegion<B = ()> {
+    guest_base: GuestAddress,
+    len: GuestUsize,
+    handler: Arc<dyn MmioHandler>,
+    bitmap: B,
+}
+
+impl<B: Bitmap + Default> MmioRegion<B> {
+    /// Create a new region of `len` bytes at `guest_base`, whose accesses are handled by
+    /// `handler`.
+    ///
+    /// Returns `GuestMemoryError::InvalidGuestAddress` if the region is empty or extends past
+    /// the end of the guest address space.
+    pub fn new(
+        guest_base: GuestAddress,
+        len: GuestUsize,
+        handler: Arc<dyn MmioHandler>,
+    ) -> guest_memory::Result<Self> {
+        if len == 0 || guest_base.checked_add(len - 1).is_none() {
+            return Err(guest_memory::Error::InvalidGuestAddress(guest_base));
+        }
+
+        Ok(MmioRegion {
+            guest_base,
+            len,
+            handler,
+            bitmap: B::default(),
+        })
+    }
+}
+
+impl<B> MmioRegion<B> {
+    /// Returns the handler of the region.
+    pub fn handler(&self) -> &Arc<dyn MmioHandler> {
+        &self.handler
+    }
+
+    // Returns the number of bytes of an access of `count` bytes at `addr` that fall within the
+    // region, failing if `addr` is not within the region.
+    fn access_len(&self, addr: MemoryRegionAddress, count: usize) -> guest_memory::Result<usize> {
+        if count == 0 {
+            return Ok(0);
+        }
+        match self.len.checked_sub(addr.raw_value()) {
+            Some(available) if available > 0 => {
+                Ok(std::cmp::min(available, count as GuestUsize) as usize)
+            }
+            _ => Err(guest_memory::Error::InvalidBackendAddress),
+        }
+    }
+
+    // Checks that the access of `count` bytes at `addr` is completely contained within the
+    // region.
+    fn check_access(&self, addr: MemoryRegionAddress, count: usize) -> guest_memory::Result<()> {
+        match self.len.checked_sub(addr.raw_value()) {
+            Some(available) if available >= count as GuestUsize => Ok(()),
+            _ => Err(guest_memory::Error::InvalidBackendAddress),
+        }
+    }
+
+    // Checks that the `count` bytes at `addr` are within the region, and calls `f` for each
+    // chunk of at most `MAX_ACCESS_CHUNK` bytes of the range, with the address of the chunk and
+    // a scratch buffer of the size of the chunk.
+    fn for_each_chunk<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        count: usize,
+        mut f: F,
+    ) -> guest_memory::Result<()>
+    where
+        F: FnMut(MemoryRegionAddress, &mut [u8]) -> guest_memory::Result<()>,
+    {
+        self.check_access(addr, count)?;
+        let mut buf = [0u8; MAX_ACCESS_CHUNK];
+        let mut done = 0;
+        while done < count {
+            let len = std::cmp::min(count - done, buf.len());
+            f(addr.unchecked_add(done as GuestUsize), &mut buf[..len])?;
+            done += len;
+        }
+        Ok(())
+    }
+}
+
+impl<B> fmt::Debug for MmioRegion<B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MmioRegion")
+            .field("guest_base", &self.guest_base)
+            .field("len", &self.len)
+            .finish()
+    }
+}
+
+impl<B: Bitmap> Bytes<MemoryRegionAddress> for MmioRegion<B> {
+    type E = guest_memory::Error;
+
+    fn write(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
+        let len = self.access_len(addr, buf.len())?;
+        if len > 0 {
+            self.handler.write(addr.raw_value(), &buf[..len]);
+        }
+        Ok(len)
+    }
+
+    fn read(&self, buf: &mut [u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
+        let len = self.access_len(addr, buf.len())?;
+        if len > 0 {
+            self.handler.read(addr.raw_value(), &mut buf[..len]);
+        }
+        Ok(len)
+    }
+
+    fn write_slice(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
+        let len = self.write(buf, addr)?;
+        if len != buf.len() {
+            return Err(guest_memory::Error::PartialBuffer {
+                expected: buf.len(),
+                completed: len,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_slice(&self, buf: &mut [u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
+        let len = self.read(buf, addr)?;
+        if len != buf.len() {
+            return Err(guest_memory::Error::PartialBuffer {
+                expected: buf.len(),
+                completed: len,
+            });
+        }
+        Ok(())
+    }
+
+    fn read_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: Read,
+    {
+        self.check_access(addr, count)?;
+        let mut buf = [0u8; MAX_ACCESS_CHUNK];
+        let buf = &mut buf[..std::cmp::min(count, MAX_ACCESS_CHUNK)];
+        let bytes_read = loop {
+            match src.read(buf) {
+                Ok(n) => break n,
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(guest_memory::Error::IOError(e)),
+            }
+        };
+        self.write(&buf[..bytes_read], addr)
+    }
+
+    fn read_exact_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: Read,
+    {
+        self.for_each_chunk(addr, count, |addr, buf| {
+            src.read_exact(buf).map_err(guest_memory::Error::IOError)?;
+            self.write_slice(buf, addr)
+        })
+    }
+
+    fn write_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: Write,
+    {
+        // Reading could have side effects, so all the data read has to be written out.
+        self.write_all_to(addr, dst, count).map(|_| count)
+    }
+
+    fn write_all_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: Write,
+    {
+        self.for_each_chunk(addr, count, |addr, buf| {
+            self.read_slice(buf, addr)?;
+            dst.write_all(buf).map_err(guest_memory::Error::IOError)
+        })
+    }
+
+    fn read_volatile_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: ReadVolatile,
+    {
+        self.check_access(addr, count)?;
+        let mut buf = [0u8; MAX_ACCESS_CHUNK];
+        let buf = &mut buf[..std::cmp::min(count, MAX_ACCESS_CHUNK)];
+        let bytes_read = loop {
+            match src.read_volatile(&mut VolatileSlice::from(&mut buf[..])) {
+                Err(crate::VolatileMemoryError::IOError(ref e))
+                    if e.kind() == io::ErrorKind::Interrupted =>
+                {
+                    continue
+                }
+                result => break result?,
+            }
+        };
+        self.write(&buf[..bytes_read], addr)
+    }
+
+    fn read_exact_volatile_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: ReadVolatile,
+    {
+        self.for_each_chunk(addr, count, |addr, buf| {
+            src.read_exact_volatile(&mut VolatileSlice::from(&mut *buf))?;
+            self.write_slice(buf, addr)
+        })
+    }
+
+    fn write_volatile_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: WriteVolatile,
+    {
+        // Reading could have side effects, so all the data read has to be written out.
+        self.write_all_volatile_to(addr, dst, count).map(|_| count)
+    }
+
+    fn write_all_volatile_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: WriteVolatile,
+    {
+        self.for_each_chunk(addr, count, |addr, buf| {
+            self.read_slice(buf, addr)?;
+            dst.write_all_volatile(&VolatileSlice::from(buf))
+                .map_err(Into::into)
+        })
+    }
+
+    /// Writes `val` with a single call to the handler. The ordering is irrelevant, as there is
+    /// no memory backing the access.
+    fn store<T: AtomicAccess>(
+        &self,
+        val: T,
+        addr: MemoryRegionAddress,
+        _order: Ordering,
+    ) -> guest_memory::Result<()> {
+        self.check_access(addr, size_of::<T>())?;
+        self.handler.write(addr.raw_value(), val.as_slice());
+        Ok(())
+    }
+
+    /// Reads a value with a single call to the handler. The ordering is irrelevant, as there is
+    /// no memory backing the access.
+    fn load<T: AtomicAccess>(
+        &self,
+        addr: MemoryRegionAddress,
+        _order: Ordering,
+    ) -> guest_memory::Result<T> {
+        self.check_access(addr, size_of::<T>())?;
+        let mut val = T::zeroed();
+        self.handler.read(addr.raw_value(), val.as_mut_slice());
+        Ok(val)
+    }
+}
+
+impl<B: Bitmap> GuestMemoryRegion for MmioRegion<B> {
+    type B = B;
+
+    fn len(&self) -> GuestUsize {
+        self.len
+    }
+
+    fn start_addr(&self) -> GuestAddress {
+        self.guest_base
+    }
+
+    fn bitmap(&self) -> &Self::B {
+        &self.bitmap
+    }
+}
+
+/// Region of a [`GuestMemoryHybrid`](struct.GuestMemoryHybrid.html), which is either guest
+/// RAM mapped in the current process or an MMIO region.
+#[cfg(feature = "backend-mmap")]
+#[derive(Debug)]
+pub enum GuestRegionHybrid<B = ()> {
+    /// Guest RAM mapped in the current process.
+    Mmap(GuestRegionMmap<B>),
+    /// Guest addresses whose accesses are handled by device emulation code.
+    Mmio(MmioRegion<B>),
+}
+
+#[cfg(feature = "backend-mmap")]
+impl<B> From<GuestRegionMmap<B>> for GuestRegionHybrid<B> {
+    fn from(region: GuestRegionMmap<B>) -> Self {
+        GuestRegionHybrid::Mmap(region)
+    }
+}
+
+#[cfg(feature = "backend-mmap")]
+impl<B> From<MmioRegion<B>> for GuestRegionHybrid<B> {
+    fn from(region: MmioRegion<B>) -> Self {
+        GuestRegionHybrid::Mmio(region)
+    }
+}
+
+// Forwards a method call to the region wrapped by a `GuestRegionHybrid`.
+#[cfg(feature = "backend-mmap")]
+macro_rules! dispatch {
+    ($self:ident.$method:ident($($arg:expr),*)) => {
+        match $self {
+            GuestRegionHybrid::Mmap(region) => region.$method($($arg),*),
+            GuestRegionHybrid::Mmio(region) => region.$method($($arg),*),
+        }
+    };
+}
+
+#[cfg(feature = "backend-mmap")]
+impl<B: Bitmap> Bytes<MemoryRegionAddress> for GuestRegionHybrid<B> {
+    type E = guest_memory::Error;
+
+    fn write(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
+        dispatch!(self.write(buf, addr))
+    }
+
+    fn read(&self, buf: &mut [u8], addr: MemoryRegionAddress) -> guest_memory::Result<usize> {
+        dispatch!(self.read(buf, addr))
+    }
+
+    fn write_slice(&self, buf: &[u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
+        dispatch!(self.write_slice(buf, addr))
+    }
+
+    fn read_slice(&self, buf: &mut [u8], addr: MemoryRegionAddress) -> guest_memory::Result<()> {
+        dispatch!(self.read_slice(buf, addr))
+    }
+
+    fn read_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: Read,
+    {
+        dispatch!(self.read_from(addr, src, count))
+    }
+
+    fn read_exact_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: Read,
+    {
+        dispatch!(self.read_exact_from(addr, src, count))
+    }
+
+    fn write_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: Write,
+    {
+        dispatch!(self.write_to(addr, dst, count))
+    }
+
+    fn write_all_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: Write,
+    {
+        dispatch!(self.write_all_to(addr, dst, count))
+    }
+
+    fn read_volatile_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: ReadVolatile,
+    {
+        dispatch!(self.read_volatile_from(addr, src, count))
+    }
+
+    fn read_exact_volatile_from<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        src: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: ReadVolatile,
+    {
+        dispatch!(self.read_exact_volatile_from(addr, src, count))
+    }
+
+    fn write_volatile_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<usize>
+    where
+        F: WriteVolatile,
+    {
+        dispatch!(self.write_volatile_to(addr, dst, count))
+    }
+
+    fn write_all_volatile_to<F>(
+        &self,
+        addr: MemoryRegionAddress,
+        dst: &mut F,
+        count: usize,
+    ) -> guest_memory::Result<()>
+    where
+        F: WriteVolatile,
+    {
+        dispatch!(self.write_all_volatile_to(addr, dst, count))
+    }
+
+    fn store<T: AtomicAccess>(
+        &self,
+        val: T,
+        addr: MemoryRegionAddress,
+        order: Ordering,
+    ) -> guest_memory::Result<()> {
+        dispatch!(self.store(val, addr, order))
+    }
+
+    fn load<T: AtomicAccess>(
+        &self,
+        addr: MemoryRegionAddress,
+        order: Ordering,
+    ) -> guest_memory::Result<T> {
+        dispatch!(self.load(addr, order))
+    }
+}
+
+#[cfg(feature = "backend-mmap")]
+impl<B: Bitmap> GuestMemoryRegion for GuestRegionHybrid<B> {
+    type B = B;
+
+    fn len(&self) -> GuestUsize {
+        dispatch!(self.len())
+    }
+
+    fn start_addr(&self) -> GuestAddress {
+        dispatch!(self.start_addr())
+    }
+
+    fn bitmap(&self) -> &Self::B {
+        dispatch!(self.bitmap())
+    }
+
+    fn get_host_address(&self, addr: MemoryRegionAddress) -> guest_memory::Result<*mut u8> {
+        dispatch!(self.get_host_address(addr))
+    }
+
+    fn file_offset(&self) -> Option<&FileOffset> {
+        dispatch!(self.file_offset())
+    }
+
+    fn get_slice(
+        &self,
+        offset: MemoryRegionAddress,
+        count: usize,
+    ) -> guest_memory::Result<VolatileSlice<'_, BS<'_, B>>> {
+        dispatch!(self.get_slice(offset, count))
+    }
+
+    #[cfg(target_os = "linux")]
+    fn is_hugetlbfs(&self) -> Option<bool> {
+        dispatch!(self.is_hugetlbfs())
+    }
+}
+
+/// [`GuestMemory`](trait.GuestMemory.html) implementation made of both guest RAM mapped in the
+/// current process and MMIO regions.
+///
+/// # Examples
+///
+/// ```
+/// # use std::sync::atomic::{AtomicU32, Ordering};
+/// # use std::sync::Arc;
+/// # use vm_memory::mmio::{GuestMemoryHybrid, MmioHandler, MmioRegion};
+/// # use vm_memory::{Bytes, GuestAddress, GuestMemory, GuestMemoryError, GuestRegionMmap};
+/// #
+/// #[derive(Default)]
+/// struct Register(AtomicU32);
+///
+/// impl MmioHandler for Register {
+///     fn read(&self, _offset: u64, data: &mut [u8]) {
+///         data.copy_from_slice(&self.0.load(Ordering::SeqCst).to_le_bytes()[..data.len()]);
+///     }
+///
+///     fn write(&self, _offset: u64, data: &[u8]) {
+///         let mut bytes = [0; 4];
+///         bytes[..data.len()].copy_from_slice(data);
+///         self.0.store(u32::from_le_bytes(bytes), Ordering::SeqCst);
+///     }
+/// }
+///
+/// let ram = GuestRegionMmap::<()>::from_range(GuestAddress(0), 0x10000, None)
+///     .expect("Could not create RAM region");
+/// let register = MmioRegion::new(GuestAddress(0x10000), 4, Arc::new(Register::default()))
+///     .expect("Could not create MMIO region");
+/// let gm = GuestMemoryHybrid::from_regions(vec![ram.into(), register.into()])
+///     .expect("Could not create guest memory");
+///
+/// gm.write_obj(0x1234u32, GuestAddress(0x10000)).unwrap();
+/// assert_eq!(gm.read_obj::<u32>(GuestAddress(0x10000)).unwrap(), 0x1234);
+/// assert!(matches!(
+///     gm.get_slice(GuestAddress(0x10000), 4),
+///     Err(GuestMemoryError::HostAddressNotAvailable)
+/// ));
+/// assert!(gm.get_slice(GuestAddress(0x1000), 4).is_ok());
+/// ```
+#[cfg(feature = "backend-mmap")]
+#[derive(Clone, Debug, Default)]
+pub struct GuestMemoryHybrid<B = ()> {
+    regions: Vec<Arc<GuestRegionHybrid<B>>>,
+}
+
+#[cfg(feature = "backend-mmap")]
+impl<B: Bitmap> GuestMemoryHybrid<B> {
+    /// Creates a new `GuestMemoryHybrid` from a vector of regions, which shouldn't overlap and
+    /// should be sorted by their starting address.
+    pub fn from_regions(regions: Vec<GuestRegionHybrid<B>>) -> Result<Self, Error> {
+        Self::from_arc_regions(regions.into_iter().map(Arc::new).collect())
+    }
+
+    /// Creates a new `GuestMemoryHybrid` from a vector of `Arc` regions, which shouldn't overlap
+    /// and should be sorted by their starting address.
+    pub fn from_arc_regions(regions: Vec<Arc<GuestRegionHybrid<B>>>) -> Result<Self, Error> {
+        check_regions(&regions)?;
+        Ok(Self { regions })
+    }
+}
+
+/// An iterator over the elements of `GuestMemoryHybrid`.
+///
+/// This struct is created by `GuestMemory::iter()`. See its documentation for more.
+#[cfg(feature = "backend-mmap")]
+pub struct Iter<'a, B>(std::slice::Iter<'a, Arc<GuestRegionHybrid<B>>>);
+
+#[cfg(feature = "backend-mmap")]
+impl<'a, B> Iterator for Iter<'a, B> {
+    type Item = &'a GuestRegionHybrid<B>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(AsRef::as_ref)
+    }
+}
+
+#[cfg(feature = "backend-mmap")]
+impl<'a, B: 'a> GuestMemoryIterator<'a, GuestRegionHybrid<B>> for GuestMemoryHybrid<B> {
+    type Iter = Iter<'a, B>;
+}
+
+#[cfg(feature = "backend-mmap")]
+impl<B: Bitmap + 'static> GuestMemory for GuestMemoryHybrid<B> {
+    type R = GuestRegionHybrid<B>;
+
+    type I = Self;
+
+    fn num_regions(&self) -> usize {
+        self.regions.len()
+    }
+
+    fn find_region(&self, addr: GuestAddress) -> Option<&GuestRegionHybrid<B>> {
+        guest_memory::find_sorted_region(&self.regions, addr)
+    }
+
+    fn iter(&self) -> Iter<'_, B> {
+        Iter(self.regions.iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use matches::assert_matches;
+
+    // Device recording the accesses it handles, and returning the low byte of the offset for
+    // every byte read.
+    #[derive(Debug, Default)]
+    struct Recorder {
+        accesses: Mutex<Vec<(bool, u64, Vec<u8>)>>,
+    }
+
+    impl Recorder {
+        fn take(&self) -> Vec<(bool, u64, Vec<u8>)> {
+            std::mem::take(&mut *self.accesses.lock().unwrap())
+        }
+    }
+
+    impl MmioHandler for Recorder {
+        fn read(&self, offset: u64, data: &mut [u8]) {
+            for (i, b) in data.iter_mut().enumerate() {
+                *b = (offset + i as u64) as u8;
+            }
+            self.accesses
+                .lock()
+                .unwrap()
+                .push((false, offset, data.to_vec()));
+        }
+
+        fn write(&self, offset: u64, data: &[u8]) {
+            self.accesses
+                .lock()
+                .unwrap()
+                .push((true, offset, data.to_vec()));
+        }
+    }
+
+    #[test]
+    fn test_mmio_region() {
+        let device = Arc::new(Recorder::default());
+        assert!(MmioRegion::<()>::new(GuestAddress(0x1000), 0, device.clone()).is_err());
+        assert!(MmioRegion::<()>::new(GuestAddress(u64::MAX), 2, device.clone()).is_err());
+        let region = MmioRegion::<()>::new(GuestAddress(0x1000), 0x100, device.clone()).unwrap();
+        assert_eq!(region.start_addr(), GuestAddress(0x1000));
+        assert_eq!(region.len(), 0x100);
+
+        region
+            .write_obj(0x0403_0201u32, MemoryRegionAddress(0x10))
+            .unwrap();
+        assert_eq!(
+            region.read_obj::<u16>(MemoryRegionAddress(0x20)).unwrap(),
+            0x2120
+        );
+        region
+            .store(0xffu8, MemoryRegionAddress(0xff), Ordering::Relaxed)
+            .unwrap();
+        assert_eq!(
+            region
+                .load::<u32>(MemoryRegionAddress(0x40), Ordering::Relaxed)
+                .unwrap(),
+            0x4342_4140
+        );
+        assert_eq!(
+            device.take(),
+            [
+                (true, 0x10, vec![1, 2, 3, 4]),
+                (false, 0x20, vec![0x20, 0x21]),
+                (true, 0xff, vec![0xff]),
+                (false, 0x40, vec![0x40, 0x41, 0x42, 0x43]),
+            ]
+        );
+
+        // Accesses are clipped to the region, or rejected when they don't fit.
+        assert_eq!(region.write(&[1; 4], MemoryRegionAddress(0xfe)).unwrap(), 2);
+        assert_matches!(
+            region.write_slice(&[1; 4], MemoryRegionAddress(0xfe)),
+            Err(guest_memory::Error::PartialBuffer {
+                expected: 4,
+                completed: 2
+            })
+        );
+        assert_matches!(
+            region.read(&mut [0; 4], MemoryRegionAddress(0x100)),
+            Err(guest_memory::Error::InvalidBackendAddress)
+        );
+        assert_matches!(
+            region.store(1u32, MemoryRegionAddress(0xfe), Ordering::Relaxed),
+            Err(guest_memory::Error::InvalidBackendAddress)
+        );
+        assert_eq!(region.read(&mut [], MemoryRegionAddress(0x200)).unwrap(), 0);
+        assert_eq!(device.take().len(), 2);
+
+        // Small streams are passed through with a single access.
+        let mut src = &[5u8; 8][..];
+        assert_eq!(
+            region
+                .read_volatile_from(MemoryRegionAddress(0), &mut src, 8)
+                .unwrap(),
+            8
+        );
+        let mut dst = Vec::new();
+        region
+            .write_all_to(MemoryRegionAddress(0x80), &mut dst, 3)
+            .unwrap();
+        assert_eq!(dst, [0x80, 0x81, 0x82]);
+        assert_eq!(
+            device.take(),
+            [(true, 0, vec![5; 8]), (false, 0x80, vec![0x80, 0x81, 0x82])]
+        );
+
+        assert_matches!(
+            region.get_host_address(MemoryRegionAddress(0)),
+            Err(guest_memory::Error::HostAddressNotAvailable)
+        );
+        assert_matches!(
+            region.get_slice(MemoryRegionAddress(0), 1),
+            Err(guest_memory::Error::HostAddressNotAvailable)
+        );
+    }
+
+    #[test]
+    fn test_mmio_region_large_streams() {
+        let device = Arc::new(Recorder::default());
+        let region = MmioRegion::<()>::new(GuestAddress(0), 0x4000, device.clone()).unwrap();
+        let sizes = |accesses: Vec<(bool, u64, Vec<u8>)>| {
+            accesses
+                .iter()
+                .map(|(write, offset, data)| (*write, *offset, data.len()))
+                .collect::<Vec<_>>()
+        };
+
+        // Large streams are split into accesses of at most `MAX_ACCESS_CHUNK` bytes.
+        let mut src = &vec![1u8; 0x2800][..];
+        region
+            .read_exact_from(MemoryRegionAddress(0x800), &mut src, 0x2800)
+            .unwrap();
+        assert!(src.is_empty());
+        assert_eq!(
+            sizes(device.take()),
+            [
+                (true, 0x800, 0x1000),
+                (true, 0x1800, 0x1000),
+                (true, 0x2800, 0x800)
+            ]
+        );
+
+        let mut dst = Vec::new();
+        region
+            .write_all_volatile_to(MemoryRegionAddress(0), &mut dst, 0x1800)
+            .unwrap();
+        assert_eq!(dst.len(), 0x1800);
+        assert_eq!(dst[0x1001], 1);
+        assert_eq!(
+            sizes(device.take()),
+            [(false, 0, 0x1000), (false, 0x1000, 0x800)]
+        );
+
+        // Single reads from the stream are bounded as well.
+        let mut src = &vec![1u8; 0x2000][..];
+        assert_eq!(
+            region
+                .read_from(MemoryRegionAddress(0), &mut src, 0x2000)
+                .unwrap(),
+            0x1000
+        );
+        assert_eq!(sizes(device.take()), [(true, 0, 0x1000)]);
+
+        // Accesses not contained within the region are rejected before reaching the device.
+        assert_matches!(
+            region.read_exact_from(MemoryRegionAddress(0x3000), &mut src, 0x2000),
+            Err(guest_memory::Error::InvalidBackendAddress)
+        );
+        assert!(device.take().is_empty());
+    }
+
+    #[cfg(feature = "backend-mmap")]
+    #[test]
+    fn test_guest_memory_hybrid() {
+        use crate::bitmap::AtomicBitmap;
+
+        let device = Arc::new(Recorder::default());
+        let ram = |addr, len| {
+            GuestRegionMmap::<AtomicBitmap>::from_range(GuestAddress(addr), len, None)
+                .unwrap()
+                .into()
+        };
+        let mmio = MmioRegion::new(GuestAddress(0x1000), 0x100, device.clone()).unwrap();
+        let gm =
+            GuestMemoryHybrid::from_regions(vec![ram(0, 0x1000), mmio.into(), ram(0x2000, 0x1000)])
+                .unwrap();
+        assert_eq!(gm.num_regions(), 3);
+        assert_eq!(gm.last_addr(), GuestAddress(0x2fff));
+
+        // RAM and MMIO accesses go to the right places.
+        gm.write_obj(0xaau8, GuestAddress(0x2000)).unwrap();
+        gm.write_obj(0x0201u16, GuestAddress(0x1004)).unwrap();
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x2000)).unwrap(), 0xaa);
+        assert_eq!(gm.read_obj::<u8>(GuestAddress(0x1080)).unwrap(), 0x80);
+        assert_eq!(
+            device.take(),
+            [(true, 4, vec![1, 2]), (false, 0x80, vec![0x80])]
+        );
+
+        // An access spanning RAM and MMIO is split between them.
+        gm.write_slice(&[7; 8], GuestAddress(0xffc)).unwrap();
+        assert_eq!(device.take(), [(true, 0, vec![7; 4])]);
+        assert_eq!(
+            gm.read_obj::<u32>(GuestAddress(0xffc)).unwrap(),
+            0x0707_0707
+        );
+        assert!(gm
+            .find_region(GuestAddress(0xfff))
+            .unwrap()
+            .bitmap()
+            .dirty_at(0xfff));
+
+        // There's no host memory behind the MMIO region.
+        assert_matches!(
+            gm.get_host_address(GuestAddress(0x1000)),
+            Err(guest_memory::Error::HostAddressNotAvailable)
+        );
+        assert_matches!(
+            gm.get_slice(GuestAddress(0x1000), 4),
+            Err(guest_memory::Error::HostAddressNotAvailable)
+        );
+        assert!(gm.get_host_address(GuestAddress(0x2000)).is_ok());
+        assert!(gm.get_slice(GuestAddress(0x2000), 4).is_ok());
+
+        // Accesses to the hole after the MMIO region fail.
+        assert_matches!(
+            gm.write_slice(&[1; 4], GuestAddress(0x10fe)),
+            Err(guest_memory::Error::PartialBuffer {
+                expected: 4,
+                completed: 2
+            })
+        );
+        assert!(gm.read_obj::<u8>(GuestAddress(0x1100)).is_err());
+
+        assert_matches!(
+            GuestMemoryHybrid::<()>::from_regions(vec![]),
+            Err(Error::NoMemoryRegion)
+        );
+        let overlapping = MmioRegion::new(GuestAddress(0xfff), 2, device).unwrap();
+        assert_matches!(
+            GuestMemoryHybrid::from_regions(vec![ram(0, 0x1000), overlapping.into()]),
+            Err(Error::MemoryRegionOverlap)
+        );
+    }
+}
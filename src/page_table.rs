@@ -0,0 +1,625 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Translation of guest virtual addresses by walking the guest page tables.
+//!
+//! [`translate`](fn.translate.html) walks the page tables stored in guest memory, starting from
+//! the root table (e.g. the table pointed to by `CR3` on x86-64, or by `TTBR0_EL1`/`TTBR1_EL1`
+//! on aarch64), and returns the guest physical address a guest virtual address maps to, along
+//! with the permissions of the mapping.
+//!
+//! The walker only reads the page tables; it never sets the accessed or dirty bits of the
+//! entries. Entries are read as little endian 64-bit values.
+
+use std::fmt;
+
+use crate::endian::Le64;
+use crate::guest_memory::{self, GuestAddress, GuestMemory, GuestUsize};
+use crate::Bytes;
+
+/// Errors associated with walking guest page tables.
+///
+/// Page table levels are numbered as in the documentation of each architecture: from 5 (PML5)
+/// or 4 (PML4) down to 1 (page table) on x86-64, and from the start level up to 3 on aarch64.
+#[allow(missing_docs)]
+#[derive(Debug)]
+pub enum Error {
+    /// The paging mode is not supported.
+    InvalidMode(PagingMode),
+    /// The virtual address is outside of the range translated by the paging mode.
+    NonCanonical(u64),
+    /// The entry of the page table at `level` is not present (or not valid).
+    NotPresent { level: u8, entry_addr: GuestAddress },
+    /// The entry of the page table at `level` is malformed.
+    Reserved { level: u8, entry_addr: GuestAddress },
+    /// Reading the entry of the page table at `level` from guest memory failed.
+    GuestMemory {
+        level: u8,
+        entry_addr: GuestAddress,
+        source: guest_memory::Error,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::InvalidMode(mode) => write!(f, "unsupported paging mode {:?}", mode),
+            Error::NonCanonical(gva) => write!(f, "non-canonical virtual address 0x{:x}", gva),
+            Error::NotPresent { level, entry_addr } => write!(
+                f,
+                "level {} entry at 0x{:x} is not present",
+                level, entry_addr.0
+            ),
+            Error::Reserved { level, entry_addr } => write!(
+                f,
+                "level {} entry at 0x{:x} is malformed",
+                level, entry_addr.0
+            ),
+            Error::GuestMemory {
+                level,
+                entry_addr,
+                source,
+            } => write!(
+                f,
+                "failed to read level {} entry at 0x{:x}: {}",
+                level, entry_addr.0, source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::GuestMemory { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Result of page table walks.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Translation granule of aarch64 page tables.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Granule {
+    /// 4 KiB pages, with 2 MiB and 1 GiB blocks.
+    Size4K,
+    /// 64 KiB pages, with 512 MiB blocks.
+    Size64K,
+}
+
+/// Format of the guest page tables.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PagingMode {
+    /// x86-64 4-level paging, translating 48-bit virtual addresses.
+    X86_64Level4,
+    /// x86-64 5-level paging (`CR4.LA57`), translating 57-bit virtual addresses.
+    X86_64Level5,
+    /// aarch64 translation of `va_bits`-bit virtual addresses (`64 - TnSZ`), from 25
+    /// to 48. Addresses are translated if their upper bits are all zeros or all ones, as the
+    /// caller picks the root table of the matching half of the address space.
+    Aarch64 {
+        /// Translation granule.
+        granule: Granule,
+        /// Size of the virtual addresses.
+        va_bits: u8,
+    },
+}
+
+/// Effective permissions of a translation, combining those of all the levels of the walk.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PagePermissions {
+    /// Whether the page is writable.
+    pub writable: bool,
+    /// Whether the page is accessible by user mode (EL0).
+    pub user: bool,
+    /// Whether the page is executable by supervisor mode (EL1).
+    pub executable: bool,
+    /// Whether the page is executable by user mode (EL0).
+    pub user_executable: bool,
+}
+
+impl Default for PagePermissions {
+    fn default() -> Self {
+        PagePermissions {
+            writable: true,
+            user: true,
+            executable: true,
+            user_executable: true,
+        }
+    }
+}
+
+/// Translation of a guest virtual address.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Translation {
+    /// Guest physical address the virtual address maps to.
+    pub gpa: GuestAddress,
+    /// Size of the page (or block) containing the address.
+    pub page_size: GuestUsize,
+    /// Effective permissions of the page.
+    pub permissions: PagePermissions,
+}
+
+// Physical address bits of x86_64 page table entries.
+const X86_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+const X86_PRESENT: u64 = 1 << 0;
+const X86_WRITABLE: u64 = 1 << 1;
+const X86_USER: u64 = 1 << 2;
+const X86_PAGE_SIZE: u64 = 1 << 7;
+const X86_NX: u64 = 1 << 63;
+
+// Output address bits of AArch64 descriptors (without 52-bit addresses).
+const ARM_ADDR_MASK: u64 = 0x0000_ffff_ffff_f000;
+// Table address bits of the translation table base registers.
+const ARM_ROOT_MASK: u64 = 0x0000_ffff_ffff_fffe;
+const ARM_VALID: u64 = 1 << 0;
+const ARM_TABLE: u64 = 1 << 1;
+const ARM_AP_USER: u64 = 1 << 6;
+const ARM_AP_READ_ONLY: u64 = 1 << 7;
+const ARM_PXN: u64 = 1 << 53;
+const ARM_UXN: u64 = 1 << 54;
+const ARM_PXN_TABLE: u64 = 1 << 59;
+const ARM_UXN_TABLE: u64 = 1 << 60;
+const ARM_AP_TABLE_NO_USER: u64 = 1 << 61;
+const ARM_AP_TABLE_READ_ONLY: u64 = 1 << 62;
+
+fn read_entry<M: GuestMemory + ?Sized>(
+    mem: &M,
+    level: u8,
+    entry_addr: GuestAddress,
+) -> Result<u64> {
+    mem.read_obj::<Le64>(entry_addr)
+        .map(u64::from)
+        .map_err(|source| Error::GuestMemory {
+            level,
+            entry_addr,
+            source,
+        })
+}
+
+// Checks that the bits of `gva` above `va_bits` are all equal to bit `va_bits - 1`, or (if
+// `either_half` is set) all zeros or all ones.
+fn check_canonical(gva: u64, va_bits: u32, either_half: bool) -> Result<()> {
+    let upper = (gva as i64) >> (va_bits - 1);
+    let lower_half = upper == 0 || (either_half && gva >> va_bits == 0);
+    let upper_half = upper == -1 || (either_half && (gva as i64) >> va_bits == -1);
+    if lower_half || upper_half {
+        Ok(())
+    } else {
+        Err(Error::NonCanonical(gva))
+    }
+}
+
+/// Translates the guest virtual address `gva` with the page tables of format `mode` rooted at
+/// `root`.
+///
+/// The flags in the low bits of `root` (e.g. the PCID in `CR3`, or the common-not-private bit
+/// of `TTBRn_EL1`) and the ASID in the upper bits of aarch64 translation table base registers
+/// are ignored, so the value of the register can be passed as is.
+///
+/// # Examples (uses the `backend-mmap` feature)
+///
+/// ```
+/// # #[cfg(feature = "backend-mmap")]
+/// # {
+/// # use vm_memory::page_table::{translate, PagingMode};
+/// # use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+/// #
+/// let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x10000)])
+///     .expect("Could not create guest memory");
+///
+/// // Identity map the first GiB with a 1 GiB page: PML4 at 0x1000, PDPT at 0x2000.
+/// gm.write_obj(0x2003u64.to_le(), GuestAddress(0x1000)).unwrap();
+/// gm.write_obj(0x83u64.to_le(), GuestAddress(0x2000)).unwrap();
+///
+/// let t = translate(&gm, GuestAddress(0x1000), PagingMode::X86_64Level4, 0x1234_5678).unwrap();
+/// assert_eq!(t.gpa, GuestAddress(0x1234_5678));
+/// assert_eq!(t.page_size, 1 << 30);
+/// assert!(t.permissions.writable && !t.permissions.user);
+/// # }
+/// ```
+pub fn translate<M: GuestMemory + ?Sized>(
+    mem: &M,
+    root: GuestAddress,
+    mode: PagingMode,
+    gva: u64,
+) -> Result<Translation> {
+    match mode {
+        PagingMode::X86_64Level4 => walk_x86_64(mem, root, 4, gva),
+        PagingMode::X86_64Level5 => walk_x86_64(mem, root, 5, gva),
+        PagingMode::Aarch64 { granule, va_bits } => {
+            if !(25..=48).contains(&va_bits) {
+                return Err(Error::InvalidMode(mode));
+            }
+            walk_aarch64(mem, root, granule, u32::from(va_bits), gva)
+        }
+    }
+}
+
+fn walk_x86_64<M: GuestMemory + ?Sized>(
+    mem: &M,
+    root: GuestAddress,
+    levels: u8,
+    gva: u64,
+) -> Result<Translation> {
+    check_canonical(gva, 12 + 9 * u32::from(levels), false)?;
+
+    let mut table = root.0 & X86_ADDR_MASK;
+    let mut permissions = PagePermissions::default();
+    for level in (1..=levels).rev() {
+        let shift = 12 + 9 * u32::from(level - 1);
+        let entry_addr = GuestAddress(table + ((gva >> shift) & 0x1ff) * 8);
+        let entry = read_entry(mem, level, entry_addr)?;
+        if entry & X86_PRESENT == 0 {
+            return Err(Error::NotPresent { level, entry_addr });
+        }
+
+        permissions.writable &= entry & X86_WRITABLE != 0;
+        permissions.user &= entry & X86_USER != 0;
+        permissions.executable &= entry & X86_NX == 0;
+
+        // Bit 7 is the PAT bit of page table entries, and is reserved in PML4 and PML5 entries.
+        let leaf = level == 1 || entry & X86_PAGE_SIZE != 0;
+        if leaf {
+            if level > 3 {
+                return Err(Error::Reserved { level, entry_addr });
+            }
+            let page_size = 1u64 << shift;
+            permissions.user_executable = permissions.user && permissions.executable;
+            return Ok(Translation {
+                gpa: GuestAddress(
+                    (entry & X86_ADDR_MASK & !(page_size - 1)) | (gva & (page_size - 1)),
+                ),
+                page_size,
+                permissions,
+            });
+        }
+        table = entry & X86_ADDR_MASK;
+    }
+    unreachable!()
+}
+
+fn walk_aarch64<M: GuestMemory + ?Sized>(
+    mem: &M,
+    root: GuestAddress,
+    granule: Granule,
+    va_bits: u32,
+    gva: u64,
+) -> Result<Translation> {
+    check_canonical(gva, va_bits, true)?;
+
+    let (page_shift, stride) = match granule {
+        Granule::Size4K => (12, 9),
+        Granule::Size64K => (16, 13),
+    };
+    let addr_mask = ARM_ADDR_MASK & !((1 << page_shift) - 1);
+    let start_level = (4 - (va_bits - page_shift).div_ceil(stride)) as u8;
+
+    // Root tables smaller than a page are only aligned to their size.
+    let mut table = root.0 & ARM_ROOT_MASK;
+    let mut permissions = PagePermissions::default();
+    for level in start_level..=3 {
+        let shift = page_shift + stride * u32::from(3 - level);
+        let bits = if level == start_level {
+            va_bits - shift
+        } else {
+            stride
+        };
+        let entry_addr = GuestAddress(table + ((gva >> shift) & ((1 << bits) - 1)) * 8);
+        let entry = read_entry(mem, level, entry_addr)?;
+        if entry & ARM_VALID == 0 {
+            return Err(Error::NotPresent { level, entry_addr });
+        }
+
+        if level < 3 && entry & ARM_TABLE != 0 {
+            permissions.writable &= entry & ARM_AP_TABLE_READ_ONLY == 0;
+            permissions.user &= entry & ARM_AP_TABLE_NO_USER == 0;
+            permissions.executable &= entry & ARM_PXN_TABLE == 0;
+            permissions.user_executable &= entry & ARM_UXN_TABLE == 0;
+            table = entry & addr_mask;
+            continue;
+        }
+
+        // Level 3 descriptors must be page descriptors, and blocks are only allowed at levels 1
+        // and 2 with 4K granules, and at level 2 with 64K granules.
+        let block_allowed = match granule {
+            Granule::Size4K => level == 1 || level == 2,
+            Granule::Size64K => level == 2,
+        };
+        if (level == 3 && entry & ARM_TABLE == 0) || (level < 3 && !block_allowed) {
+            return Err(Error::Reserved { level, entry_addr });
+        }
+
+        permissions.writable &= entry & ARM_AP_READ_ONLY == 0;
+        permissions.user &= entry & ARM_AP_USER != 0;
+        permissions.executable &= entry & ARM_PXN == 0;
+        permissions.user_executable &= entry & ARM_UXN == 0;
+        let page_size = 1u64 << shift;
+        return Ok(Translation {
+            gpa: GuestAddress((entry & addr_mask & !(page_size - 1)) | (gva & (page_size - 1))),
+            page_size,
+            permissions,
+        });
+    }
+    unreachable!()
+}
+
+#[cfg(all(test, feature = "backend-mmap"))]
+mod tests {
+    use super::*;
+
+    use matches::assert_matches;
+
+    use crate::GuestMemoryMmap;
+
+    fn write_entry(gm: &GuestMemoryMmap<()>, addr: u64, entry: u64) {
+        gm.write_obj(Le64::from(entry), GuestAddress(addr)).unwrap();
+    }
+
+    #[test]
+    fn test_x86_64() {
+        let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let mode = PagingMode::X86_64Level4;
+        // PML4 at 0x1000, PDPT at 0x2000, PD at 0x3000, PT at 0x4000.
+        write_entry(&gm, 0x1000, 0x2007);
+        write_entry(&gm, 0x2000, 0x8000_0000 | X86_PAGE_SIZE | 0x7);
+        write_entry(&gm, 0x2008, 0x3007);
+        write_entry(&gm, 0x3000, 0x20_0000 | X86_NX | X86_PAGE_SIZE | 0x5);
+        write_entry(&gm, 0x3008, 0x4003);
+        // Bit 7 of page table entries is the PAT bit.
+        write_entry(&gm, 0x4008, 0x9000 | X86_PAGE_SIZE | 0x7);
+
+        // 1G page.
+        let t = translate(&gm, GuestAddress(0x1000), mode, 0x1234_5678).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x9234_5678));
+        assert_eq!(t.page_size, 1 << 30);
+        assert_eq!(t.permissions, PagePermissions::default());
+
+        // 2M page; the low bits of the root are ignored.
+        let t = translate(&gm, GuestAddress(0x1018), mode, 0x4012_3456).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x32_3456));
+        assert_eq!(t.page_size, 2 << 20);
+        assert_eq!(
+            t.permissions,
+            PagePermissions {
+                writable: false,
+                user: true,
+                executable: false,
+                user_executable: false
+            }
+        );
+
+        // 4K page.
+        let t = translate(&gm, GuestAddress(0x1000), mode, 0x4020_1abc).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x9abc));
+        assert_eq!(t.page_size, 0x1000);
+        assert_eq!(
+            t.permissions,
+            PagePermissions {
+                writable: true,
+                user: false,
+                executable: true,
+                user_executable: false
+            }
+        );
+
+        assert_matches!(
+            translate(&gm, GuestAddress(0x1000), mode, 0x4020_2000),
+            Err(Error::NotPresent {
+                level: 1,
+                entry_addr: GuestAddress(0x4010)
+            })
+        );
+        assert_matches!(
+            translate(&gm, GuestAddress(0x1000), mode, 0x8000_0000),
+            Err(Error::NotPresent {
+                level: 3,
+                entry_addr: GuestAddress(0x2010)
+            })
+        );
+        assert_matches!(
+            translate(&gm, GuestAddress(0x1000), mode, 0x0000_8000_0000_0000),
+            Err(Error::NonCanonical(0x0000_8000_0000_0000))
+        );
+        assert_matches!(
+            translate(&gm, GuestAddress(0x1000), mode, 0xffff_8000_0000_0000),
+            Err(Error::NotPresent {
+                level: 4,
+                entry_addr: GuestAddress(0x1800)
+            })
+        );
+
+        // Large pages are reserved in PML4 entries.
+        write_entry(&gm, 0x1008, 0x2000 | X86_PAGE_SIZE | 0x3);
+        assert_matches!(
+            translate(&gm, GuestAddress(0x1000), mode, 0x80_0000_0000),
+            Err(Error::Reserved {
+                level: 4,
+                entry_addr: GuestAddress(0x1008)
+            })
+        );
+
+        // Tables outside of guest memory.
+        write_entry(&gm, 0x2018, 0x10_0000 | 0x3);
+        assert_matches!(
+            translate(&gm, GuestAddress(0x1000), mode, 0xc000_0000),
+            Err(Error::GuestMemory {
+                level: 2,
+                entry_addr: GuestAddress(0x10_0000),
+                ..
+            })
+        );
+
+        // 5-level paging, with a PML5 at 0x5000 pointing to the same PML4.
+        let mode = PagingMode::X86_64Level5;
+        write_entry(&gm, 0x5000, 0x1007);
+        let t = translate(&gm, GuestAddress(0x5000), mode, 0x4020_1abc).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x9abc));
+        assert_matches!(
+            translate(&gm, GuestAddress(0x5000), mode, 0x0100_0000_0000_0000),
+            Err(Error::NonCanonical(_))
+        );
+        assert_matches!(
+            translate(&gm, GuestAddress(0x5000), mode, 0x0001_0000_0000_0000),
+            Err(Error::NotPresent {
+                level: 5,
+                entry_addr: GuestAddress(0x5008)
+            })
+        );
+    }
+
+    #[test]
+    fn test_aarch64_4k() {
+        let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x10000)]).unwrap();
+        let mode = PagingMode::Aarch64 {
+            granule: Granule::Size4K,
+            va_bits: 48,
+        };
+        // Level 0 at 0x1000, level 1 at 0x2000, level 2 at 0x3000, level 3 at 0x4000.
+        write_entry(&gm, 0x1000, 0x2003);
+        write_entry(&gm, 0x2000, 0x4000_0000 | ARM_UXN | ARM_AP_USER | 0x1);
+        write_entry(&gm, 0x2008, 0x3003 | ARM_AP_TABLE_READ_ONLY);
+        write_entry(&gm, 0x3000, 0x4000 | 0x3);
+        write_entry(&gm, 0x3008, 0x60_0000 | ARM_PXN | 0x1);
+        write_entry(&gm, 0x4008, 0x9000 | ARM_AP_USER | 0x3);
+        write_entry(&gm, 0x4010, 0xa000 | 0x1);
+
+        // 1G block; the ASID of the root is ignored.
+        let root = GuestAddress(0x0042_0000_0000_1000);
+        let t = translate(&gm, root, mode, 0x1234_5678).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x5234_5678));
+        assert_eq!(t.page_size, 1 << 30);
+        assert_eq!(
+            t.permissions,
+            PagePermissions {
+                writable: true,
+                user: true,
+                executable: true,
+                user_executable: false
+            }
+        );
+
+        // 2M block, with the permissions of the table descriptor.
+        let t = translate(&gm, root, mode, 0x4030_0000).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x70_0000));
+        assert_eq!(t.page_size, 2 << 20);
+        assert_eq!(
+            t.permissions,
+            PagePermissions {
+                writable: false,
+                user: false,
+                executable: false,
+                user_executable: true
+            }
+        );
+
+        // 4K page.
+        let t = translate(&gm, root, mode, 0x4000_1abc).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x9abc));
+        assert_eq!(t.page_size, 0x1000);
+        assert!(t.permissions.user && !t.permissions.writable);
+
+        // Level 3 descriptors with bit 1 clear are reserved.
+        assert_matches!(
+            translate(&gm, root, mode, 0x4000_2000),
+            Err(Error::Reserved {
+                level: 3,
+                entry_addr: GuestAddress(0x4010)
+            })
+        );
+        assert_matches!(
+            translate(&gm, root, mode, 0x4000_3000),
+            Err(Error::NotPresent {
+                level: 3,
+                entry_addr: GuestAddress(0x4018)
+            })
+        );
+
+        // Blocks are reserved at level 0.
+        write_entry(&gm, 0x1008, 0x8000_0000 | 0x1);
+        assert_matches!(
+            translate(&gm, root, mode, 0x80_0000_0000),
+            Err(Error::Reserved {
+                level: 0,
+                entry_addr: GuestAddress(0x1008)
+            })
+        );
+
+        // Both halves of the address space are translated.
+        assert_matches!(
+            translate(&gm, root, mode, 0xffff_ff80_0000_0000),
+            Err(Error::NotPresent {
+                level: 0,
+                entry_addr: GuestAddress(0x1ff8)
+            })
+        );
+        assert_matches!(
+            translate(&gm, root, mode, 0x0001_0000_0000_0000),
+            Err(Error::NonCanonical(_))
+        );
+
+        // With 39-bit addresses, the walk starts at level 1.
+        let mode = PagingMode::Aarch64 {
+            granule: Granule::Size4K,
+            va_bits: 39,
+        };
+        let t = translate(&gm, GuestAddress(0x2000), mode, 0x4000_1abc).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x9abc));
+        assert_matches!(
+            translate(
+                &gm,
+                root,
+                PagingMode::Aarch64 {
+                    granule: Granule::Size4K,
+                    va_bits: 52
+                },
+                0
+            ),
+            Err(Error::InvalidMode(_))
+        );
+    }
+
+    #[test]
+    fn test_aarch64_64k() {
+        let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0), 0x40000)]).unwrap();
+        let mode = PagingMode::Aarch64 {
+            granule: Granule::Size64K,
+            va_bits: 48,
+        };
+        // Level 1 (64 entries) at 0x100, level 2 at 0x10000, level 3 at 0x20000.
+        write_entry(&gm, 0x100, 0x10003);
+        write_entry(&gm, 0x10000, 0x2_0000_0000 | 0x1);
+        write_entry(&gm, 0x10008, 0x20003);
+        write_entry(&gm, 0x20008, 0x30000 | ARM_AP_USER | 0x3);
+
+        // 512M block.
+        let t = translate(&gm, GuestAddress(0x100), mode, 0x1234_5678).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x2_1234_5678));
+        assert_eq!(t.page_size, 512 << 20);
+
+        // 64K page.
+        let t = translate(&gm, GuestAddress(0x100), mode, 0x2001_abcd).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x3abcd));
+        assert_eq!(t.page_size, 0x10000);
+        assert!(t.permissions.user);
+
+        // Blocks are reserved at level 1 with 64K granules.
+        write_entry(&gm, 0x108, 0x1);
+        assert_matches!(
+            translate(&gm, GuestAddress(0x100), mode, 0x400_0000_0000),
+            Err(Error::Reserved {
+                level: 1,
+                entry_addr: GuestAddress(0x108)
+            })
+        );
+
+        // With 42-bit addresses, the walk starts at level 2.
+        let mode = PagingMode::Aarch64 {
+            granule: Granule::Size64K,
+            va_bits: 42,
+        };
+        let t = translate(&gm, GuestAddress(0x10000), mode, 0x2001_abcd).unwrap();
+        assert_eq!(t.gpa, GuestAddress(0x3abcd));
+    }
+}
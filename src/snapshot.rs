@@ -0,0 +1,1040 @@
+// SPDX-License-Identifier: Apache-2.0 OR BSD-3-Clause
+
+//! Save and restore the contents of guest memory.
+//!
+//! A snapshot stores the layout of a [`GuestMemory`](trait.GuestMemory.html) object together
+//! with the contents of its regions, and can be written to any [`Write`] implementation. It can
+//! be restored into an existing guest memory object with the same layout, or (with the
+//! `backend-mmap` feature) into a newly created `GuestMemoryMmap`.
+//!
+//! When the regions track writes with an [`AtomicBitmap`](../bitmap/struct.AtomicBitmap.html),
+//! incremental snapshots only containing the pages written since the previous snapshot can be
+//! taken with [`save_dirty`](fn.save_dirty.html), and applied on top of a restored full snapshot
+//! with [`restore_dirty`](fn.restore_dirty.html) or [`restore_chain`](fn.restore_chain.html).
+//!
+//! # Format
+//!
+//! All integers are stored in little-endian byte order. A snapshot starts with a header:
+//!
+//! | Offset | Size | Field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 8    | Magic value, the ASCII string `VMMEMSNP`       |
+//! | 8      | 4    | Format version, currently `1`                  |
+//! | 12     | 4    | Snapshot kind, `0` for full, `1` for dirty     |
+//! | 16     | 4    | Page size used for the page data, in bytes     |
+//! | 20     | 4    | Number of entries in the region table          |
+//!
+//! The header is followed by the region table, with one 24 byte entry per region, sorted by
+//! guest address:
+//!
+//! | Offset | Size | Field                                          |
+//! |--------|------|------------------------------------------------|
+//! | 0      | 8    | Guest physical address of the region           |
+//! | 8      | 8    | Size of the region, in bytes                   |
+//! | 16     | 4    | Region flags, see `REGION_FLAG_*`              |
+//! | 20     | 4    | Reserved, must be zero                         |
+//!
+//! In a full snapshot, next comes the page data of every region, in the order of the region
+//! table. Each page of a region (the last one may be shorter than the page size) is stored as a
+//! one byte tag: `0` for a page that only contains zeroes, and `1` for a page whose contents
+//! follow the tag.
+//!
+//! In an incremental snapshot, the page size is the one of the dirty bitmap, and the region table
+//! is followed by the number of dirty pages as an 8 byte integer. Each dirty page is stored as
+//! its 8 byte guest physical address, its 4 byte length (which is at most the page size), and
+//! its contents.
+//!
+//! The snapshot ends with the CRC-32 checksum (as used by zlib and Ethernet) of all the bytes
+//! preceding it, stored as a 4 byte integer.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::address::Address;
+#[cfg(feature = "backend-bitmap")]
+use crate::bitmap::AtomicBitmap;
+use crate::guest_memory::{
+    self, GuestAddress, GuestMemory, GuestMemoryRegion, MemoryRegionAddress,
+};
+#[cfg(feature = "backend-mmap")]
+use crate::mmap::{self, GuestMemoryMmap, NewBitmap};
+use crate::Bytes;
+
+/// Magic value identifying a snapshot.
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"VMMEMSNP";
+/// Version of the snapshot format written by this module.
+pub const SNAPSHOT_VERSION: u32 = 1;
+/// Page size used for the page data of the snapshots written by this module.
+pub const SNAPSHOT_PAGE_SIZE: usize = 0x1000;
+
+/// The region is backed by a file on the host.
+pub const REGION_FLAG_FILE_BACKED: u32 = 1 << 0;
+/// The region is backed by huge pages on the host.
+pub const REGION_FLAG_HUGETLBFS: u32 = 1 << 1;
+
+const KIND_FULL: u32 = 0;
+const KIND_DIRTY: u32 = 1;
+
+// Largest page size accepted when reading a snapshot, bounding the size of the page buffers.
+const MAX_PAGE_SIZE: u32 = 1 << 30;
+
+const PAGE_ZERO: u8 = 0;
+const PAGE_DATA: u8 = 1;
+
+/// Errors that can occur when saving or restoring a snapshot.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing the snapshot stream failed.
+    IOError(io::Error),
+    /// Accessing guest memory failed.
+    GuestMemory(guest_memory::Error),
+    /// Creating the guest memory for the snapshot failed.
+    #[cfg(feature = "backend-mmap")]
+    Mmap(mmap::Error),
+    /// The stream does not start with the snapshot magic value.
+    InvalidMagic,
+    /// The snapshot uses an unsupported version of the format.
+    UnsupportedVersion(u32),
+    /// The snapshot is of an unknown or unexpected kind.
+    UnsupportedKind(u32),
+    /// The page size of the snapshot is not a power of two of at most 1 GiB.
+    InvalidPageSize(u32),
+    /// The region table of the snapshot is malformed.
+    InvalidRegionTable,
+    /// The page data of the snapshot contains an unknown tag.
+    InvalidPageTag(u8),
+    /// A dirty page of the snapshot does not fit into a region of the guest memory.
+    InvalidPageRecord(GuestAddress),
+    /// The layout of the guest memory does not match the region table of the snapshot.
+    LayoutMismatch,
+    /// The checksum of the snapshot does not match its contents.
+    ChecksumMismatch {
+        /// The checksum stored in the snapshot.
+        expected: u32,
+        /// The checksum computed over the contents of the snapshot.
+        actual: u32,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::IOError(e) => write!(f, "Error accessing the snapshot stream: {}", e),
+            Error::GuestMemory(e) => write!(f, "Error accessing guest memory: {}", e),
+            #[cfg(feature = "backend-mmap")]
+            Error::Mmap(e) => write!(f, "Error creating guest memory: {}", e),
+            Error::InvalidMagic => write!(f, "The stream does not contain a snapshot"),
+            Error::UnsupportedVersion(v) => write!(f, "Unsupported snapshot version {}", v),
+            Error::UnsupportedKind(k) => write!(f, "Unsupported snapshot kind {}", k),
+            Error::InvalidPageSize(s) => write!(f, "Invalid snapshot page size {}", s),
+            Error::InvalidRegionTable => write!(f, "The snapshot region table is malformed"),
+            Error::InvalidPageTag(t) => write!(f, "Invalid snapshot page tag {}", t),
+            Error::InvalidPageRecord(addr) => write!(
+                f,
+                "Invalid snapshot page at guest address {:#x}",
+                addr.raw_value()
+            ),
+            Error::LayoutMismatch => write!(
+                f,
+                "The guest memory layout does not match the snapshot region table"
+            ),
+            Error::ChecksumMismatch { expected, actual } => write!(
+                f,
+                "Snapshot checksum mismatch: expected {:#010x}, computed {:#010x}",
+                expected, actual
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::IOError(e)
+    }
+}
+
+impl From<guest_memory::Error> for Error {
+    fn from(e: guest_memory::Error) -> Self {
+        Error::GuestMemory(e)
+    }
+}
+
+/// Result of snapshot operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An entry of the region table of a snapshot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SnapshotRegion {
+    /// Guest physical address of the region.
+    pub guest_base: GuestAddress,
+    /// Size of the region in bytes.
+    pub size: u64,
+    /// Region flags, see `REGION_FLAG_*`.
+    pub flags: u32,
+}
+
+impl SnapshotRegion {
+    fn from_region<R: GuestMemoryRegion>(region: &R) -> Self {
+        let mut flags = 0;
+        if region.file_offset().is_some() {
+            flags |= REGION_FLAG_FILE_BACKED;
+        }
+        #[cfg(target_os = "linux")]
+        if region.is_hugetlbfs() == Some(true) {
+            flags |= REGION_FLAG_HUGETLBFS;
+        }
+
+        SnapshotRegion {
+            guest_base: region.start_addr(),
+            size: region.len(),
+            flags,
+        }
+    }
+}
+
+// The header and region table of a snapshot.
+#[derive(Debug)]
+struct Header {
+    kind: u32,
+    page_size: usize,
+    regions: Vec<SnapshotRegion>,
+}
+
+impl Header {
+    fn new<M: GuestMemory + ?Sized>(mem: &M, kind: u32, page_size: usize) -> Self {
+        Header {
+            kind,
+            page_size,
+            regions: mem.iter().map(SnapshotRegion::from_region).collect(),
+        }
+    }
+
+    fn write_to<W: Write>(&self, writer: &mut W) -> Result<()> {
+        writer.write_all(&SNAPSHOT_MAGIC)?;
+        writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+        writer.write_all(&self.kind.to_le_bytes())?;
+        writer.write_all(&(self.page_size as u32).to_le_bytes())?;
+        writer.write_all(&(self.regions.len() as u32).to_le_bytes())?;
+        for region in self.regions.iter() {
+            writer.write_all(&region.guest_base.raw_value().to_le_bytes())?;
+            writer.write_all(&region.size.to_le_bytes())?;
+            writer.write_all(&region.flags.to_le_bytes())?;
+            writer.write_all(&0u32.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read_from<R: Read>(reader: &mut R) -> Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic != SNAPSHOT_MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+
+        let version = read_u32(reader)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let kind = read_u32(reader)?;
+        let page_size = read_u32(reader)?;
+        if !page_size.is_power_of_two() || page_size > MAX_PAGE_SIZE {
+            return Err(Error::InvalidPageSize(page_size));
+        }
+
+        let count = read_u32(reader)?;
+        let mut regions = Vec::new();
+        for _ in 0..count {
+            let region = SnapshotRegion {
+                guest_base: GuestAddress(read_u64(reader)?),
+                size: read_u64(reader)?,
+                flags: read_u32(reader)?,
+            };
+            let reserved = read_u32(reader)?;
+
+            let valid = reserved == 0
+                && region.size > 0
+                && region.guest_base.checked_add(region.size - 1).is_some()
+                && !regions.last().is_some_and(|prev: &SnapshotRegion| {
+                    prev.guest_base.unchecked_add(prev.size - 1) >= region.guest_base
+                });
+            if !valid {
+                return Err(Error::InvalidRegionTable);
+            }
+            regions.push(region);
+        }
+
+        Ok(Header {
+            kind,
+            page_size: page_size as usize,
+            regions,
+        })
+    }
+
+    // Checks that `mem` has the same layout as the region table.
+    fn check_layout<M: GuestMemory + ?Sized>(&self, mem: &M) -> Result<()> {
+        let matches = mem.num_regions() == self.regions.len()
+            && mem
+                .iter()
+                .zip(self.regions.iter())
+                .all(|(r, s)| r.start_addr() == s.guest_base && r.len() == s.size);
+        if !matches {
+            return Err(Error::LayoutMismatch);
+        }
+        Ok(())
+    }
+}
+
+// Incremental CRC-32 (IEEE 802.3) computation.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Crc32(u32);
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+impl Crc32 {
+    pub(crate) fn new() -> Self {
+        Crc32(!0)
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 = CRC32_TABLE[((self.0 ^ byte as u32) & 0xff) as usize] ^ (self.0 >> 8);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+// A `Write` adapter computing the checksum of the data written through it.
+struct ChecksumWriter<'a, W> {
+    inner: &'a mut W,
+    crc: Crc32,
+}
+
+impl<'a, W: Write> ChecksumWriter<'a, W> {
+    fn new(inner: &'a mut W) -> Self {
+        ChecksumWriter {
+            inner,
+            crc: Crc32::new(),
+        }
+    }
+
+    // Writes the checksum trailer.
+    fn finish(self) -> Result<()> {
+        self.inner.write_all(&self.crc.finish().to_le_bytes())?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for ChecksumWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+// A `Read` adapter computing the checksum of the data read through it.
+struct ChecksumReader<'a, R> {
+    inner: &'a mut R,
+    crc: Crc32,
+}
+
+impl<'a, R: Read> ChecksumReader<'a, R> {
+    fn new(inner: &'a mut R) -> Self {
+        ChecksumReader {
+            inner,
+            crc: Crc32::new(),
+        }
+    }
+
+    // Reads the checksum trailer and compares it against the data read so far.
+    fn finish(self) -> Result<()> {
+        let actual = self.crc.finish();
+        let expected = read_u32(self.inner)?;
+        if expected != actual {
+            return Err(Error::ChecksumMismatch { expected, actual });
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ChecksumReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    reader.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// Calls `f` with the region address and length of every page of `region`.
+fn for_each_page<F>(size: u64, page_size: usize, mut f: F) -> Result<()>
+where
+    F: FnMut(MemoryRegionAddress, usize) -> Result<()>,
+{
+    let mut offset = 0;
+    while offset < size {
+        let len = std::cmp::min(page_size as u64, size - offset) as usize;
+        f(MemoryRegionAddress(offset), len)?;
+        offset += len as u64;
+    }
+    Ok(())
+}
+
+/// Writes a full snapshot of `mem` to `writer`.
+///
+/// Pages only containing zeroes are elided from the page data. The contents of the guest memory
+/// should not change while the snapshot is taken, e.g. by pausing the guest.
+///
+/// # Examples (uses the `backend-mmap` feature)
+///
+/// ```
+/// # #[cfg(feature = "backend-mmap")]
+/// # {
+/// # use vm_memory::snapshot;
+/// # use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+/// #
+/// let gm = GuestMemoryMmap::<()>::from_ranges(&[(GuestAddress(0x1000), 0x4000)])
+///     .expect("Could not create guest memory");
+/// gm.write_obj(0x1234_5678u32, GuestAddress(0x2000)).unwrap();
+///
+/// let mut data = Vec::new();
+/// snapshot::save(&gm, &mut data).expect("Could not save guest memory");
+///
+/// let restored: GuestMemoryMmap<()> =
+///     snapshot::restore_new(&mut data.as_slice()).expect("Could not restore guest memory");
+/// assert_eq!(
+///     restored.read_obj::<u32>(GuestAddress(0x2000)).unwrap(),
+///     0x1234_5678
+/// );
+/// # }
+/// ```
+pub fn save<M, W>(mem: &M, writer: &mut W) -> Result<()>
+where
+    M: GuestMemory + ?Sized,
+    W: Write,
+{
+    let header = Header::new(mem, KIND_FULL, SNAPSHOT_PAGE_SIZE);
+    let mut writer = ChecksumWriter::new(writer);
+    header.write_to(&mut writer)?;
+
+    let mut buf = vec![0u8; header.page_size];
+    for region in mem.iter() {
+        for_each_page(region.len(), header.page_size, |addr, len| {
+            let page = &mut buf[..len];
+            region.read_slice(page, addr)?;
+            if page.iter().all(|&b| b == 0) {
+                writer.write_all(&[PAGE_ZERO])?;
+            } else {
+                writer.write_all(&[PAGE_DATA])?;
+                writer.write_all(page)?;
+            }
+            Ok(())
+        })?;
+    }
+
+    writer.finish()
+}
+
+// Loads the page data of a full snapshot into `mem`, which has the layout of the header.
+// Zero pages are only written when `write_zero_pages` is set.
+fn load_pages<M, R>(mem: &M, header: &Header, reader: &mut R, write_zero_pages: bool) -> Result<()>
+where
+    M: GuestMemory + ?Sized,
+    R: Read,
+{
+    let mut buf = vec![0u8; header.page_size];
+    for region in mem.iter() {
+        for_each_page(region.len(), header.page_size, |addr, len| {
+            let mut tag = [0u8];
+            reader.read_exact(&mut tag)?;
+            let page = &mut buf[..len];
+            match tag[0] {
+                PAGE_ZERO if !write_zero_pages => return Ok(()),
+                PAGE_ZERO => page.fill(0),
+                PAGE_DATA => reader.read_exact(page)?,
+                tag => return Err(Error::InvalidPageTag(tag)),
+            }
+            region.write_slice(page, addr)?;
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+/// Restores a full snapshot from `reader` into `mem`, which must have the same layout as the
+/// memory the snapshot was taken from.
+///
+/// The checksum is only verified after all the data has been loaded, so the contents of `mem`
+/// are unspecified when an error is returned.
+pub fn restore<M, R>(mem: &M, reader: &mut R) -> Result<()>
+where
+    M: GuestMemory + ?Sized,
+    R: Read,
+{
+    let mut reader = ChecksumReader::new(reader);
+    let header = Header::read_from(&mut reader)?;
+    if header.kind != KIND_FULL {
+        return Err(Error::UnsupportedKind(header.kind));
+    }
+    header.check_layout(mem)?;
+
+    load_pages(mem, &header, &mut reader, true)?;
+    reader.finish()
+}
+
+/// Creates a new `GuestMemoryMmap` with the layout stored in the full snapshot read from
+/// `reader`, and restores the snapshot into it.
+///
+/// The regions of the new guest memory are anonymous mappings, regardless of the flags stored
+/// in the region table.
+#[cfg(feature = "backend-mmap")]
+pub fn restore_new<B, R>(reader: &mut R) -> Result<GuestMemoryMmap<B>>
+where
+    B: NewBitmap + 'static,
+    R: Read,
+{
+    let mut reader = ChecksumReader::new(reader);
+    let header = Header::read_from(&mut reader)?;
+    if header.kind != KIND_FULL {
+        return Err(Error::UnsupportedKind(header.kind));
+    }
+
+    let ranges = header
+        .regions
+        .iter()
+        .map(|r| {
+            usize::try_from(r.size)
+                .map(|size| (r.guest_base, size))
+                .map_err(|_| Error::InvalidRegionTable)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let mem = GuestMemoryMmap::from_ranges(&ranges).map_err(Error::Mmap)?;
+
+    // Freshly mapped memory is already zeroed.
+    load_pages(&mem, &header, &mut reader, false)?;
+    reader.finish()?;
+    Ok(mem)
+}
+
+/// Writes an incremental snapshot of `mem` to `writer`, containing the pages written since the
+/// last time the dirty bitmaps of its regions were reset.
+///
+/// The dirty bits of each region are atomically fetched and reset before the pages are read, so
+/// writes racing with the snapshot are included in the next one. If writing the snapshot fails,
+/// the consumed bits are set again.
+#[cfg(feature = "backend-bitmap")]
+pub fn save_dirty<M, W>(mem: &M, writer: &mut W) -> Result<()>
+where
+    M: GuestMemory + ?Sized,
+    M::R: GuestMemoryRegion<B = AtomicBitmap>,
+    W: Write,
+{
+    let dirty: Vec<(&M::R, Vec<u64>)> = mem
+        .iter()
+        .map(|region| (region, region.bitmap().get_and_reset()))
+        .collect();
+
+    let result = write_dirty(mem, &dirty, writer);
+    if result.is_err() {
+        for (region, bits) in dirty.iter() {
+            let bitmap = region.bitmap();
+            for_each_dirty_page(region.len(), bitmap, bits, |addr, len| {
+                bitmap.set_addr_range(addr.raw_value() as usize, len);
+                Ok(())
+            })?;
+        }
+    }
+    result
+}
+
+// Calls `f` with the region address and length of every page of a region of `size` bytes whose
+// bit is set in `bits`, which was taken from `bitmap`.
+#[cfg(feature = "backend-bitmap")]
+fn for_each_dirty_page<F>(size: u64, bitmap: &AtomicBitmap, bits: &[u64], mut f: F) -> Result<()>
+where
+    F: FnMut(MemoryRegionAddress, usize) -> Result<()>,
+{
+    let page_size = bitmap.page_size() as u64;
+    for (i, &word) in bits.iter().enumerate() {
+        let mut word = word;
+        while word != 0 {
+            let bit = (i * 64) as u64 + u64::from(word.trailing_zeros());
+            word &= word - 1;
+            let offset = bit * page_size;
+            if offset >= size {
+                return Ok(());
+            }
+            f(
+                MemoryRegionAddress(offset),
+                std::cmp::min(page_size, size - offset) as usize,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "backend-bitmap")]
+fn write_dirty<M, W>(mem: &M, dirty: &[(&M::R, Vec<u64>)], writer: &mut W) -> Result<()>
+where
+    M: GuestMemory + ?Sized,
+    M::R: GuestMemoryRegion<B = AtomicBitmap>,
+    W: Write,
+{
+    let page_size = dirty
+        .iter()
+        .map(|(region, _)| region.bitmap().page_size())
+        .max()
+        .unwrap_or(SNAPSHOT_PAGE_SIZE);
+    let header = Header::new(mem, KIND_DIRTY, page_size);
+    let mut writer = ChecksumWriter::new(writer);
+    header.write_to(&mut writer)?;
+
+    let mut count = 0u64;
+    for (region, bits) in dirty.iter() {
+        for_each_dirty_page(region.len(), region.bitmap(), bits, |_, _| {
+            count += 1;
+            Ok(())
+        })?;
+    }
+    writer.write_all(&count.to_le_bytes())?;
+
+    let mut buf = vec![0u8; page_size];
+    for (region, bits) in dirty.iter() {
+        for_each_dirty_page(region.len(), region.bitmap(), bits, |addr, len| {
+            let page = &mut buf[..len];
+            region.read_slice(page, addr)?;
+            let guest_addr = region.start_addr().unchecked_add(addr.raw_value());
+            writer.write_all(&guest_addr.raw_value().to_le_bytes())?;
+            writer.write_all(&(len as u32).to_le_bytes())?;
+            writer.write_all(page)?;
+            Ok(())
+        })?;
+    }
+
+    writer.finish()
+}
+
+/// Applies an incremental snapshot from `reader` to `mem`, which must have the same layout as the
+/// memory the snapshot was taken from, and already contain the state the snapshot is based on.
+///
+/// The checksum is only verified after all the data has been loaded, so the contents of `mem`
+/// are unspecified when an error is returned.
+pub fn restore_dirty<M, R>(mem: &M, reader: &mut R) -> Result<()>
+where
+    M: GuestMemory + ?Sized,
+    R: Read,
+{
+    let mut reader = ChecksumReader::new(reader);
+    let header = Header::read_from(&mut reader)?;
+    if header.kind != KIND_DIRTY {
+        return Err(Error::UnsupportedKind(header.kind));
+    }
+    header.check_layout(mem)?;
+
+    let count = read_u64(&mut reader)?;
+    let mut buf = vec![0u8; header.page_size];
+    for _ in 0..count {
+        let addr = GuestAddress(read_u64(&mut reader)?);
+        let len = read_u32(&mut reader)? as usize;
+        let (region, offset) = mem
+            .to_region_addr(addr)
+            .filter(|(region, offset)| {
+                len > 0
+                    && len <= header.page_size
+                    && region.checked_offset(*offset, len - 1).is_some()
+            })
+            .ok_or(Error::InvalidPageRecord(addr))?;
+
+        let page = &mut buf[..len];
+        reader.read_exact(page)?;
+        region.write_slice(page, offset)?;
+    }
+
+    reader.finish()
+}
+
+/// Creates a new `GuestMemoryMmap` from the full snapshot read from `base`, and applies the
+/// incremental snapshots read from `diffs` on top of it, in order.
+///
+/// # Examples (uses the `backend-bitmap` and `backend-mmap` features)
+///
+/// ```
+/// # #[cfg(all(feature = "backend-bitmap", feature = "backend-mmap"))]
+/// # {
+/// # use vm_memory::bitmap::AtomicBitmap;
+/// # use vm_memory::snapshot;
+/// # use vm_memory::{Bytes, GuestAddress, GuestMemoryMmap};
+/// #
+/// let gm = GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[(GuestAddress(0), 0x10000)])
+///     .expect("Could not create guest memory");
+///
+/// let mut base = Vec::new();
+/// snapshot::save(&gm, &mut base).unwrap();
+/// // Only track the writes done after the full snapshot.
+/// snapshot::save_dirty(&gm, &mut std::io::sink()).unwrap();
+///
+/// gm.write_obj(1u64, GuestAddress(0x1000)).unwrap();
+/// let mut diff1 = Vec::new();
+/// snapshot::save_dirty(&gm, &mut diff1).unwrap();
+///
+/// gm.write_obj(2u64, GuestAddress(0x1000)).unwrap();
+/// let mut diff2 = Vec::new();
+/// snapshot::save_dirty(&gm, &mut diff2).unwrap();
+///
+/// let restored: GuestMemoryMmap<()> =
+///     snapshot::restore_chain(&mut base.as_slice(), [diff1.as_slice(), diff2.as_slice()])
+///         .expect("Could not restore guest memory");
+/// assert_eq!(restored.read_obj::<u64>(GuestAddress(0x1000)).unwrap(), 2);
+/// # }
+/// ```
+#[cfg(feature = "backend-mmap")]
+pub fn restore_chain<B, R, I>(base: &mut R, diffs: I) -> Result<GuestMemoryMmap<B>>
+where
+    B: NewBitmap + 'static,
+    R: Read,
+    I: IntoIterator,
+    I::Item: Read,
+{
+    let mem = restore_new(base)?;
+    for mut diff in diffs {
+        restore_dirty(&mem, &mut diff)?;
+    }
+    Ok(mem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "backend-mmap")]
+    use crate::bitmap::AtomicBitmap;
+
+    #[cfg(feature = "backend-mmap")]
+    type GuestMemoryMmap = crate::GuestMemoryMmap<()>;
+
+    #[test]
+    fn test_crc32() {
+        let mut crc = Crc32::new();
+        assert_eq!(crc.finish(), 0);
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+    }
+
+    #[cfg(feature = "backend-mmap")]
+    fn create_memory() -> GuestMemoryMmap {
+        let gm = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0x0), 0x4000),
+            (GuestAddress(0x10000), 0x2800),
+        ])
+        .unwrap();
+        gm.write_slice(&[0xa5; 0x20], GuestAddress(0x1ff0)).unwrap();
+        gm.write_obj(0x0123_4567_89ab_cdefu64, GuestAddress(0x127f8))
+            .unwrap();
+        gm
+    }
+
+    #[cfg(feature = "backend-mmap")]
+    fn check_memory<M: GuestMemory>(gm: &M) {
+        let mut buf = [0u8; 0x40];
+        gm.read_slice(&mut buf, GuestAddress(0x1fe0)).unwrap();
+        assert_eq!(buf[..0x10], [0; 0x10]);
+        assert_eq!(buf[0x10..0x30], [0xa5; 0x20]);
+        assert_eq!(buf[0x30..], [0; 0x10]);
+        assert_eq!(
+            gm.read_obj::<u64>(GuestAddress(0x127f8)).unwrap(),
+            0x0123_4567_89ab_cdef
+        );
+        assert_eq!(gm.read_obj::<u64>(GuestAddress(0x0)).unwrap(), 0);
+    }
+
+    #[cfg(feature = "backend-mmap")]
+    #[test]
+    fn test_save_restore() {
+        let gm = create_memory();
+        let mut data = Vec::new();
+        save(&gm, &mut data).unwrap();
+
+        // Header, region table, one tag per page, two full data pages, the partial last page of the
+        // second region and the checksum.
+        assert_eq!(data.len(), 24 + 2 * 24 + (4 + 3) + 2 * 0x1000 + 0x800 + 4);
+
+        // Restore into memory with stale contents, which must be overwritten.
+        let target = GuestMemoryMmap::from_ranges(&[
+            (GuestAddress(0x0), 0x4000),
+            (GuestAddress(0x10000), 0x2800),
+        ])
+        .unwrap();
+        target
+            .write_slice(&[0xff; 0x4000], GuestAddress(0x0))
+            .unwrap();
+        restore(&target, &mut data.as_slice()).unwrap();
+        check_memory(&target);
+        assert_eq!(target.read_obj::<u8>(GuestAddress(0x3fff)).unwrap(), 0);
+
+        let restored: crate::GuestMemoryMmap<AtomicBitmap> =
+            restore_new(&mut data.as_slice()).unwrap();
+        check_memory(&restored);
+        let layout: Vec<_> = restored.iter().map(|r| (r.start_addr(), r.len())).collect();
+        assert_eq!(
+            layout,
+            vec![(GuestAddress(0x0), 0x4000), (GuestAddress(0x10000), 0x2800)]
+        );
+    }
+
+    #[cfg(feature = "backend-mmap")]
+    #[test]
+    fn test_region_flags() {
+        use crate::guest_memory::FileOffset;
+        use vmm_sys_util::tempfile::TempFile;
+
+        let f = TempFile::new().unwrap().into_file();
+        f.set_len(0x1000).unwrap();
+        let gm = GuestMemoryMmap::from_ranges_with_files(&[
+            (GuestAddress(0x0), 0x1000, None),
+            (GuestAddress(0x1000), 0x1000, Some(FileOffset::new(f, 0))),
+        ])
+        .unwrap();
+
+        let mut data = Vec::new();
+        save(&gm, &mut data).unwrap();
+        let header = Header::read_from(&mut data.as_slice()).unwrap();
+        assert_eq!(header.page_size, SNAPSHOT_PAGE_SIZE);
+        assert_eq!(header.regions[0].flags, 0);
+        assert_eq!(header.regions[1].flags, REGION_FLAG_FILE_BACKED);
+    }
+
+    #[cfg(feature = "backend-mmap")]
+    #[test]
+    fn test_restore_errors() {
+        let gm = create_memory();
+        let mut data = Vec::new();
+        save(&gm, &mut data).unwrap();
+
+        let other = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 0x4000)]).unwrap();
+        assert!(matches!(
+            restore(&other, &mut data.as_slice()),
+            Err(Error::LayoutMismatch)
+        ));
+
+        let mut corrupted = data.clone();
+        let len = corrupted.len();
+        corrupted[len - 100] ^= 1;
+        assert!(matches!(
+            restore(&gm, &mut corrupted.as_slice()),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+        assert!(matches!(
+            restore_new::<(), _>(&mut corrupted.as_slice()),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+
+        let mut corrupted = data.clone();
+        corrupted[0] = b'X';
+        assert!(matches!(
+            restore(&gm, &mut corrupted.as_slice()),
+            Err(Error::InvalidMagic)
+        ));
+
+        let mut corrupted = data.clone();
+        corrupted[8] = 2;
+        assert!(matches!(
+            restore(&gm, &mut corrupted.as_slice()),
+            Err(Error::UnsupportedVersion(2))
+        ));
+
+        for page_size in [0u32, 0x1800, 1 << 31] {
+            let mut corrupted = data.clone();
+            corrupted[16..20].copy_from_slice(&page_size.to_le_bytes());
+            assert!(matches!(
+                restore(&gm, &mut corrupted.as_slice()),
+                Err(Error::InvalidPageSize(s)) if s == page_size
+            ));
+        }
+
+        // The first page of the first region is a zero page, turn its tag into garbage.
+        let mut corrupted = data.clone();
+        corrupted[24 + 2 * 24] = 7;
+        assert!(matches!(
+            restore(&gm, &mut corrupted.as_slice()),
+            Err(Error::InvalidPageTag(7))
+        ));
+
+        // Make the regions overlap.
+        let mut corrupted = data.clone();
+        corrupted[24 + 24 + 2] = 0;
+        assert!(matches!(
+            restore_new::<(), _>(&mut corrupted.as_slice()),
+            Err(Error::InvalidRegionTable)
+        ));
+
+        match restore(&gm, &mut &data[..data.len() - 1]).unwrap_err() {
+            Error::IOError(e) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+            e => panic!("unexpected error {:?}", e),
+        }
+    }
+
+    #[cfg(all(feature = "backend-bitmap", feature = "backend-mmap"))]
+    #[test]
+    fn test_save_restore_dirty() {
+        let gm = crate::GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[
+            (GuestAddress(0x0), 0x4000),
+            (GuestAddress(0x10000), 0x2800),
+        ])
+        .unwrap();
+        let page_size = gm.iter().next().unwrap().bitmap().page_size();
+        gm.write_slice(&[0x11; 0x100], GuestAddress(0x0)).unwrap();
+
+        let mut base = Vec::new();
+        save(&gm, &mut base).unwrap();
+        let mut diff0 = Vec::new();
+        save_dirty(&gm, &mut diff0).unwrap();
+        assert!(gm
+            .iter()
+            .all(|r| r.bitmap().get_and_reset().iter().all(|&w| w == 0)));
+
+        // The last page of the second region is a partial one.
+        gm.write_obj(0x1234u16, GuestAddress(0x127fe)).unwrap();
+        gm.write_slice(&[0; 0x10], GuestAddress(0x0)).unwrap();
+        let mut diff1 = Vec::new();
+        save_dirty(&gm, &mut diff1).unwrap();
+        assert!(gm
+            .iter()
+            .all(|r| r.bitmap().get_and_reset().iter().all(|&w| w == 0)));
+
+        let last_len = 0x2800 % page_size;
+        let last_len = if last_len == 0 { page_size } else { last_len };
+        assert_eq!(
+            diff1.len(),
+            24 + 2 * 24 + 8 + 2 * 12 + page_size + last_len + 4
+        );
+
+        gm.write_obj(0x5678u16, GuestAddress(0x127fe)).unwrap();
+        let mut diff2 = Vec::new();
+        save_dirty(&gm, &mut diff2).unwrap();
+
+        let restored: crate::GuestMemoryMmap<()> = restore_chain(
+            &mut base.as_slice(),
+            [diff0.as_slice(), diff1.as_slice(), diff2.as_slice()],
+        )
+        .unwrap();
+        let mut buf = [0u8; 0x100];
+        restored.read_slice(&mut buf, GuestAddress(0x0)).unwrap();
+        assert_eq!(buf[..0x10], [0; 0x10]);
+        assert_eq!(buf[0x10..], [0x11; 0xf0]);
+        assert_eq!(
+            restored.read_obj::<u16>(GuestAddress(0x127fe)).unwrap(),
+            0x5678
+        );
+
+        let restored: crate::GuestMemoryMmap<()> =
+            restore_chain(&mut base.as_slice(), [diff0.as_slice(), diff1.as_slice()]).unwrap();
+        assert_eq!(
+            restored.read_obj::<u16>(GuestAddress(0x127fe)).unwrap(),
+            0x1234
+        );
+
+        // Full and incremental snapshots are not interchangeable.
+        assert!(matches!(
+            restore(&restored, &mut diff1.as_slice()),
+            Err(Error::UnsupportedKind(KIND_DIRTY))
+        ));
+        assert!(matches!(
+            restore_dirty(&restored, &mut base.as_slice()),
+            Err(Error::UnsupportedKind(KIND_FULL))
+        ));
+
+        let other = GuestMemoryMmap::from_ranges(&[(GuestAddress(0x0), 0x4000)]).unwrap();
+        assert!(matches!(
+            restore_dirty(&other, &mut diff1.as_slice()),
+            Err(Error::LayoutMismatch)
+        ));
+
+        // Move the first dirty page past the end of the first region.
+        let mut corrupted = diff1.clone();
+        corrupted[24 + 2 * 24 + 8..24 + 2 * 24 + 16]
+            .copy_from_slice(&(0x4000 - 0x10u64).to_le_bytes());
+        assert!(matches!(
+            restore_dirty(&restored, &mut corrupted.as_slice()),
+            Err(Error::InvalidPageRecord(GuestAddress(0x3ff0)))
+        ));
+
+        let mut corrupted = diff1;
+        let len = corrupted.len();
+        corrupted[len - 5] ^= 1;
+        assert!(matches!(
+            restore_dirty(&restored, &mut corrupted.as_slice()),
+            Err(Error::ChecksumMismatch { .. })
+        ));
+    }
+
+    #[cfg(all(feature = "backend-bitmap", feature = "backend-mmap"))]
+    #[test]
+    fn test_save_dirty_error() {
+        struct FailingWriter;
+
+        impl Write for FailingWriter {
+            fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+                Err(io::Error::from(io::ErrorKind::BrokenPipe))
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let gm =
+            crate::GuestMemoryMmap::<AtomicBitmap>::from_ranges(&[(GuestAddress(0x0), 0x4000)])
+                .unwrap();
+        gm.write_obj(1u8, GuestAddress(0x2000)).unwrap();
+        assert!(matches!(
+            save_dirty(&gm, &mut FailingWriter),
+            Err(Error::IOError(_))
+        ));
+
+        // The consumed dirty bits are restored.
+        let bitmap = gm.iter().next().unwrap().bitmap();
+        assert!(bitmap.is_addr_set(0x2000));
+        assert!(!bitmap.is_addr_set(0x0));
+    }
+}
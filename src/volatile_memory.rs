@@ -37,6 +37,8 @@ use std::usize;
 
 use crate::atomic_integer::AtomicInteger;
 use crate::bitmap::{Bitmap, BitmapSlice, BS};
+use crate::guest_memory::MAX_ACCESS_CHUNK;
+use crate::io::{ReadVolatile, WriteVolatile};
 use crate::{AtomicAccess, ByteValued, Bytes};
 
 use copy_slice_impl::copy_slice;
@@ -382,6 +384,33 @@ impl<'a, B: BitmapSlice> VolatileSlice<'a, B> {
         }
     }
 
+    // Returns a `VolatileRef` to the `T` at `offset`. Unlike `VolatileMemory::get_ref`, the
+    // reference lives as long as the memory of the slice rather than the slice itself.
+    pub(crate) fn into_ref<T: ByteValued>(self, offset: usize) -> Result<VolatileRef<'a, T, B>> {
+        let slice = self.subslice(offset, size_of::<T>())?;
+        // SAFETY: This is safe because the pointer is range-checked by subslice, and the
+        // lifetime is the same as the original slice.
+        unsafe { Ok(VolatileRef::with_bitmap(slice.addr, slice.bitmap)) }
+    }
+
+    // Returns a `VolatileArrayRef` of `n` elements starting at `offset`. Unlike
+    // `VolatileMemory::get_array_ref`, the reference lives as long as the memory of the slice
+    // rather than the slice itself.
+    pub(crate) fn into_array_ref<T: ByteValued>(
+        self,
+        offset: usize,
+        n: usize,
+    ) -> Result<VolatileArrayRef<'a, T, B>> {
+        let nbytes = n.checked_mul(size_of::<T>()).ok_or(Error::TooBig {
+            nelements: n,
+            size: size_of::<T>(),
+        })?;
+        let slice = self.subslice(offset, nbytes)?;
+        // SAFETY: This is safe because the pointer is range-checked by subslice, and the
+        // lifetime is the same as the original slice.
+        unsafe { Ok(VolatileArrayRef::with_bitmap(slice.addr, n, slice.bitmap)) }
+    }
+
     /// Copies as many elements of type `T` as possible from this slice to `buf`.
     ///
     /// Copies `self.len()` or `buf.len()` times the size of `T` bytes, whichever is smaller,
@@ -694,10 +723,12 @@ impl<B: BitmapSlice> Bytes<usize> for VolatileSlice<'_, B> {
     {
         let _ = self.compute_end_offset(addr, count)?;
 
-        let mut dst = vec![0; count];
+        // A single read is done, so at most one chunk is needed.
+        let mut buf = [0u8; MAX_ACCESS_CHUNK];
+        let dst = &mut buf[..min(count, MAX_ACCESS_CHUNK)];
 
         let bytes_read = loop {
-            match src.read(&mut dst) {
+            match src.read(dst) {
                 Ok(n) => break n,
                 Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
                 Err(e) => return Err(Error::IOError(e)),
@@ -706,12 +737,12 @@ impl<B: BitmapSlice> Bytes<usize> for VolatileSlice<'_, B> {
 
         // There is no guarantee that the read implementation is well-behaved, see the docs for
         // Read::read.
-        assert!(bytes_read <= count);
+        assert!(bytes_read <= dst.len());
 
         // SAFETY: We have checked via compute_end_offset that accessing the specified
         // region of guest memory is valid. We asserted that the value returned by `read` is between
-        // 0 and count (the length of the buffer passed to it), and that the
-        // regions don't overlap because we allocated the Vec outside of guest memory.
+        // 0 and the length of the buffer passed to it, which is at most count, and that the
+        // regions don't overlap because the buffer is outside of guest memory.
         unsafe {
             copy_slice(self.as_ptr().add(addr), dst.as_ptr(), bytes_read);
         }
@@ -749,19 +780,24 @@ impl<B: BitmapSlice> Bytes<usize> for VolatileSlice<'_, B> {
     {
         let _ = self.compute_end_offset(addr, count)?;
 
-        let mut dst = vec![0; count];
+        let mut buf = [0u8; MAX_ACCESS_CHUNK];
+        let mut done = 0;
+        while done < count {
+            let dst = &mut buf[..min(count - done, MAX_ACCESS_CHUNK)];
 
-        // Read into buffer that can be copied into guest memory
-        src.read_exact(&mut dst).map_err(Error::IOError)?;
+            // Read into buffer that can be copied into guest memory
+            src.read_exact(dst).map_err(Error::IOError)?;
 
-        // SAFETY: We have checked via compute_end_offset that accessing the specified
-        // region of guest memory is valid. We know that `dst` has len `count`, and that the
-        // regions don't overlap because we allocated the Vec outside of guest memory
-        unsafe {
-            copy_slice(self.as_ptr().add(addr), dst.as_ptr(), count);
-        }
+            // SAFETY: We have checked via compute_end_offset that accessing the specified
+            // region of guest memory is valid, and `dst` doesn't extend past its end. The
+            // regions don't overlap because the buffer is outside of guest memory.
+            unsafe {
+                copy_slice(self.as_ptr().add(addr + done), dst.as_ptr(), dst.len());
+            }
 
-        self.bitmap.mark_dirty(addr, count);
+            self.bitmap.mark_dirty(addr + done, dst.len());
+            done += dst.len();
+        }
         Ok(())
     }
 
@@ -792,20 +828,20 @@ impl<B: BitmapSlice> Bytes<usize> for VolatileSlice<'_, B> {
         F: Write,
     {
         let _ = self.compute_end_offset(addr, count)?;
-        let mut src = Vec::with_capacity(count);
+
+        // A single write is done, so at most one chunk is needed.
+        let mut buf = [0u8; MAX_ACCESS_CHUNK];
+        let src = &mut buf[..min(count, MAX_ACCESS_CHUNK)];
         // SAFETY: We checked the addr and count so accessing the slice is safe.
-        // It is safe to read from volatile memory. The Vec has capacity for exactly `count`
-        // many bytes, and the memory regions pointed to definitely do not overlap, as we
-        // allocated src outside of guest memory.
-        // The call to set_len is safe because the bytes between 0 and count have been initialized
-        // via copying from guest memory, and the Vec's capacity is `count`
+        // It is safe to read from volatile memory. The buffer is at most `count` bytes long,
+        // and the memory regions pointed to definitely do not overlap, as the buffer is outside
+        // of guest memory.
         unsafe {
-            copy_slice(src.as_mut_ptr(), self.as_ptr().add(addr), count);
-            src.set_len(count);
+            copy_slice(src.as_mut_ptr(), self.as_ptr().add(addr), src.len());
         }
 
         loop {
-            match dst.write(&src) {
+            match dst.write(src) {
                 Ok(n) => break Ok(n),
                 Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
                 Err(e) => break Err(Error::IOError(e)),
@@ -840,24 +876,110 @@ impl<B: BitmapSlice> Bytes<usize> for VolatileSlice<'_, B> {
         F: Write,
     {
         let _ = self.compute_end_offset(addr, count)?;
-        let mut src = Vec::with_capacity(count);
 
-        // SAFETY: We checked the addr and count so accessing the slice is safe.
-        // It is safe to read from volatile memory. The Vec has capacity for exactly `count`
-        // many bytes, and the memory regions pointed to definitely do not overlap, as we
-        // allocated src outside of guest memory.
-        // The call to set_len is safe because the bytes between 0 and count have been initialized
-        // via copying from guest memory, and the Vec's capacity is `count`
-        unsafe {
-            copy_slice(src.as_mut_ptr(), self.as_ptr().add(addr), count);
-            src.set_len(count);
-        }
+        let mut buf = [0u8; MAX_ACCESS_CHUNK];
+        let mut done = 0;
+        while done < count {
+            let src = &mut buf[..min(count - done, MAX_ACCESS_CHUNK)];
+
+            // SAFETY: We checked the addr and count so accessing the slice is safe, and `src`
+            // doesn't extend past its end. It is safe to read from volatile memory, and the
+            // memory regions pointed to definitely do not overlap, as the buffer is outside of
+            // guest memory.
+            unsafe {
+                copy_slice(src.as_mut_ptr(), self.as_ptr().add(addr + done), src.len());
+            }
 
-        dst.write_all(&src).map_err(Error::IOError)?;
+            dst.write_all(src).map_err(Error::IOError)?;
+            done += src.len();
+        }
 
         Ok(())
     }
 
+    /// # Examples
+    ///
+    /// * Read bytes from /dev/urandom without an intermediate buffer
+    ///
+    /// ```
+    /// # use vm_memory::{Bytes, VolatileMemory, VolatileSlice};
+    /// # use std::fs::File;
+    /// # use std::path::Path;
+    /// #
+    /// # if cfg!(unix) {
+    /// # let mut mem = [0u8; 1024];
+    /// # let vslice = VolatileSlice::from(&mut mem[..]);
+    /// let mut file = File::open(Path::new("/dev/urandom")).expect("Could not open /dev/urandom");
+    ///
+    /// vslice
+    ///     .read_volatile_from(32, &mut file, 128)
+    ///     .expect("Could not read bytes from file into VolatileSlice");
+    /// # }
+    /// ```
+    fn read_volatile_from<F>(&self, addr: usize, src: &mut F, count: usize) -> Result<usize>
+    where
+        F: ReadVolatile,
+    {
+        let mut slice = self.subslice(addr, count)?;
+
+        loop {
+            match src.read_volatile(&mut slice) {
+                Err(Error::IOError(ref e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        }
+    }
+
+    fn read_exact_volatile_from<F>(&self, addr: usize, src: &mut F, count: usize) -> Result<()>
+    where
+        F: ReadVolatile,
+    {
+        src.read_exact_volatile(&mut self.subslice(addr, count)?)
+    }
+
+    /// # Examples
+    ///
+    /// * Write 128 bytes to /dev/null without an intermediate buffer
+    ///
+    /// ```
+    /// # use vm_memory::{Bytes, VolatileMemory, VolatileSlice};
+    /// # use std::fs::OpenOptions;
+    /// # use std::path::Path;
+    /// #
+    /// # if cfg!(unix) {
+    /// # let mut mem = [0u8; 1024];
+    /// # let vslice = VolatileSlice::from(&mut mem[..]);
+    /// let mut file = OpenOptions::new()
+    ///     .write(true)
+    ///     .open("/dev/null")
+    ///     .expect("Could not open /dev/null");
+    ///
+    /// vslice
+    ///     .write_volatile_to(32, &mut file, 128)
+    ///     .expect("Could not write value from VolatileSlice to /dev/null");
+    /// # }
+    /// ```
+    fn write_volatile_to<F>(&self, addr: usize, dst: &mut F, count: usize) -> Result<usize>
+    where
+        F: WriteVolatile,
+    {
+        let slice = self.subslice(addr, count)?;
+
+        loop {
+            match dst.write_volatile(&slice) {
+                Err(Error::IOError(ref e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => break result,
+            }
+        }
+    }
+
+    fn write_all_volatile_to<F>(&self, addr: usize, dst: &mut F, count: usize) -> Result<()>
+    where
+        F: WriteVolatile,
+    {
+        dst.write_all_volatile(&self.subslice(addr, count)?)
+    }
+
     fn store<T: AtomicAccess>(&self, val: T, addr: usize, order: Ordering) -> Result<()> {
         self.get_atomic_ref::<T::A>(addr).map(|r| {
             r.store(val.into(), order);
@@ -1308,6 +1430,54 @@ where
                 .mark_dirty(0, addr as usize - self.addr as usize)
         }
     }
+
+    /// Reads all the elements of the array, converting them to `N`.
+    ///
+    /// This is mostly useful for arrays of endian types, which are converted to their native
+    /// counterparts in bulk.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use vm_memory::{Le32, VolatileArrayRef};
+    /// #
+    /// let mut v = [Le32::from(1), Le32::from(2)];
+    /// let v_ref = unsafe { VolatileArrayRef::<Le32>::new(v.as_mut_ptr() as *mut u8, v.len()) };
+    ///
+    /// assert_eq!(v_ref.to_native_vec::<u32>(), [1, 2]);
+    /// ```
+    pub fn to_native_vec<N>(&self) -> Vec<N>
+    where
+        T: Into<N>,
+    {
+        let mut buf = vec![T::zeroed(); self.len()];
+        self.copy_to(&mut buf);
+        buf.into_iter().map(Into::into).collect()
+    }
+
+    /// Copies as many elements of `values` as possible to this array, converting them to `T`.
+    ///
+    /// This is the counterpart of [`to_native_vec`](#method.to_native_vec), and copies
+    /// `self.len()` or `values.len()` elements, whichever is smaller.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use vm_memory::{Be16, VolatileArrayRef};
+    /// #
+    /// let mut v = [Be16::default(); 2];
+    /// let v_ref = unsafe { VolatileArrayRef::<Be16>::new(v.as_mut_ptr() as *mut u8, v.len()) };
+    ///
+    /// v_ref.copy_from_native(&[0x1234u16, 0x5678]);
+    /// assert_eq!(v[1], 0x5678);
+    /// ```
+    pub fn copy_from_native<N>(&self, values: &[N])
+    where
+        N: Copy + Into<T>,
+    {
+        let buf: Vec<T> = values.iter().take(self.len()).map(|&v| v.into()).collect();
+        self.copy_from(&buf);
+    }
 }
 
 impl<'a, B: BitmapSlice> From<VolatileSlice<'a, B>> for VolatileArrayRef<'a, u8, B> {
@@ -1987,6 +2157,28 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_stream_access_multiple_chunks() {
+        let len = 3 * MAX_ACCESS_CHUNK + 5;
+        let data: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        let mut backing = vec![0u8; len];
+        let s = VolatileSlice::from(backing.as_mut_slice());
+
+        // A single read or write is limited to one chunk.
+        assert_eq!(
+            s.read_from(0, &mut Cursor::new(&data), len).unwrap(),
+            MAX_ACCESS_CHUNK
+        );
+        let mut out = Vec::new();
+        assert_eq!(s.write_to(0, &mut out, len).unwrap(), MAX_ACCESS_CHUNK);
+        assert_eq!(out, &data[..MAX_ACCESS_CHUNK]);
+
+        s.read_exact_from(0, &mut Cursor::new(&data), len).unwrap();
+        let mut out = Vec::new();
+        s.write_all_to(0, &mut out, len).unwrap();
+        assert_eq!(out, data);
+    }
+
     #[test]
     fn ref_array_from_slice() {
         let mut a = [2, 4, 6, 8, 10];
@@ -2033,6 +2225,24 @@ mod tests {
         ;
     }
 
+    #[test]
+    fn ref_array_native() {
+        let mut a = [0u8; 16];
+        a[..4].copy_from_slice(&[1, 0, 0, 0x80]);
+        let a_ref = VolatileSlice::from(&mut a[..]);
+        let v_ref = a_ref.get_array_ref::<crate::Le32>(0, 4).unwrap();
+        assert_eq!(v_ref.to_native_vec::<u32>(), [0x8000_0001, 0, 0, 0]);
+
+        v_ref.copy_from_native(&[1u32, 2, 3, 4, 5]);
+        assert_eq!(v_ref.to_native_vec::<u32>(), [1, 2, 3, 4]);
+        v_ref.copy_from_native(&[0x0102_0304u32]);
+        assert_eq!(a[..8], [4, 3, 2, 1, 2, 0, 0, 0]);
+
+        let a_ref = VolatileSlice::from(&mut a[..]);
+        let v_ref = a_ref.get_array_ref::<crate::endian::BeI16>(0, 2).unwrap();
+        assert_eq!(v_ref.to_native_vec::<i16>(), [0x0403, 0x0201]);
+    }
+
     #[test]
     fn ref_array_overflow() {
         let mut a = [0, 0, 2, 3, 10];